@@ -26,6 +26,7 @@ struct Cli {
 enum Commands {
     p { key: i64, value: i64 },
     g { key: i64 },
+    m { keys: Vec<i64> },
     r { from: i64, to: i64 },
     d { key: i64 },
     l { file: String },
@@ -55,6 +56,18 @@ async fn handle_get(
     Ok(get_response)
 }
 
+async fn handle_multi_get(
+    mut client: ByronClient<Channel>,
+    keys: Vec<i64>,
+) -> Result<MultiGetResponse, Box<dyn std::error::Error>> {
+    let request = tonic::Request::new(MultiGetRequest { keys });
+
+    let response = client.multi_get(request).await?;
+    let multi_get_response = response.into_inner();
+
+    Ok(multi_get_response)
+}
+
 async fn handle_range(
     mut client: ByronClient<Channel>,
     from: i64,
@@ -196,6 +209,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let target = handle_get(client, key).await?;
             println!("{:}", target.value);
         }
+        Commands::m { keys } => {
+            let target = handle_multi_get(client, keys).await?;
+            for result in target.results {
+                if result.found {
+                    println!("{:?} -> {:?}", result.key, result.value);
+                } else {
+                    println!("{:?} not found", result.key);
+                }
+            }
+        }
         Commands::r { from, to } => {
             let target = handle_range(client, from, to).await?;
             for value in target.pairs {
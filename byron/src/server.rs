@@ -1,7 +1,11 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use lsm::lsm_database::LsmDatabase;
+use lsm::{
+    comparator::{decode_i64, encode_i64},
+    error::LsmError,
+    lsm_database::LsmDatabase,
+};
 use tonic::{transport::Server, Request, Response, Status};
 
 use byron::byron_server::{Byron, ByronServer};
@@ -19,12 +23,12 @@ pub struct ByronServerContext {
     pub database: Arc<RwLock<LsmDatabase>>,
 }
 
-impl Default for ByronServerContext {
-    fn default() -> Self {
-        let database = LsmDatabase::new("./data".to_string(), None);
-        Self {
+impl ByronServerContext {
+    pub async fn open(data_dir: &str) -> Result<Self, LsmError> {
+        let database = LsmDatabase::open(data_dir, None).await?;
+        Ok(Self {
             database: Arc::new(RwLock::new(database)),
-        }
+        })
     }
 }
 
@@ -35,7 +39,8 @@ impl Byron for ByronServerContext {
         tracing::debug!("Received get request: {:?}", request);
         println!("In here");
         let input = request.get_ref();
-        let key = input.key.to_string();
+        // keys are stored so that their byte order is their numeric order
+        let key = encode_i64(input.key);
 
         let db = self.database.read().await;
         let kv = db
@@ -44,7 +49,8 @@ impl Byron for ByronServerContext {
             .map_err(|e| Status::internal(format!("Database error: {:?}", e)))?;
 
         let value: i64 = kv
-            .value
+            .value_str()
+            .unwrap_or_default()
             .parse()
             .map_err(|e| Status::invalid_argument(format!("Value parsing error: {:?}", e)))?;
 
@@ -56,11 +62,41 @@ impl Byron for ByronServerContext {
         Ok(Response::new(response))
     }
 
+    #[tracing::instrument]
+    async fn multi_get(
+        &self,
+        request: Request<MultiGetRequest>,
+    ) -> Result<Response<MultiGetResponse>, Status> {
+        tracing::debug!("Received multi get request: {:?}", request);
+        let input = request.get_ref();
+        let keys: Vec<[u8; 8]> = input.keys.iter().map(|&key| encode_i64(key)).collect();
+
+        let db = self.database.read().await;
+        let mut results = Vec::with_capacity(keys.len());
+        for (&key, result) in input.keys.iter().zip(db.multi_get(&keys).await) {
+            let (found, value) = match result {
+                Ok(kv) => {
+                    let value: i64 = kv.value_str().unwrap_or_default().parse().map_err(|e| {
+                        Status::invalid_argument(format!("Value parsing error: {:?}", e))
+                    })?;
+                    (true, value)
+                }
+                Err(LsmError::KeyNotFound) => (false, 0),
+                Err(e) => return Err(Status::internal(format!("Database error: {:?}", e))),
+            };
+            results.push(MultiGetResult { key, found, value });
+        }
+
+        let response = MultiGetResponse { results };
+        tracing::info!("Returning multi get response: {:?}", response);
+        Ok(Response::new(response))
+    }
+
     #[tracing::instrument]
     async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
         tracing::debug!("Received put request: {:?}", request);
         let input = request.get_ref();
-        let key = input.key.to_string();
+        let key = encode_i64(input.key);
         let value = input.value.to_string();
         let db = self.database.write().await;
         let _ = db.put(key, value).await;
@@ -78,32 +114,29 @@ impl Byron for ByronServerContext {
     ) -> Result<Response<RangeResponse>, Status> {
         tracing::debug!("Received range request: {:?}", request);
         let input = request.get_ref();
-        let start = input.start.to_string();
-        let end = input.end.to_string();
+        let start = encode_i64(input.start);
+        let end = encode_i64(input.end);
 
         let db = self.database.read().await;
-        let values = db
+        let items = db
             .range(start, end)
             .await
-            .map_err(|e| Status::internal(format!("Database Error: {:?}", e)))?
-            .iter()
-            .map(|item| byron::KeyValue {
-                key: item
-                    .key
-                    .clone()
-                    .parse()
-                    .map_err(|e| Status::invalid_argument(format!("Kvp parsing error: {:?}", e)))
-                    .unwrap(),
-                value: item
-                    .value
-                    .clone()
-                    .parse()
-                    .map_err(|e| Status::invalid_argument(format!("Kvp parsing error: {:?}", e)))
-                    .unwrap(),
-            })
-            .collect();
+            .map_err(|e| Status::internal(format!("Database Error: {:?}", e)))?;
         drop(db);
 
+        let mut values = Vec::with_capacity(items.len());
+        for item in items {
+            // keys written before they were encoded as i64s don't decode
+            let key = decode_i64(&item.key)
+                .ok_or_else(|| Status::invalid_argument("Kvp parsing error: key is not an i64"))?;
+            let value = item
+                .value_str()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|e| Status::invalid_argument(format!("Kvp parsing error: {:?}", e)))?;
+            values.push(byron::KeyValue { key, value });
+        }
+
         let response = RangeResponse { pairs: values };
         tracing::info!("Returning range response: {:?}", response);
         Ok(Response::new(response))
@@ -115,7 +148,7 @@ impl Byron for ByronServerContext {
     ) -> Result<Response<DeleteResponse>, Status> {
         tracing::debug!("Received delete request: {:?}", request);
         let input = request.get_ref();
-        let key = input.key.to_string();
+        let key = encode_i64(input.key);
 
         let db = self.database.write().await;
         let _ = db
@@ -139,7 +172,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
 
     let addr = "[::1]:50051".parse()?;
-    let byron = ByronServerContext::default();
+    let byron = ByronServerContext::open("./data").await?;
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(byron::FILE_DESCRIPTOR_SET)
         .build_v1()
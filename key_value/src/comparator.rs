@@ -0,0 +1,85 @@
+use std::{cmp::Ordering, fmt::Debug, sync::Arc};
+
+/// Decides the order of keys. The memtables, the table indexes and the
+/// compaction merge all sort and search through the same comparator, so they
+/// agree on what "before" means.
+pub trait Comparator: Debug + Send + Sync {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// Identifies the ordering. It is stored in every table so that a table
+    /// is never searched with a different order than it was written in.
+    fn name(&self) -> &str;
+}
+
+/// Plain lexicographic byte order.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn name(&self) -> &str {
+        "byron.BytewiseComparator"
+    }
+}
+
+pub fn default_comparator() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+/// Encodes `value` so that the bytewise order of encodings matches numeric
+/// order: big-endian with the sign bit flipped, so negatives come first.
+pub fn encode_i64(value: i64) -> [u8; 8] {
+    ((value as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+/// Reverses `encode_i64`. `None` unless `bytes` is exactly eight bytes long.
+pub fn decode_i64(bytes: &[u8]) -> Option<i64> {
+    let bytes: [u8; 8] = bytes.try_into().ok()?;
+    Some((u64::from_be_bytes(bytes) ^ (1 << 63)) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EDGES: [i64; 7] = [i64::MIN, i64::MIN + 1, -256, -1, 0, 1, i64::MAX];
+
+    #[test]
+    fn test_i64_round_trip() {
+        for value in EDGES {
+            assert_eq!(decode_i64(&encode_i64(value)), Some(value));
+        }
+        assert_eq!(decode_i64(&[0; 7]), None);
+        assert_eq!(decode_i64(b"not an i64"), None);
+    }
+
+    #[test]
+    fn test_i64_encoding_keeps_numeric_order() {
+        let comparator = BytewiseComparator;
+        for a in EDGES {
+            for b in EDGES {
+                assert_eq!(
+                    comparator.compare(&encode_i64(a), &encode_i64(b)),
+                    a.cmp(&b),
+                    "{} against {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bytewise_order() {
+        let comparator = default_comparator();
+        assert_eq!(comparator.compare(b"a", b"b"), Ordering::Less);
+        assert_eq!(comparator.compare(b"ab", b"a"), Ordering::Greater);
+        assert_eq!(comparator.compare(b"", b""), Ordering::Equal);
+        // bytes compare unsigned
+        assert_eq!(comparator.compare(&[0x7f], &[0x80]), Ordering::Less);
+        assert_eq!(comparator.name(), BytewiseComparator.name());
+    }
+}
@@ -1,14 +1,16 @@
 use std::sync::Arc;
 
-use crate::KeyValue;
+use crate::{KeyValue, ValueKind};
 use integer_encoding::*;
 
 #[derive(Debug, Clone, PartialEq, Default, Eq, Hash)]
-//
+// on disk: shared | unshared | value len (varints) | kind (1 byte) | seq (varint) | key delta | value
 pub struct DeltaEncodedKV {
     pub shared_bytes: usize,
     pub unshared_bytes: usize,
     pub value_bytes: usize,
+    pub kind: ValueKind,
+    pub seq: u64,
     pub key_delta: Box<[u8]>,
     pub value: Box<[u8]>,
 }
@@ -16,13 +18,13 @@ pub struct DeltaEncodedKV {
 impl DeltaEncodedKV {
     pub fn forward(previous_kv: Option<KeyValue>, kv: KeyValue) -> Self {
         let value_bytes: usize = kv.value.len();
-        let value: Box<[u8]> = Box::from(kv.value.as_bytes());
+        let value: Box<[u8]> = Box::from(&kv.value[..]);
 
         if let Some(pkv) = previous_kv {
             let mut shared_bytes: usize = 0;
 
-            let current_key_bytes = kv.key.as_bytes();
-            let previous_key_bytes = pkv.key.as_bytes();
+            let current_key_bytes = &kv.key[..];
+            let previous_key_bytes = &pkv.key[..];
 
             let min_len = std::cmp::min(current_key_bytes.len(), previous_key_bytes.len());
 
@@ -42,49 +44,98 @@ impl DeltaEncodedKV {
                 shared_bytes,
                 unshared_bytes,
                 value_bytes,
+                kind: kv.kind,
+                seq: kv.seq,
                 key_delta,
                 value,
             }
         } else {
             let shared_bytes: usize = 0;
             let unshared_bytes: usize = kv.key.len();
-            let key_delta: Box<[u8]> = Box::from(kv.key.as_bytes());
+            let key_delta: Box<[u8]> = Box::from(&kv.key[..]);
 
             Self {
                 shared_bytes,
                 unshared_bytes,
                 value_bytes,
+                kind: kv.kind,
+                seq: kv.seq,
                 key_delta,
                 value,
             }
         }
     }
 
+    /// Rebuilds the entry from the one before it. `None` if the entry claims
+    /// to share more bytes than the previous key has.
     pub fn reverse(&self, previous_kv: Option<KeyValue>) -> Option<KeyValue> {
-        let value = String::from(std::str::from_utf8(&self.value).unwrap());
-        let suffix = std::str::from_utf8(&self.key_delta).unwrap();
+        let value: Arc<[u8]> = Arc::from(&self.value[..]);
         if let Some(pkv) = previous_kv {
-            let prefix = String::from(
-                std::str::from_utf8(&pkv.key.as_bytes()[..self.shared_bytes]).unwrap(),
-            );
-            let full_key = prefix + suffix;
+            let prefix = pkv.key.get(..self.shared_bytes)?;
+            let mut full_key = Vec::with_capacity(prefix.len() + self.key_delta.len());
+            full_key.extend_from_slice(prefix);
+            full_key.extend_from_slice(&self.key_delta);
             Some(KeyValue {
-                key: full_key,
+                key: full_key.into(),
                 value,
+                kind: self.kind,
+                seq: self.seq,
             })
         } else {
             Some(KeyValue {
-                key: String::from(suffix),
+                key: Arc::from(&self.key_delta[..]),
                 value,
+                kind: self.kind,
+                seq: self.seq,
             })
         }
     }
 
+    /// Decodes the entry at the start of `buffer`, returning it together with
+    /// the number of bytes it took up. `None` means the bytes don't hold a
+    /// complete entry.
+    pub fn decode(buffer: &[u8]) -> Option<(Self, usize)> {
+        let mut position = 0;
+        let mut read_len = || -> Option<usize> {
+            let (len, read) = usize::decode_var(&buffer[position..])?;
+            position += read;
+            Some(len)
+        };
+        let shared_bytes = read_len()?;
+        let unshared_bytes = read_len()?;
+        let value_bytes = read_len()?;
+        let kind = ValueKind::from_byte(*buffer.get(position)?)?;
+        position += 1;
+        let (seq, read) = u64::decode_var(&buffer[position..])?;
+        position += read;
+
+        let key_end = position.checked_add(unshared_bytes)?;
+        let value_end = key_end.checked_add(value_bytes)?;
+        if value_end > buffer.len() {
+            return None;
+        }
+
+        Some((
+            Self {
+                shared_bytes,
+                unshared_bytes,
+                value_bytes,
+                kind,
+                seq,
+                key_delta: buffer[position..key_end].into(),
+                value: buffer[key_end..value_end].into(),
+            },
+            value_end,
+        ))
+    }
+
     pub fn calculate_size(&self) -> usize {
         let mut size = 0;
         size += self.value_bytes.required_space();
         size += self.unshared_bytes.required_space();
         size += self.shared_bytes.required_space();
+        size += 1; // kind
+        size += self.seq.required_space();
         size += self.key_delta.len();
         size += self.value.len();
 
@@ -96,6 +147,8 @@ impl DeltaEncodedKV {
             self.shared_bytes.required_space()
                 + self.unshared_bytes.required_space()
                 + self.value_bytes.required_space()
+                + 1
+                + self.seq.required_space()
                 + self.key_delta.len()
                 + self.value.len(),
         );
@@ -103,6 +156,8 @@ impl DeltaEncodedKV {
         buffer.extend_from_slice(&self.shared_bytes.encode_var_vec());
         buffer.extend_from_slice(&self.unshared_bytes.encode_var_vec());
         buffer.extend_from_slice(&self.value_bytes.encode_var_vec());
+        buffer.push(self.kind.as_byte());
+        buffer.extend_from_slice(&self.seq.encode_var_vec());
         buffer.extend_from_slice(&self.key_delta);
         buffer.extend_from_slice(&self.value);
 
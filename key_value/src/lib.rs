@@ -1,9 +1,100 @@
+use std::sync::Arc;
+
+pub mod comparator;
 pub mod key_value_pair;
 
+/// Whether an entry holds a value or marks its key as deleted.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub enum ValueKind {
+    #[default]
+    Put,
+    Delete,
+}
+
+impl ValueKind {
+    pub fn as_byte(self) -> u8 {
+        match self {
+            ValueKind::Put => 0,
+            ValueKind::Delete => 1,
+        }
+    }
 
-#[derive(Debug, Default, Clone, Hash, PartialEq, PartialOrd, Eq, Ord)]
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ValueKind::Put),
+            1 => Some(ValueKind::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Keys and values are raw bytes. Keys order bytewise.
+///
+/// `seq` is the sequence number of the write that produced the entry. Every
+/// write gets a higher one than the last, so of two versions of a key the one
+/// with the larger `seq` is newer.
+#[derive(Debug, Clone, Hash, PartialEq, PartialOrd, Eq, Ord)]
 pub struct KeyValue {
-    pub key: String,
-    pub value: String,
+    pub key: Arc<[u8]>,
+    pub value: Arc<[u8]>,
+    pub kind: ValueKind,
+    pub seq: u64,
+}
+
+impl Default for KeyValue {
+    fn default() -> Self {
+        Self {
+            key: Arc::from(&[][..]),
+            value: Arc::from(&[][..]),
+            kind: ValueKind::Put,
+            seq: 0,
+        }
+    }
 }
 
+impl KeyValue {
+    pub fn new(key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: Arc::from(key.as_ref()),
+            value: Arc::from(value.as_ref()),
+            kind: ValueKind::Put,
+            seq: 0,
+        }
+    }
+
+    /// A deletion marker for `key`. It shadows older values until compaction
+    /// drops it.
+    pub fn tombstone(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: Arc::from(key.as_ref()),
+            value: Arc::from(&[][..]),
+            kind: ValueKind::Delete,
+            seq: 0,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.kind == ValueKind::Delete
+    }
+
+    /// The key as text, if it is valid UTF-8.
+    pub fn key_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.key).ok()
+    }
+
+    /// The value as text, if it is valid UTF-8.
+    pub fn value_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.value).ok()
+    }
+}
+
+/// Whether the version of a key written at `seq` must be kept, given the
+/// sequence of the next newer version of the same key (`None` if it is the
+/// newest). The newest version is always needed; an older one only while a
+/// snapshot in `snapshots` reads from between the two.
+pub fn version_is_needed(seq: u64, newer: Option<u64>, snapshots: &[u64]) -> bool {
+    match newer {
+        None => true,
+        Some(newer) => snapshots.iter().any(|&s| seq <= s && s < newer),
+    }
+}
@@ -0,0 +1,360 @@
+use key_value::{comparator::Comparator, KeyValue};
+use sstable::cursor::SSTableCursor;
+use std::{cmp::Reverse, collections::BinaryHeap, sync::Arc};
+
+use crate::{error::LsmError, lsm_compaction::HeapItem};
+
+/// One input of the merge: a table, or the visible part of a memtable.
+/// Entries come by key, newest version first within a key.
+pub(crate) trait Source: Send {
+    fn current(&self) -> Option<&KeyValue>;
+    /// Moves to the first entry at or after `key`.
+    fn seek(&mut self, key: &[u8]) -> Result<(), LsmError>;
+    /// Moves to the last entry at or before `key`.
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), LsmError>;
+    fn advance(&mut self) -> Result<(), LsmError>;
+    fn retreat(&mut self) -> Result<(), LsmError>;
+}
+
+impl Source for SSTableCursor {
+    fn current(&self) -> Option<&KeyValue> {
+        SSTableCursor::current(self)
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), LsmError> {
+        Ok(SSTableCursor::seek(self, key)?)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), LsmError> {
+        Ok(SSTableCursor::seek_for_prev(self, key)?)
+    }
+
+    fn advance(&mut self) -> Result<(), LsmError> {
+        Ok(SSTableCursor::advance(self)?)
+    }
+
+    fn retreat(&mut self) -> Result<(), LsmError> {
+        Ok(SSTableCursor::retreat(self)?)
+    }
+}
+
+/// The newest visible version of each key of a memtable, read up front since
+/// memtables keep changing under the iterator. Prefix scans also feed each
+/// table's matching entries through one.
+pub(crate) struct MemTableSource {
+    entries: Vec<KeyValue>,
+    position: Option<usize>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl MemTableSource {
+    pub fn new(entries: Vec<KeyValue>, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            entries,
+            position: None,
+            comparator,
+        }
+    }
+
+    fn first_after(&self, key: &[u8], inclusive: bool) -> usize {
+        self.entries.partition_point(|kv| {
+            let ordering = self.comparator.compare(&kv.key, key);
+            ordering.is_lt() || (!inclusive && ordering.is_eq())
+        })
+    }
+}
+
+impl Source for MemTableSource {
+    fn current(&self) -> Option<&KeyValue> {
+        self.position.map(|index| &self.entries[index])
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), LsmError> {
+        let index = self.first_after(key, true);
+        self.position = (index < self.entries.len()).then_some(index);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), LsmError> {
+        self.position = self.first_after(key, false).checked_sub(1);
+        Ok(())
+    }
+
+    fn advance(&mut self) -> Result<(), LsmError> {
+        self.position = self
+            .position
+            .map(|index| index + 1)
+            .filter(|&index| index < self.entries.len());
+        Ok(())
+    }
+
+    fn retreat(&mut self) -> Result<(), LsmError> {
+        self.position = self.position.and_then(|index| index.checked_sub(1));
+        Ok(())
+    }
+}
+
+// Where the iterator stands: just before or just after a key. `next` returns
+// the first live key past the gap and `prev` the last one before it.
+#[derive(Debug, Clone)]
+enum Gap {
+    Before(Arc<[u8]>),
+    After(Arc<[u8]>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A k-way merge over every memtable and table that moves in both
+/// directions between the inclusive bounds it was created with.
+///
+/// Only versions written at or before the read sequence are considered. Of
+/// those, the newest version of each key wins, and keys whose newest version
+/// is a tombstone are skipped.
+///
+/// The iterator sits between keys. `next` returns the key after that spot
+/// and `prev` the one before it, so a `next` followed by a `prev` returns
+/// the same entry twice. It starts before the lower bound.
+pub struct DbIterator {
+    sources: Vec<Box<dyn Source>>,
+    forward: BinaryHeap<HeapItem>,
+    backward: BinaryHeap<Reverse<HeapItem>>,
+    direction: Option<Direction>,
+    gap: Gap,
+    comparator: Arc<dyn Comparator>,
+    seq: u64,
+    lower: Arc<[u8]>,
+    upper: Arc<[u8]>,
+}
+
+impl DbIterator {
+    /// `sources` must be ordered oldest first.
+    pub(crate) fn new(
+        sources: Vec<Box<dyn Source>>,
+        comparator: Arc<dyn Comparator>,
+        seq: u64,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Self {
+        let lower: Arc<[u8]> = Arc::from(lower);
+        Self {
+            forward: BinaryHeap::with_capacity(sources.len()),
+            backward: BinaryHeap::new(),
+            sources,
+            direction: None,
+            gap: Gap::Before(lower.clone()),
+            comparator,
+            seq,
+            lower,
+            upper: Arc::from(upper),
+        }
+    }
+
+    /// Positions the iterator so that `next` returns the first live key at or
+    /// after `key`.
+    pub fn seek(&mut self, key: impl AsRef<[u8]>) {
+        let key = key.as_ref();
+        self.gap = if self.comparator.compare(key, &self.lower).is_lt() {
+            Gap::Before(self.lower.clone())
+        } else if self.comparator.compare(key, &self.upper).is_gt() {
+            Gap::After(self.upper.clone())
+        } else {
+            Gap::Before(Arc::from(key))
+        };
+        self.direction = None;
+    }
+
+    /// Positions the iterator so that `prev` returns the last live key at or
+    /// before `key`.
+    pub fn seek_for_prev(&mut self, key: impl AsRef<[u8]>) {
+        let key = key.as_ref();
+        self.gap = if self.comparator.compare(key, &self.upper).is_gt() {
+            Gap::After(self.upper.clone())
+        } else if self.comparator.compare(key, &self.lower).is_lt() {
+            Gap::Before(self.lower.clone())
+        } else {
+            Gap::After(Arc::from(key))
+        };
+        self.direction = None;
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.seek(self.lower.clone());
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.seek_for_prev(self.upper.clone());
+    }
+
+    /// Moves back over the previous live entry and returns it.
+    pub fn prev(&mut self) -> Option<Result<KeyValue, LsmError>> {
+        if self.direction != Some(Direction::Backward)
+            && let Err(e) = self.position(Direction::Backward)
+        {
+            return Some(Err(e));
+        }
+
+        while let Some(Reverse(top)) = self.backward.pop() {
+            let key = top.key_value.key.clone();
+            if self.comparator.compare(&key, &self.lower).is_lt() {
+                self.backward.clear();
+                self.gap = Gap::Before(self.lower.clone());
+                return None;
+            }
+
+            // versions of a key come oldest first going backwards
+            let mut newest = None;
+            let mut item = Some(top);
+            while let Some(HeapItem {
+                key_value,
+                sstable_idx,
+                ..
+            }) = item
+            {
+                if key_value.seq <= self.seq {
+                    newest = Some(key_value);
+                }
+                if let Err(e) = self.step(sstable_idx, Direction::Backward) {
+                    return Some(Err(e));
+                }
+                item = match self.backward.peek() {
+                    Some(Reverse(next))
+                        if self.comparator.compare(&next.key_value.key, &key).is_eq() =>
+                    {
+                        self.backward.pop().map(|Reverse(next)| next)
+                    }
+                    _ => None,
+                };
+            }
+
+            self.gap = Gap::Before(key);
+            if let Some(kv) = newest
+                && !kv.is_tombstone()
+            {
+                return Some(Ok(kv));
+            }
+        }
+        self.gap = Gap::Before(self.lower.clone());
+        None
+    }
+
+    // Puts every source back in line with the gap, moving in `direction`.
+    fn position(&mut self, direction: Direction) -> Result<(), LsmError> {
+        self.direction = None;
+        self.forward.clear();
+        self.backward.clear();
+        for idx in 0..self.sources.len() {
+            let source = &mut self.sources[idx];
+            match (&self.gap, direction) {
+                (Gap::Before(key), Direction::Forward) => source.seek(key)?,
+                (Gap::After(key), Direction::Backward) => source.seek_for_prev(key)?,
+                (Gap::After(key), Direction::Forward) => {
+                    source.seek(key)?;
+                    while let Some(kv) = source.current()
+                        && self.comparator.compare(&kv.key, key).is_eq()
+                    {
+                        source.advance()?;
+                    }
+                }
+                (Gap::Before(key), Direction::Backward) => {
+                    source.seek_for_prev(key)?;
+                    while let Some(kv) = source.current()
+                        && self.comparator.compare(&kv.key, key).is_eq()
+                    {
+                        source.retreat()?;
+                    }
+                }
+            }
+            self.push(idx, direction);
+        }
+        self.direction = Some(direction);
+        Ok(())
+    }
+
+    // moves source `idx` one entry along and queues what it lands on
+    fn step(&mut self, idx: usize, direction: Direction) -> Result<(), LsmError> {
+        let moved = match direction {
+            Direction::Forward => self.sources[idx].advance(),
+            Direction::Backward => self.sources[idx].retreat(),
+        };
+        if let Err(e) = moved {
+            // the heaps no longer account for this source
+            self.direction = None;
+            return Err(e);
+        }
+        self.push(idx, direction);
+        Ok(())
+    }
+
+    fn push(&mut self, idx: usize, direction: Direction) {
+        let Some(kv) = self.sources[idx].current() else {
+            return;
+        };
+        let item = HeapItem {
+            key_value: kv.clone(),
+            sstable_idx: idx,
+            comparator: self.comparator.clone(),
+        };
+        match direction {
+            Direction::Forward => self.forward.push(item),
+            Direction::Backward => self.backward.push(Reverse(item)),
+        }
+    }
+}
+
+impl Iterator for DbIterator {
+    type Item = Result<KeyValue, LsmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.direction != Some(Direction::Forward)
+            && let Err(e) = self.position(Direction::Forward)
+        {
+            return Some(Err(e));
+        }
+
+        while let Some(top) = self.forward.pop() {
+            let key = top.key_value.key.clone();
+            if self.comparator.compare(&key, &self.upper).is_gt() {
+                self.forward.clear();
+                self.gap = Gap::After(self.upper.clone());
+                return None;
+            }
+
+            // versions of a key come newest first, so the first visible one
+            // wins and the rest are shadowed
+            let mut newest: Option<KeyValue> = None;
+            let mut item = Some(top);
+            while let Some(HeapItem {
+                key_value,
+                sstable_idx,
+                ..
+            }) = item
+            {
+                if newest.is_none() && key_value.seq <= self.seq {
+                    newest = Some(key_value);
+                }
+                if let Err(e) = self.step(sstable_idx, Direction::Forward) {
+                    return Some(Err(e));
+                }
+                item = match self.forward.peek() {
+                    Some(next) if self.comparator.compare(&next.key_value.key, &key).is_eq() => {
+                        self.forward.pop()
+                    }
+                    _ => None,
+                };
+            }
+
+            self.gap = Gap::After(key);
+            if let Some(kv) = newest
+                && !kv.is_tombstone()
+            {
+                return Some(Ok(kv));
+            }
+        }
+        self.gap = Gap::After(self.upper.clone());
+        None
+    }
+}
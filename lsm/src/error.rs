@@ -1,3 +1,4 @@
+use sstable::error::clone_io_error;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,9 +9,15 @@ pub enum LsmError {
     #[error("MemTable error: {0}")]
     MemTable(#[from] memtable::error::MemTableError),
 
+    #[error("IO error: {0}")]
+    FileSystemError(#[from] std::io::Error),
+
     #[error("Key not found")]
     KeyNotFound,
 
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
     #[error("Synchronisation issue")]
     LockPoisoned,
 
@@ -18,3 +25,17 @@ pub enum LsmError {
     Other(String),
 }
 
+
+impl Clone for LsmError {
+    fn clone(&self) -> Self {
+        match self {
+            LsmError::SSTable(e) => LsmError::SSTable(e.clone()),
+            LsmError::MemTable(e) => LsmError::MemTable(e.clone()),
+            LsmError::FileSystemError(e) => LsmError::FileSystemError(clone_io_error(e)),
+            LsmError::KeyNotFound => LsmError::KeyNotFound,
+            LsmError::InvalidArgument(message) => LsmError::InvalidArgument(message.clone()),
+            LsmError::LockPoisoned => LsmError::LockPoisoned,
+            LsmError::Other(message) => LsmError::Other(message.clone()),
+        }
+    }
+}
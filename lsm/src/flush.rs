@@ -0,0 +1,144 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use memtable::MemTable;
+use tokio::sync::{futures::Notified, Notify};
+use uuid::Uuid;
+
+use crate::error::LsmError;
+use crate::wal::WalSegment;
+
+/// How often, and for how long, writes were held back for flushes and
+/// compactions to catch up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStallStats {
+    /// Writes delayed a little because level 0 is getting deep.
+    pub slowdowns: u64,
+    /// Writes that waited for a flush, because the immutable memtable queue
+    /// was full or level 0 too deep.
+    pub stops: u64,
+    /// Time writes spent delayed or waiting, in microseconds.
+    pub stall_micros: u64,
+}
+
+/// An immutable memtable waiting for its flush, with the log segment that
+/// holds its writes.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingFlush {
+    pub id: Uuid,
+    pub memtable: Arc<MemTable>,
+    pub segment: WalSegment,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    // oldest first; an entry stays until its table is in the tree
+    pending: VecDeque<PendingFlush>,
+    // whether a task is draining `pending`
+    running: bool,
+    // why the last flush failed, until one succeeds
+    error: Option<String>,
+}
+
+/// Immutable memtables waiting to be flushed, drained oldest first by one
+/// background task at a time. Writers queue a memtable and start the task
+/// if none is running; the task ends once the queue is empty. A failed
+/// flush stays at the front of the queue until a retry succeeds.
+#[derive(Debug, Default)]
+pub(crate) struct FlushQueue {
+    state: Mutex<QueueState>,
+    // woken whenever a flush finishes or fails, and when the task stops
+    progress: Notify,
+    slowdowns: AtomicU64,
+    stops: AtomicU64,
+    stall_micros: AtomicU64,
+}
+
+impl FlushQueue {
+    /// Queues `flush`. Returns whether the caller has to start a task to
+    /// drain the queue.
+    pub fn push(&self, flush: PendingFlush) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.pending.push_back(flush);
+        let start = !state.running;
+        state.running |= start;
+        start
+    }
+
+    /// The oldest memtable still to flush. `None` once the queue is empty,
+    /// which also ends the running task.
+    pub fn next(&self) -> Option<PendingFlush> {
+        let mut state = self.state.lock().unwrap();
+        let next = state.pending.front().cloned();
+        state.running = next.is_some();
+        drop(state);
+        if next.is_none() {
+            // whoever waits for the task to go idle checked before this
+            self.progress.notify_waiters();
+        }
+        next
+    }
+
+    /// Drops the oldest memtable, now that it has been flushed.
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.pop_front();
+        state.error = None;
+        drop(state);
+        self.progress.notify_waiters();
+    }
+
+    /// Records why the oldest flush failed. The memtable stays queued for
+    /// the task to retry, and writes waiting on it give up with `error`.
+    pub fn fail(&self, error: &LsmError) {
+        self.state.lock().unwrap().error = Some(error.to_string());
+        self.progress.notify_waiters();
+    }
+
+    /// Why the last flush failed, unless one has succeeded since.
+    pub fn check(&self) -> Result<(), LsmError> {
+        match &self.state.lock().unwrap().error {
+            Some(error) => Err(LsmError::Other(format!(
+                "background flush failed: {}",
+                error
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Immutable memtables not yet flushed.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    /// Whether no task is flushing, nor will be until a memtable is queued.
+    pub fn is_idle(&self) -> bool {
+        !self.state.lock().unwrap().running
+    }
+
+    /// Resolves after the next flush finishes or fails, or the task stops
+    /// for want of work. Take it before checking the queue, so that no
+    /// wakeup is missed in between.
+    pub fn progress(&self) -> Notified<'_> {
+        self.progress.notified()
+    }
+
+    pub fn record_stall(&self, stopped: bool, micros: u64) {
+        let counter = if stopped { &self.stops } else { &self.slowdowns };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.stall_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn stall_stats(&self) -> WriteStallStats {
+        WriteStallStats {
+            slowdowns: self.slowdowns.load(Ordering::Relaxed),
+            stops: self.stops.load(Ordering::Relaxed),
+            stall_micros: self.stall_micros.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,6 +1,17 @@
+pub mod db_iterator;
 pub mod error;
+pub mod flush;
+pub use key_value::comparator;
+pub use memtable::mem_table_builder::MemTableType;
 pub mod lsm_database;
 pub mod lsm_compaction;
+mod log_record;
+pub mod manifest;
+pub mod options;
+pub mod row_cache;
+pub mod snapshot;
+pub mod wal;
+pub mod write_buffer_manager;
 
 
 
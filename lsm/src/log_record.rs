@@ -0,0 +1,42 @@
+use std::io::Write;
+
+// Framing shared by the write-ahead log and the manifest. Every record is
+//
+//   crc32c(payload) (u32 LE) | payload length (u32 LE) | payload
+//
+// and records are only ever appended, so a bad record can only be a torn tail.
+const HEADER_SIZE: usize = 8;
+
+pub(crate) fn write_record<W: Write>(writer: &mut W, payload: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&crc32c::crc32c(payload).to_le_bytes())?;
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Decodes every record in `data`, stopping at the first torn, corrupt or
+/// undecodable one. Also returns the offset just past the last good record.
+pub(crate) fn read_records<T>(data: &[u8], decode: impl Fn(&[u8]) -> Option<T>) -> (Vec<T>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset + HEADER_SIZE <= data.len() {
+        let crc = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let len = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let start = offset + HEADER_SIZE;
+        if start + len > data.len() {
+            break;
+        }
+
+        let payload = &data[start..start + len];
+        if crc32c::crc32c(payload) != crc {
+            break;
+        }
+        match decode(payload) {
+            Some(record) => records.push(record),
+            None => break,
+        }
+        offset = start + len;
+    }
+
+    (records, offset)
+}
@@ -1,32 +1,42 @@
-use key_value::KeyValue;
+use key_value::{comparator::Comparator, version_is_needed, KeyValue};
 use sstable::builder::SSTableFeatures;
 use sstable::{streamed_builder::StreamedSSTableBuilder, SSTable};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::sync::Arc;
 use tokio::task;
-use uuid::Uuid;
 
 use crate::{
     error::LsmError,
     lsm_database::{Level, LsmDatabase},
+    manifest::ManifestEdit,
 };
 
-struct HeapItem {
-    key_value: KeyValue,
-    sstable_idx: usize,
+/// One entry of a k-way merge, tagged with the input it came from. Inputs
+/// are numbered oldest first. Shared by compaction and `DbIterator`.
+pub(crate) struct HeapItem {
+    pub key_value: KeyValue,
+    pub sstable_idx: usize,
+    pub comparator: Arc<dyn Comparator>,
 }
 
 impl Eq for HeapItem {}
 
 impl PartialEq for HeapItem {
     fn eq(&self, other: &Self) -> bool {
-        self.key_value == other.key_value
+        self.cmp(other) == Ordering::Equal
     }
 }
+// Smallest key first, by the database's comparator. For equal keys the
+// highest sequence number comes first, so the newest write is the one that
+// survives the merge. The input position only breaks ties between entries
+// that carry no sequence number.
 impl Ord for HeapItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.key_value.cmp(&self.key_value)
+        self.comparator
+            .compare(&other.key_value.key, &self.key_value.key)
+            .then(self.key_value.seq.cmp(&other.key_value.seq))
+            .then(self.sstable_idx.cmp(&other.sstable_idx))
     }
 }
 impl PartialOrd for HeapItem {
@@ -73,57 +83,30 @@ impl LsmDatabase {
 
     pub async fn insert_new_table(
         &self,
-        mut incoming_table: Arc<SSTable>,
-        mut level_number: usize,
+        incoming_table: Arc<SSTable>,
+        level_number: usize,
     ) -> Result<(), LsmError> {
-        loop {
-            log::info!(
-                "Step 1: Inserting table with {} entries into level {}",
-                incoming_table.actual_item_count, level_number
-            );
-
-            // Step 1: Check if we need to extend levels
-            let mut final_level_flag = false;
-            {
-                let levels = self.levels.read().await;
-                if level_number >= levels.len() {
-                    drop(levels); // Release read lock before getting write lock
-                    log::info!(
-                        "Step 2: Level {} does not exist. Extending database...",
-                        level_number
-                    );
-                    final_level_flag = true;
-                    self.extend(level_number).await?;
-
-                    log::info!("Step 2.1: New level layout:");
-                    let levels = self.levels.read().await;
-                    for (i, level) in levels.iter().enumerate() {
-                        log::info!(
-                            "  Level {}: {} tables, width {}, depth {}",
-                            i,
-                            level.inner.len(),
-                            level.width,
-                            level.depth
-                        );
-                    }
-                }
-            }
+        log::info!(
+            "Step 1: Inserting table with {} entries into level {}",
+            incoming_table.actual_item_count, level_number
+        );
 
-            // Step 2: Add table to the level
-            log::info!("Step 3: Adding table to level {}", level_number);
-            {
-                let mut levels = self.levels.write().await;
-                let level = &mut levels[level_number];
-                level.inner.push(incoming_table.clone());
-                level.total_entries += incoming_table.actual_item_count;
-                log::info!(
-                    "Step 3.1: Level {} now has {} tables with {} total entries",
-                    level_number,
-                    level.inner.len(),
-                    level.total_entries
-                );
-            }
+        // Steps 2-3: Extend the levels if needed and add the table
+        let final_level_flag = self
+            .install_table(incoming_table, level_number, Vec::new())
+            .await?;
+        self.compact_from(level_number, final_level_flag).await
+    }
 
+    /// Compacts `level_number` into the level below while it is full, then
+    /// that level, and so on. `final_level_flag` tells whether the last
+    /// install created its level.
+    pub(crate) async fn compact_from(
+        &self,
+        mut level_number: usize,
+        mut final_level_flag: bool,
+    ) -> Result<(), LsmError> {
+        loop {
             // Step 3: Check if compaction is needed
             let needs_compaction = {
                 let levels = self.levels.read().await;
@@ -159,15 +142,20 @@ impl LsmDatabase {
             log::info!("Step 5: Starting compaction for level {}", level_number);
 
             // Gather all necessary data before spawning the blocking task
-            let file_name = self
-                .parent_directory
-                .join(format!("sstable-id-{}", Uuid::new_v4()));
+            let file_name = self.new_table_path();
             let level_counts: Vec<usize>;
             let tables_to_compact: Vec<Arc<SSTable>>;
+            let drop_tombstones: bool;
+            let snapshots = self.snapshots.sequences();
+            let comparator = self.comparator.clone();
             {
                 let levels = self.levels.read().await;
                 level_counts = levels.iter().map(|lvl| lvl.total_entries).collect();
                 tables_to_compact = levels[level_number].inner.clone();
+                // a tombstone can only go once nothing older is left below it
+                drop_tombstones = levels[level_number + 1..]
+                    .iter()
+                    .all(|lvl| lvl.inner.is_empty());
             }
 
             // Calculate bloom filter parameters
@@ -180,18 +168,21 @@ impl LsmDatabase {
                 fpr, total_entries
             );
 
+            // the output lands one level down
             let features = SSTableFeatures {
                 fpr,
                 item_count: total_entries,
+                ..self.table_features(level_number + 1)
             };
 
+            let compaction_inputs = tables_to_compact.clone();
             let compacted_table =
                 task::spawn_blocking(move || -> Result<Arc<SSTable>, LsmError> {
                     log::info!("Inside compaction task for level {}", level_number);
 
                     // Create iterators for all tables
                     let mut iterators: Vec<_> =
-                        tables_to_compact.iter().map(|table| table.iter()).collect();
+                        compaction_inputs.iter().map(|table| table.iter()).collect();
 
                     // Initialize min heap
                     let mut min_heap = BinaryHeap::new();
@@ -204,6 +195,7 @@ impl LsmDatabase {
                             min_heap.push(HeapItem {
                                 key_value,
                                 sstable_idx,
+                                comparator: comparator.clone(),
                             });
                         }
                     }
@@ -218,31 +210,47 @@ impl LsmDatabase {
                         !final_level_flag,
                         &file_name,
                     ) {
-                        Ok(builder) => builder,
+                        Ok(builder) => builder.with_comparator(comparator.clone()),
                         Err(e) => return Err(LsmError::SSTable(e)),
                     };
 
                     // Merge sort from heap
                     let mut items_processed = 0;
+                    let mut newer: Option<(Arc<[u8]>, u64)> = None;
                     while let Some(HeapItem {
                         key_value,
                         sstable_idx,
+                        ..
                     }) = min_heap.pop()
                     {
-                        let _ = new_table.add_from_kv(key_value);
-                        items_processed += 1;
+                        // the newest entry for each key is kept, plus any older
+                        // one a live snapshot can still read
+                        let newer_seq = newer
+                            .as_ref()
+                            .filter(|(key, _)| comparator.compare(key, &key_value.key).is_eq())
+                            .map(|(_, seq)| *seq);
+                        let current = (key_value.key.clone(), key_value.seq);
+                        if version_is_needed(key_value.seq, newer_seq, &snapshots) {
+                            // at the bottom, a tombstone goes once no snapshot
+                            // is old enough to see what it deleted
+                            let droppable = drop_tombstones
+                                && key_value.is_tombstone()
+                                && snapshots.iter().all(|&s| s >= key_value.seq);
+                            if !droppable {
+                                new_table.add_from_kv(key_value).map_err(LsmError::SSTable)?;
+                                items_processed += 1;
+                            }
+                        }
+                        newer = Some(current);
                         if let Some(next_kv_result) = iterators[sstable_idx].next() {
                             let next_kv = match next_kv_result {
                                 Ok(kv) => kv,
                                 Err(e) => return Err(LsmError::SSTable(e)),
                             };
-                            if next_kv.value == "d34db33f" {
-                                // deletion sentinel value
-                                continue;
-                            }
                             min_heap.push(HeapItem {
                                 key_value: next_kv,
                                 sstable_idx,
+                                comparator: comparator.clone(),
                             });
                         }
                     }
@@ -269,28 +277,98 @@ impl LsmDatabase {
                 compacted_table.actual_item_count
             );
 
-            // Clear the current level
-            {
-                let mut levels = self.levels.write().await;
-                // Delete the old tables from disk
-                for table in &levels[level_number].inner {
-                    if let Err(e) = table.delete() {
-                        log::error!("Warning: Failed to delete table: {:?}", e);
-                    }
-                }
-                levels[level_number].inner.clear();
-                levels[level_number].total_entries = 0;
-                log::info!("Step 5.5: Cleared level {}", level_number);
+            // Step 5.5: Swap the compacted inputs for the new table in one manifest edit,
+            // then delete the old tables from disk once open iterators let go of them
+            final_level_flag = self
+                .install_table(compacted_table, level_number + 1, tables_to_compact.clone())
+                .await?;
+            for table in &tables_to_compact {
+                table.delete_when_unused();
             }
+            log::info!("Step 5.5: Cleared compacted tables from level {}", level_number);
 
             // Instead of recursion, update the loop variables and continue
             log::info!("Step 5.6: Moving to next level: {}", level_number + 1);
-            incoming_table = compacted_table;
             level_number += 1;
 
         }
     }
 
+    /// Adds `table` to `level_number`, removing `replaced` from the level above
+    /// it, and records both in the manifest as a single edit. Returns whether
+    /// the level had to be created for this table.
+    pub(crate) async fn install_table(
+        &self,
+        table: Arc<SSTable>,
+        level_number: usize,
+        replaced: Vec<Arc<SSTable>>,
+    ) -> Result<bool, LsmError> {
+        let mut created_level = false;
+        if level_number >= self.levels.read().await.len() {
+            log::info!(
+                "Step 2: Level {} does not exist. Extending database...",
+                level_number
+            );
+            created_level = true;
+            self.extend(level_number).await?;
+
+            log::info!("Step 2.1: New level layout:");
+            let levels = self.levels.read().await;
+            for (i, level) in levels.iter().enumerate() {
+                log::info!(
+                    "  Level {}: {} tables, width {}, depth {}",
+                    i,
+                    level.inner.len(),
+                    level.width,
+                    level.depth
+                );
+            }
+        }
+
+        log::info!("Step 3: Adding table to level {}", level_number);
+        let mut levels = self.levels.write().await;
+        let mut manifest = self.manifest.lock().await;
+
+        let mut edits: Vec<ManifestEdit> = replaced
+            .iter()
+            .map(|old| ManifestEdit::RemoveTable {
+                level: level_number - 1,
+                file_name: LsmDatabase::table_file_name(old),
+            })
+            .collect();
+        edits.push(ManifestEdit::AddTable {
+            level: level_number,
+            file_name: LsmDatabase::table_file_name(&table),
+        });
+        manifest.log(&edits)?;
+
+        if !replaced.is_empty() {
+            // only drop the tables that were compacted; anything flushed into the
+            // level while the compaction ran stays put
+            let source = &mut levels[level_number - 1];
+            source
+                .inner
+                .retain(|current| !replaced.iter().any(|old| Arc::ptr_eq(current, old)));
+            source.total_entries = source
+                .inner
+                .iter()
+                .map(|current| current.actual_item_count)
+                .sum();
+        }
+
+        let level = &mut levels[level_number];
+        level.total_entries += table.actual_item_count;
+        level.inner.push(table);
+        log::info!(
+            "Step 3.1: Level {} now has {} tables with {} total entries",
+            level_number,
+            level.inner.len(),
+            level.total_entries
+        );
+
+        Ok(created_level)
+    }
+
     pub async fn extend(&self, target_level: usize) -> Result<(), LsmError> {
         log::info!(
             "Extending levels from {} to {}",
@@ -307,6 +385,14 @@ impl LsmDatabase {
         let lrl = &levels[reference_level_idx];
         let new_width = (lrl.width as f64 * self.capacity_expansion_factor) as usize;
 
+        let edits: Vec<ManifestEdit> = (levels.len()..=target_level)
+            .map(|depth| ManifestEdit::AddLevel {
+                depth,
+                width: new_width,
+            })
+            .collect();
+        self.manifest.lock().await.log(&edits)?;
+
         while levels.len() <= target_level {
             let new_level = Level {
                 inner: Vec::new(),
@@ -331,21 +417,15 @@ impl LsmDatabase {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
+    use std::path::Path;
     use tempfile::tempdir;
 
-    fn create_test_kv(key: &str, value: &str) -> KeyValue {
-        KeyValue {
-            key: key.to_string(),
-            value: value.to_string(),
-        }
-    }
-
-    fn create_test_sstable(id: u32, item_count: usize, dir: &PathBuf) -> Arc<SSTable> {
+    fn create_test_sstable(id: u32, item_count: usize, dir: &Path) -> Arc<SSTable> {
         let file_name = dir.join(format!("test-sstable-{}", id));
         let features = SSTableFeatures {
             fpr: 0.01,
             item_count,
+            ..Default::default()
         };
 
         let mut builder = StreamedSSTableBuilder::new(features, true, &file_name).unwrap();
@@ -354,90 +434,90 @@ mod tests {
         for i in 0..item_count {
             let key = format!("key-{}-{}", id, i);
             let value = format!("value-{}-{}", id, i);
-            let _ = builder.add_from_kv(KeyValue { key, value });
+            builder.add_from_kv(KeyValue::new(key, value)).unwrap();
         }
 
         builder.finalize().unwrap()
     }
 
     // Helper to create a test database
-    fn create_test_db() -> LsmDatabase {
-        let mut db = LsmDatabase::new(String::from("./test"), None);
-        if db.levels.is_empty() {
-            db.levels.push(Level {
-                inner: Vec::new(),
-                depth: 0,
-                width: 2,
-                total_entries: 0,
-            });
-        }
-        return db;
+    async fn create_test_db(dir: &Path) -> LsmDatabase {
+        LsmDatabase::open(dir, None).await.unwrap()
     }
 
-    #[test]
-    fn test_extend() {
-        let mut db = create_test_db();
+    #[tokio::test]
+    async fn test_extend() {
+        let temp_dir = tempdir().unwrap();
+        let db = create_test_db(temp_dir.path()).await;
 
         // Test extending from level 0
-        db.extend(0).unwrap();
-        assert_eq!(db.levels.len(), 2);
-        assert_eq!(db.levels[1].width, 3); // 2 * 1.5 = 3
-        assert_eq!(db.levels[1].depth, 1);
-        assert_eq!(db.levels[1].inner.len(), 0);
+        db.extend(1).await.unwrap();
+        let levels = db.levels.read().await;
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1].width, 3); // 2 * 1.618 = 3.2, rounded to 3
+        assert_eq!(levels[1].depth, 1);
+        assert_eq!(levels[1].inner.len(), 0);
+        drop(levels);
 
         // Test extending from level 1
-        db.extend(1).unwrap();
-        assert_eq!(db.levels.len(), 3);
-        assert_eq!(db.levels[2].width, 4); // 3 * 1.5 = 4.5, rounded to 4
-        assert_eq!(db.levels[2].depth, 2);
+        db.extend(2).await.unwrap();
+        let levels = db.levels.read().await;
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[2].width, 4); // 3 * 1.618 = 4.85, rounded to 4
+        assert_eq!(levels[2].depth, 2);
     }
 
-    #[test]
-    fn test_insert_without_compaction() {
-        let mut db = create_test_db();
+    #[tokio::test]
+    async fn test_insert_without_compaction() {
         let temp_dir = tempdir().unwrap();
+        let db = create_test_db(temp_dir.path()).await;
 
         // Create a test SSTable
-        let sstable = create_test_sstable(1, 10, &temp_dir.path().to_path_buf());
+        let sstable = create_test_sstable(1, 10, temp_dir.path());
 
         // Insert into level 0
-        db.insert_new_table(sstable.clone(), 0).unwrap();
+        db.insert_new_table(sstable.clone(), 0).await.unwrap();
 
         // Verify it was inserted
-        assert_eq!(db.levels[0].inner.len(), 1);
-        assert_eq!(db.levels[0].total_entries, 10);
+        let levels = db.levels.read().await;
+        assert_eq!(levels[0].inner.len(), 1);
+        assert_eq!(levels[0].total_entries, 10);
     }
 
-    #[test]
-    fn test_insert_with_compaction() {
-        let mut db = create_test_db();
+    #[tokio::test]
+    async fn test_insert_with_compaction() {
         let temp_dir = tempdir().unwrap();
+        let db = create_test_db(temp_dir.path()).await;
 
         // Level 0 has width of 2, so adding 2 tables should trigger compaction
-        let sstable1 = create_test_sstable(1, 10, &temp_dir.path().to_path_buf());
-        let sstable2 = create_test_sstable(2, 15, &temp_dir.path().to_path_buf());
+        let sstable1 = create_test_sstable(1, 10, temp_dir.path());
+        let sstable2 = create_test_sstable(2, 15, temp_dir.path());
 
         // Insert first table (shouldn't trigger compaction)
-        db.insert_new_table(sstable1, 0).unwrap();
-        assert_eq!(db.levels[0].inner.len(), 1);
-        assert_eq!(db.levels.len(), 1);
+        db.insert_new_table(sstable1, 0).await.unwrap();
+        {
+            let levels = db.levels.read().await;
+            assert_eq!(levels[0].inner.len(), 1);
+            assert_eq!(levels.len(), 1);
+        }
 
         // Insert second table (should trigger compaction and create level 1)
-        db.insert_new_table(sstable2, 0).unwrap();
+        db.insert_new_table(sstable2, 0).await.unwrap();
 
+        let levels = db.levels.read().await;
         // Level 0 should be cleared after compaction
-        assert_eq!(db.levels[0].inner.len(), 0);
+        assert_eq!(levels[0].inner.len(), 0);
 
         // Level 1 should exist with 1 table containing all entries
-        assert_eq!(db.levels.len(), 2);
-        assert_eq!(db.levels[1].inner.len(), 1);
-        assert_eq!(db.levels[1].total_entries, 25); // 10 + 15
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1].inner.len(), 1);
+        assert_eq!(levels[1].total_entries, 25); // 10 + 15
     }
 
-    #[test]
-    fn test_multi_level_compaction() {
-        let mut db = create_test_db();
+    #[tokio::test]
+    async fn test_multi_level_compaction() {
         let temp_dir = tempdir().unwrap();
+        let db = create_test_db(temp_dir.path()).await;
 
         // Set up to trigger multiple levels of compaction
         // Level 0: width 2
@@ -446,30 +526,94 @@ mod tests {
 
         // Create test tables
         let tables = (0..7)
-            .map(|i| create_test_sstable(i, 10, &temp_dir.path().to_path_buf()))
+            .map(|i| create_test_sstable(i, 10, temp_dir.path()))
             .collect::<Vec<_>>();
 
         // Insert 2 tables to level 0, triggering compaction to level 1
-        db.insert_new_table(tables[0].clone(), 0).unwrap();
-        db.insert_new_table(tables[1].clone(), 0).unwrap();
+        db.insert_new_table(tables[0].clone(), 0).await.unwrap();
+        db.insert_new_table(tables[1].clone(), 0).await.unwrap();
 
         // Level 0 should be empty, level 1 should have 1 compacted table
-        assert_eq!(db.levels[0].inner.len(), 0);
-        assert_eq!(db.levels[1].inner.len(), 1);
+        {
+            let levels = db.levels.read().await;
+            assert_eq!(levels[0].inner.len(), 0);
+            assert_eq!(levels[1].inner.len(), 1);
+        }
 
         // Insert 2 more tables to level 0, triggering another compaction to level 1
-        db.insert_new_table(tables[2].clone(), 0).unwrap();
-        db.insert_new_table(tables[3].clone(), 0).unwrap();
+        db.insert_new_table(tables[2].clone(), 0).await.unwrap();
+        db.insert_new_table(tables[3].clone(), 0).await.unwrap();
 
         // Level 1 should now have 2 tables
-        assert_eq!(db.levels[1].inner.len(), 2);
+        assert_eq!(db.levels.read().await[1].inner.len(), 2);
 
         // Insert 1 more table directly to level 1, triggering compaction to level 2
-        db.insert_new_table(tables[4].clone(), 1).unwrap();
+        db.insert_new_table(tables[4].clone(), 1).await.unwrap();
 
         // Level 1 should be empty, level 2 should have 1 compacted table
-        assert_eq!(db.levels[1].inner.len(), 0);
-        assert_eq!(db.levels[2].inner.len(), 1);
-        assert_eq!(db.levels[2].total_entries, 50); // 5 tables * 10 entries
+        let levels = db.levels.read().await;
+        assert_eq!(levels[1].inner.len(), 0);
+        assert_eq!(levels[2].inner.len(), 1);
+        assert_eq!(levels[2].total_entries, 50); // 5 tables * 10 entries
+    }
+
+    #[tokio::test]
+    async fn test_compaction_keeps_newest_entry() {
+        let temp_dir = tempdir().unwrap();
+        let db = create_test_db(temp_dir.path()).await;
+        let features = || SSTableFeatures {
+            fpr: 0.01,
+            item_count: 2,
+            ..Default::default()
+        };
+
+        let older_path = temp_dir.path().join("older");
+        let mut older = StreamedSSTableBuilder::new(features(), true, &older_path).unwrap();
+        older
+            .add_from_kv(KeyValue {
+                seq: 1,
+                ..KeyValue::new("key-a", "old")
+            })
+            .unwrap();
+        older
+            .add_from_kv(KeyValue {
+                seq: 2,
+                ..KeyValue::new("key-b", "old")
+            })
+            .unwrap();
+
+        let newer_path = temp_dir.path().join("newer");
+        let mut newer = StreamedSSTableBuilder::new(features(), true, &newer_path).unwrap();
+        newer
+            .add_from_kv(KeyValue {
+                seq: 3,
+                ..KeyValue::new("key-a", "new")
+            })
+            .unwrap();
+        newer
+            .add_from_kv(KeyValue {
+                seq: 4,
+                ..KeyValue::tombstone("key-b")
+            })
+            .unwrap();
+
+        // installed out of order: the sequence numbers decide, not the position
+        db.insert_new_table(newer.finalize().unwrap(), 0).await.unwrap();
+        db.insert_new_table(older.finalize().unwrap(), 0).await.unwrap();
+
+        // nothing sits below the new level, so the tombstone is dropped too
+        let levels = db.levels.read().await;
+        assert_eq!(levels[1].total_entries, 1);
+        let merged = levels[1].inner[0]
+            .iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            merged,
+            vec![KeyValue {
+                seq: 3,
+                ..KeyValue::new("key-a", "new")
+            }]
+        );
     }
 }
@@ -1,16 +1,56 @@
-use key_value::KeyValue;
-use memtable::{mem_table_builder::MemTableBuilder, MemTable, MemTableOperations};
+use arc_swap::ArcSwap;
+use key_value::{
+    comparator::{default_comparator, BytewiseComparator, Comparator},
+    KeyValue,
+};
+use memtable::{
+    arena_skiplist::{MAX_KEY_LEN, MAX_VALUE_LEN},
+    mem_table_builder::MemTableBuilder,
+    MemTable, MemTableOperations,
+};
 use rayon::prelude::*;
-use sstable::{builder::SSTableFeatures, error::SSTableError, SSTable};
-use std::{path::PathBuf, sync::Arc};
+use sstable::{
+    block_cache::BlockCache,
+    builder::SSTableFeatures,
+    error::SSTableError,
+    filter::{default_filter_policy, FilterPolicy},
+    prefix::PrefixExtractor,
+    table_cache::TableCache,
+    SSTable, TableReadOptions,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 use tokio::sync::{Mutex, RwLock};
 use tokio::task;
 use uuid::Uuid;
 
+use crate::db_iterator::{DbIterator, MemTableSource, Source};
 use crate::error::LsmError;
+use crate::flush::{FlushQueue, PendingFlush, WriteStallStats};
+use crate::manifest::{LevelLayout, Manifest, ManifestEdit, MANIFEST_FILE};
+use crate::options::DbOptions;
+use crate::row_cache::RowCache;
+use crate::snapshot::{Snapshot, SnapshotList};
+use crate::wal::{WalRecord, WalSegment, WalWriter};
+use crate::write_buffer_manager::{Reservation, WriteBufferManager};
 
+const TABLE_PREFIX: &str = "sstable-id-";
+// how long a write is held back once level 0 reaches `level0_slowdown_tables`
+const SLOWDOWN: std::time::Duration = std::time::Duration::from_millis(1);
+// how long a failed flush waits before its first retry; each failure in a
+// row doubles the wait, up to `FLUSH_RETRY_MAX`
+const FLUSH_RETRY: std::time::Duration = std::time::Duration::from_millis(50);
+const FLUSH_RETRY_MAX: std::time::Duration = std::time::Duration::from_secs(5);
 
-
+/// The memtables, newest first: the active one, then the immutable ones
+/// waiting for their flush. A list is never changed once published.
+pub type MemTableList = Vec<(Uuid, Arc<MemTable>)>;
 
 #[derive(Debug)]
 pub struct Level {
@@ -22,34 +62,389 @@ pub struct Level {
 
 #[derive(Debug)]
 pub struct LsmDatabase {
-    pub memtables: Arc<Mutex<Vec<(Uuid, Arc<MemTable>)>>>,
+    /// Readers load the current list without taking a lock; writers swap
+    /// in a new one to rotate the active memtable, and flushes to drop the
+    /// memtables they have written out.
+    pub memtables: Arc<ArcSwap<MemTableList>>,
     pub levels: Arc<RwLock<Vec<Level>>>,
     pub parent_directory: PathBuf,
     pub capacity_expansion_factor: f64,
     pub base_fpr: f64,
+    /// See `DbOptions::max_memtables`.
     pub max_memtables: usize,
+    /// See `DbOptions::level0_slowdown_tables`.
+    pub level0_slowdown_tables: usize,
+    /// See `DbOptions::level0_stop_tables`.
+    pub level0_stop_tables: usize,
+    pub wal: Arc<Mutex<WalWriter>>,
+    pub manifest: Arc<Mutex<Manifest>>,
+    /// Sequence number of the most recent write. Every put and delete takes
+    /// the next one while holding the log lock, and publishes it here once
+    /// it is in the memtable.
+    pub last_sequence: Arc<AtomicU64>,
+    pub snapshots: Arc<SnapshotList>,
+    /// Key order shared by the memtables, tables and compaction.
+    pub comparator: Arc<dyn Comparator>,
+    /// Blocks read by any table; its stats show how well it is sized.
+    pub block_cache: Arc<BlockCache>,
+    /// Newest versions of recently read keys; see `DbOptions::row_cache_capacity`.
+    pub row_cache: Arc<RowCache>,
+    /// Open file handles of the tables.
+    pub table_cache: Arc<TableCache>,
+    pub use_mmap: bool,
+    /// Key filter policy of each level; see `DbOptions::filter_policies`.
+    pub filter_policies: Vec<Arc<dyn FilterPolicy>>,
+    /// Whether new tables get a range filter.
+    pub range_filters: bool,
+    /// See `DbOptions::prefix_extractor`.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// See `DbOptions::index_partition_blocks`.
+    pub index_partition_blocks: Option<usize>,
+    /// See `DbOptions::learned_index`.
+    pub learned_index: Option<usize>,
+    /// Builds every new memtable, with the type and limits of `DbOptions`.
+    memtable_builder: MemTableBuilder,
+    /// This database's share of its write buffer manager.
+    write_buffer: Arc<Reservation>,
+    /// Immutable memtables waiting for the background flush.
+    flush_queue: Arc<FlushQueue>,
 }
 
 impl LsmDatabase {
-    pub fn new(data_dir: impl Into<PathBuf>, expand: Option<f64>) -> Self {
-        let first = Level {
-            inner: Vec::new(),
-            depth: 0,
-            width: 2,
-            total_entries: 0,
+    /// Opens the database in `data_dir`. The level layout is rebuilt from the
+    /// MANIFEST, then any write-ahead log segments left behind by a previous
+    /// process are replayed. Every segment except the newest is flushed
+    /// straight away; the newest one becomes the active memtable again and
+    /// keeps receiving writes. A directory holding tables but no MANIFEST is
+    /// refused, as there is no telling which of them are live.
+    pub async fn open(data_dir: impl Into<PathBuf>, expand: Option<f64>) -> Result<Self, LsmError> {
+        LsmDatabase::open_with_comparator(data_dir, expand, default_comparator()).await
+    }
+
+    /// Like `open`, with keys ordered by `comparator` instead of bytewise.
+    /// A database must always be opened with the comparator it was created
+    /// with; its tables refuse to open otherwise.
+    pub async fn open_with_comparator(
+        data_dir: impl Into<PathBuf>,
+        expand: Option<f64>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self, LsmError> {
+        let options = DbOptions {
+            comparator,
+            ..DbOptions::default()
         };
+        LsmDatabase::open_with_options(data_dir, expand, options).await
+    }
 
+    /// Like `open`, with every setting in `options`.
+    pub async fn open_with_options(
+        data_dir: impl Into<PathBuf>,
+        expand: Option<f64>,
+        DbOptions {
+            comparator,
+            block_cache_capacity,
+            row_cache_capacity,
+            table_cache_capacity,
+            use_mmap,
+            filter_policies,
+            range_filters,
+            prefix_extractor,
+            index_partition_blocks,
+            learned_index,
+            memtable_type,
+            write_buffer_size,
+            max_memtable_entries,
+            write_buffer_manager,
+            max_memtables,
+            level0_slowdown_tables,
+            level0_stop_tables,
+        }: DbOptions,
+    ) -> Result<Self, LsmError> {
+        let parent_directory: PathBuf = data_dir.into();
+        let read_options = TableReadOptions {
+            block_cache: Some(Arc::new(BlockCache::new(block_cache_capacity))),
+            table_cache: Some(Arc::new(TableCache::new(table_cache_capacity))),
+            mmap: use_mmap,
+        };
+        let memtable_builder = MemTableBuilder::default()
+            .memtable_type(memtable_type)
+            .max_entries(max_memtable_entries)
+            .max_bytes(write_buffer_size)
+            .comparator(comparator.clone());
+        let write_buffer = Arc::new(Reservation::new(
+            write_buffer_manager.unwrap_or_else(|| Arc::new(WriteBufferManager::new(0))),
+        ));
+        std::fs::create_dir_all(&parent_directory)?;
+
+        let layout = match Manifest::recover(&parent_directory)? {
+            Some(layout) => layout,
+            // without a manifest, every table would be taken for an orphan
+            None if !LsmDatabase::table_files(&parent_directory)?.is_empty() => {
+                return Err(LsmError::Other(format!(
+                    "{} holds tables but no {}; not opening it, as they would be removed",
+                    parent_directory.display(),
+                    MANIFEST_FILE
+                )));
+            }
+            None => vec![LevelLayout {
+                depth: 0,
+                width: 2,
+                tables: Vec::new(),
+            }],
+        };
+        let levels =
+            LsmDatabase::load_levels(&parent_directory, &layout, &comparator, &read_options)?;
+        // memtables are flushed oldest first, so the tables hold every write
+        // up to their newest one. A log segment can still hold some of them
+        // if the process died between a flush and the segment's removal;
+        // those records are left out of the replay, as the tables may have
+        // newer versions of them further down by now.
+        let flushed_sequence = levels
+            .iter()
+            .flat_map(|level| level.inner.iter())
+            .map(|table| table.max_sequence())
+            .max()
+            .unwrap_or(0);
+        let mut last_sequence = flushed_sequence;
+        let manifest = Manifest::create(&parent_directory, &LsmDatabase::snapshot_edits(&layout))?;
+        LsmDatabase::remove_orphaned_tables(&parent_directory, &layout)?;
 
-        let initial_memtable = Arc::new(MemTableBuilder::default().max_entries(1000).build());
-        let initial_id = Uuid::new_v4();
+        let mut segments = WalSegment::list(&parent_directory)?;
+        let (active_id, active_memtable, wal) = match segments.pop() {
+            Some(newest) => {
+                let memtable = LsmDatabase::new_memtable(&memtable_builder);
+                let (records, valid_len) = newest.replay()?;
+                log::info!(
+                    "Replayed {} records from {}",
+                    records.len(),
+                    newest.path.display()
+                );
+                for record in records {
+                    last_sequence = last_sequence.max(record.seq());
+                    if record.seq() > flushed_sequence {
+                        LsmDatabase::apply_record(&memtable, record);
+                    }
+                }
+                write_buffer.reserve(memtable.memory_usage());
+                let id = newest.memtable_id;
+                (id, memtable, WalWriter::reopen(&parent_directory, newest, valid_len)?)
+            }
+            None => {
+                let id = Uuid::new_v4();
+                let memtable = LsmDatabase::new_memtable(&memtable_builder);
+                (id, memtable, WalWriter::create(&parent_directory, 0, id)?)
+            }
+        };
 
-        Self {
-            memtables: Arc::new(Mutex::new(vec![(initial_id, initial_memtable)])),
-            levels: Arc::new(RwLock::new(vec![first])),
-            parent_directory: data_dir.into(),
+        for segment in &segments {
+            let (records, _) = segment.replay()?;
+            if let Some(seq) = records.iter().map(WalRecord::seq).max() {
+                last_sequence = last_sequence.max(seq);
+            }
+        }
+
+        let db = Self {
+            memtables: Arc::new(ArcSwap::from_pointee(vec![(active_id, active_memtable)])),
+            levels: Arc::new(RwLock::new(levels)),
+            parent_directory,
             capacity_expansion_factor: expand.unwrap_or(1.618),
             base_fpr: 0.005,
-            max_memtables: 10,
+            // with no room for one immutable memtable, writes would never resume
+            max_memtables: max_memtables.max(1),
+            level0_slowdown_tables,
+            level0_stop_tables,
+            wal: Arc::new(Mutex::new(wal)),
+            manifest: Arc::new(Mutex::new(manifest)),
+            last_sequence: Arc::new(AtomicU64::new(last_sequence)),
+            snapshots: Arc::new(SnapshotList::default()),
+            comparator,
+            block_cache: read_options.block_cache.clone().unwrap(),
+            row_cache: Arc::new(RowCache::new(row_cache_capacity)),
+            table_cache: read_options.table_cache.clone().unwrap(),
+            use_mmap,
+            filter_policies,
+            range_filters,
+            prefix_extractor,
+            index_partition_blocks,
+            learned_index,
+            memtable_builder,
+            write_buffer,
+            flush_queue: Arc::new(FlushQueue::default()),
+        };
+
+        for segment in segments {
+            let memtable = LsmDatabase::new_memtable(&db.memtable_builder);
+            let (records, _) = segment.replay()?;
+            log::info!(
+                "Replayed {} records from {}, flushing",
+                records.len(),
+                segment.path.display()
+            );
+            for record in records {
+                if record.seq() > flushed_sequence {
+                    LsmDatabase::apply_record(&memtable, record);
+                }
+            }
+            if memtable.current_length() > 0 {
+                let sstable = LsmDatabase::flash_memtable(
+                    db.parent_directory.clone(),
+                    memtable,
+                    Vec::new(),
+                    db.table_features(0),
+                )
+                .await?;
+                db.insert_new_table(sstable, 0).await?;
+            }
+            segment.delete()?;
+        }
+
+        Ok(db)
+    }
+
+    /// How every table of this database reads its file.
+    pub(crate) fn table_read_options(&self) -> TableReadOptions {
+        TableReadOptions {
+            block_cache: Some(self.block_cache.clone()),
+            table_cache: Some(self.table_cache.clone()),
+            mmap: self.use_mmap,
+        }
+    }
+
+    /// How tables written into level `depth` are built, apart from their
+    /// size and false positive rate.
+    pub(crate) fn table_features(&self, depth: usize) -> SSTableFeatures {
+        SSTableFeatures {
+            read_options: self.table_read_options(),
+            filter_policy: Some(self.filter_policy(depth)),
+            range_filter: self.range_filters,
+            prefix_extractor: self.prefix_extractor.clone(),
+            index_partition_blocks: self.index_partition_blocks,
+            learned_index: self.learned_index,
+            ..SSTableFeatures::default()
+        }
+    }
+
+    /// The filter policy of tables written into level `depth`.
+    pub(crate) fn filter_policy(&self, depth: usize) -> Arc<dyn FilterPolicy> {
+        self.filter_policies
+            .get(depth)
+            .or(self.filter_policies.last())
+            .cloned()
+            .unwrap_or_else(default_filter_policy)
+    }
+
+    fn new_memtable(builder: &MemTableBuilder) -> Arc<MemTable> {
+        Arc::new(builder.clone().build())
+    }
+
+    /// Counts the memory of every memtable of this database, and of any
+    /// other database sharing its manager.
+    pub fn write_buffer_manager(&self) -> &Arc<WriteBufferManager> {
+        self.write_buffer.manager()
+    }
+
+    fn load_levels(
+        dir: &Path,
+        layout: &[LevelLayout],
+        comparator: &Arc<dyn Comparator>,
+        read_options: &TableReadOptions,
+    ) -> Result<Vec<Level>, LsmError> {
+        let mut levels = Vec::with_capacity(layout.len());
+        for level_layout in layout {
+            let mut inner = Vec::with_capacity(level_layout.tables.len());
+            for file_name in &level_layout.tables {
+                inner.push(SSTable::open_with_options(
+                    dir.join(file_name),
+                    comparator.clone(),
+                    read_options.clone(),
+                )?);
+            }
+            log::info!(
+                "Restored level {} with {} tables, width {}",
+                level_layout.depth,
+                inner.len(),
+                level_layout.width
+            );
+            levels.push(Level {
+                total_entries: inner.iter().map(|table| table.actual_item_count).sum(),
+                inner,
+                depth: level_layout.depth,
+                width: level_layout.width,
+            });
+        }
+        Ok(levels)
+    }
+
+    fn snapshot_edits(layout: &[LevelLayout]) -> Vec<ManifestEdit> {
+        let mut edits = Vec::new();
+        for level_layout in layout {
+            edits.push(ManifestEdit::AddLevel {
+                depth: level_layout.depth,
+                width: level_layout.width,
+            });
+        }
+        for level_layout in layout {
+            for file_name in &level_layout.tables {
+                edits.push(ManifestEdit::AddTable {
+                    level: level_layout.depth,
+                    file_name: file_name.clone(),
+                });
+            }
+        }
+        edits
+    }
+
+    // Tables that never made it into the manifest (e.g. a compaction output
+    // from a crashed run) are unreachable, and their data is still covered by
+    // the tables or log segments that produced them.
+    fn remove_orphaned_tables(dir: &Path, layout: &[LevelLayout]) -> Result<(), LsmError> {
+        let live: HashSet<&str> = layout
+            .iter()
+            .flat_map(|level_layout| level_layout.tables.iter().map(String::as_str))
+            .collect();
+
+        for (name, path) in LsmDatabase::table_files(dir)? {
+            if !live.contains(name.as_str()) {
+                log::info!("Removing orphaned table {}", path.display());
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the name and path of every table file in `dir`
+    fn table_files(dir: &Path) -> Result<Vec<(String, PathBuf)>, LsmError> {
+        let mut tables = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.starts_with(TABLE_PREFIX) {
+                tables.push((name.to_string(), path));
+            }
+        }
+        tables.sort();
+        Ok(tables)
+    }
+
+    pub(crate) fn table_file_name(table: &SSTable) -> String {
+        table
+            .file_path()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn new_table_path(&self) -> PathBuf {
+        self.parent_directory
+            .join(format!("{}{}", TABLE_PREFIX, Uuid::new_v4()))
+    }
+
+    fn apply_record(memtable: &MemTable, record: WalRecord) {
+        match record {
+            WalRecord::Put { seq, key, value } => memtable.insert(key, value, seq),
+            WalRecord::Delete { seq, key } => memtable.delete(key, seq),
         }
     }
 
@@ -57,193 +452,619 @@ impl LsmDatabase {
     pub async fn flash_memtable(
         parent_dir: PathBuf,
         memtable: Arc<MemTable>,
+        snapshots: Vec<u64>,
+        features: SSTableFeatures,
     ) -> Result<Arc<SSTable>, LsmError> {
 
         let features = SSTableFeatures {
             item_count: memtable.current_length(),
             fpr: 0.016,
+            ..features
         };
 
         let sstable = task::spawn_blocking(move || {
-            let path = parent_dir.join(format!("sstable-id-{}", Uuid::new_v4()));
-            memtable.flush(path, features).expect("flush failed")
+            let path = parent_dir.join(format!("{}{}", TABLE_PREFIX, Uuid::new_v4()));
+            memtable.flush(path, features, &snapshots)
         })
         .await
-        .expect("flush task panic");
+        .expect("flush task panic")?;
 
         Ok(sstable)
     }
 
-    pub async fn get(&self, key: String) -> Result<Arc<KeyValue>, LsmError> {
-        let memtables = self.memtables.lock().await;
+    /// Takes a snapshot of the database as of the last completed write.
+    pub async fn snapshot(&self) -> Snapshot {
+        // A write publishes its sequence number only once it is in a
+        // memtable, so every write the snapshot covers is already readable.
+        // The number is read under the snapshot list's lock: a flush that
+        // misses the snapshot took its list before the read, from a memtable
+        // rotated earlier still, so none of the versions it prunes is one
+        // the snapshot would read.
+        SnapshotList::acquire(&self.snapshots, &self.last_sequence)
+    }
+
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Result<Arc<KeyValue>, LsmError> {
+        self.get_at_sequence(key.as_ref(), u64::MAX).await
+    }
+
+    /// Reads `key` as it was when `snapshot` was taken.
+    pub async fn get_at(
+        &self,
+        key: impl AsRef<[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<Arc<KeyValue>, LsmError> {
+        self.get_at_sequence(key.as_ref(), snapshot.sequence()).await
+    }
+
+    async fn get_at_sequence(&self, key: &[u8], seq: u64) -> Result<Arc<KeyValue>, LsmError> {
+        // taken before the memtables are read, so that a write the read
+        // misses there also keeps what the tables hold out of the row cache
+        let read_sequence = self.last_sequence.load(Ordering::SeqCst);
+        let memtables = self.memtables.load();
 
         for (_, memtable) in memtables.iter() {
-            if let Some(kv) = memtable.get(&key) {
-                if kv.value == "d34db33f" {
+            if let Some(kv) = memtable.get_at(key, seq) {
+                if kv.is_tombstone() {
                     return Err(LsmError::KeyNotFound);
                 }
                 return Ok(kv.into());
             }
         }
+        // only reads of the latest state go through the row cache
+        let cached = seq == u64::MAX;
+        if cached && let Some(kv) = self.row_cache.get(key) {
+            if kv.is_tombstone() {
+                return Err(LsmError::KeyNotFound);
+            }
+            return Ok(kv);
+        }
         drop(memtables);
 
         let levels = self.levels.read().await;
 
-        let result = levels
-            .par_iter()
-            .flat_map(|lvl| lvl.inner.par_iter())
-            .find_map_any(|sst| match sst.get(key.clone()) {
-                Ok(kv) if kv.value != "d34db33f" => Some(Ok(kv)),
-                Ok(_) => Some(Err(LsmError::KeyNotFound)),
-                Err(SSTableError::KeyNotfound) => None,
-                Err(e) => Some(Err(LsmError::SSTable(e))),
-            });
+        // data only moves down, so a shallower level always holds the newer
+        // version. Tables within a level may overlap; the highest sequence
+        // number among them wins, and a tombstone hides what it deleted.
+        for level in levels.iter() {
+            let hits = level
+                .inner
+                .par_iter()
+                .filter_map(|sst| match sst.get_at(key, seq) {
+                    Ok(kv) => Some(Ok(kv)),
+                    Err(SSTableError::KeyNotfound) => None,
+                    Err(e) => Some(Err(LsmError::SSTable(e))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
 
-        result.unwrap_or(Err(LsmError::KeyNotFound))
-    }
+            if let Some(kv) = hits.into_iter().max_by_key(|kv| kv.seq) {
+                if cached {
+                    self.row_cache.insert(kv.clone(), read_sequence);
+                }
+                if kv.is_tombstone() {
+                    return Err(LsmError::KeyNotFound);
+                }
+                return Ok(kv);
+            }
+        }
 
-    pub async fn put(&self, key: String, value: String) -> Result<(), LsmError> {
-        let mut memtables = self.memtables.lock().await;
-        let (active_id, active_memtable) = &memtables[0];
+        Err(LsmError::KeyNotFound)
+    }
 
-        active_memtable.insert(key, value);
+    /// Reads every key of `keys` at once. The results line up with `keys`,
+    /// and a key asked for twice gets the same answer twice.
+    pub async fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Arc<KeyValue>, LsmError>> {
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_ref()).collect();
+        self.multi_get_at_sequence(&keys, u64::MAX).await
+    }
 
-        if active_memtable.at_capacity() {
+    /// Reads `keys` as they were when `snapshot` was taken.
+    pub async fn multi_get_at<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        snapshot: &Snapshot,
+    ) -> Vec<Result<Arc<KeyValue>, LsmError>> {
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_ref()).collect();
+        self.multi_get_at_sequence(&keys, snapshot.sequence()).await
+    }
 
-            let full_table = active_memtable.clone();
-            let full_id = *active_id;
+    // Like `get_at_sequence` for many keys, but each list is read once and
+    // every table is handed the keys still unresolved, sorted, so it can
+    // filter them in bulk and read each of its blocks once.
+    async fn multi_get_at_sequence(
+        &self,
+        keys: &[&[u8]],
+        seq: u64,
+    ) -> Vec<Result<Arc<KeyValue>, LsmError>> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| self.comparator.compare(keys[a], keys[b]));
+        let mut unique: Vec<&[u8]> = Vec::new();
+        let mut slots = vec![0; keys.len()];
+        for &index in &order {
+            if unique
+                .last()
+                .is_none_or(|last| self.comparator.compare(last, keys[index]).is_ne())
+            {
+                unique.push(keys[index]);
+            }
+            slots[index] = unique.len() - 1;
+        }
 
-            let new_id = Uuid::new_v4();
-            let new_table = Arc::new(MemTableBuilder::default().max_entries(1000).build());
+        // `None` until some memtable or table holds a version of the key
+        let mut found: Vec<Option<Result<Arc<KeyValue>, LsmError>>> =
+            unique.iter().map(|_| None).collect();
 
-            memtables.insert(0, (new_id, new_table));
-            drop(memtables);
+        let read_sequence = self.last_sequence.load(Ordering::SeqCst);
+        let memtables = self.memtables.load();
+        for (slot, key) in unique.iter().enumerate() {
+            found[slot] = memtables
+                .iter()
+                .find_map(|(_, memtable)| memtable.get_at(key, seq))
+                .map(|kv| Ok(kv.into()));
+        }
+        let cached = seq == u64::MAX;
+        // keys the memtables don't have may be in the row cache; the rest
+        // are filled into it once the tables have answered
+        let mut from_tables = vec![false; unique.len()];
+        for (slot, key) in unique.iter().enumerate() {
+            if cached && found[slot].is_none() {
+                found[slot] = self.row_cache.get(key).map(Ok);
+                from_tables[slot] = found[slot].is_none();
+            }
+        }
+        drop(memtables);
 
-            let memtables_ref = Arc::clone(&self.memtables);
-            let parent_dir = self.parent_directory.clone();
+        let levels = self.levels.read().await;
+        for level in levels.iter() {
+            let pending: Vec<usize> = (0..unique.len()).filter(|&slot| found[slot].is_none()).collect();
+            if pending.is_empty() {
+                break;
+            }
+            let probes: Vec<&[u8]> = pending.iter().map(|&slot| unique[slot]).collect();
+            let per_table: Vec<_> = level
+                .inner
+                .par_iter()
+                .map(|sst| sst.multi_get_at(&probes, seq))
+                .collect();
 
-            let sstable = match LsmDatabase::flash_memtable(parent_dir, full_table).await {
-                Ok(table) => {
-                    table
+            for results in per_table {
+                for (&slot, result) in pending.iter().zip(results) {
+                    found[slot] = match (found[slot].take(), result) {
+                        (Some(Err(e)), _) => Some(Err(e)),
+                        (current, Err(SSTableError::KeyNotfound)) => current,
+                        (_, Err(e)) => Some(Err(LsmError::SSTable(e))),
+                        (Some(Ok(current)), Ok(kv)) if current.seq >= kv.seq => Some(Ok(current)),
+                        (_, Ok(kv)) => Some(Ok(kv)),
+                    };
                 }
-                Err(e) => {
-                    return Err(e);
+            }
+        }
+        drop(levels);
+
+        for (slot, result) in found.iter().enumerate() {
+            if from_tables[slot]
+                && let Some(Ok(kv)) = result
+            {
+                self.row_cache.insert(kv.clone(), read_sequence);
+            }
+        }
+
+        let mut found: Vec<Option<Result<Arc<KeyValue>, LsmError>>> = found
+            .into_iter()
+            .map(|result| match result {
+                Some(Ok(kv)) if !kv.is_tombstone() => Some(Ok(kv)),
+                Some(Err(e)) => Some(Err(e)),
+                _ => Some(Err(LsmError::KeyNotFound)),
+            })
+            .collect();
+
+        // the last request for a key takes its result, earlier duplicates
+        // get a copy
+        let mut uses = vec![0; unique.len()];
+        for &slot in &slots {
+            uses[slot] += 1;
+        }
+        slots
+            .iter()
+            .map(|&slot| {
+                uses[slot] -= 1;
+                if uses[slot] == 0 {
+                    return found[slot].take().expect("result taken once");
                 }
+                found[slot].clone().expect("result taken once")
+            })
+            .collect()
+    }
+
+    pub async fn put(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<(), LsmError> {
+        LsmDatabase::check_entry(key.as_ref(), Some(value.as_ref().len()))?;
+        let (key, value) = (Arc::from(key.as_ref()), Arc::from(value.as_ref()));
+        self.write(|seq| WalRecord::Put { seq, key, value }).await
+    }
+
+    // Refuses an entry that could be logged but never applied or flushed:
+    // it would fail every flush of its memtable, and every replay of its
+    // log. `value_len` is `None` for a delete. The length limits are those
+    // of the arena memtable, the tightest of them.
+    fn check_entry(key: &[u8], value_len: Option<usize>) -> Result<(), LsmError> {
+        if key.is_empty() {
+            return Err(LsmError::InvalidArgument("keys must not be empty".to_string()));
+        }
+        if key.len() > MAX_KEY_LEN {
+            return Err(LsmError::InvalidArgument(format!(
+                "key of {} bytes is longer than {} bytes",
+                key.len(),
+                MAX_KEY_LEN
+            )));
+        }
+        if let Some(len) = value_len
+            && len > MAX_VALUE_LEN
+        {
+            return Err(LsmError::InvalidArgument(format!(
+                "value of {} bytes is longer than {} bytes",
+                len, MAX_VALUE_LEN
+            )));
+        }
+        Ok(())
+    }
+
+    /// Logs and applies the record built by `make_record`, handing it the
+    /// next sequence number. A full memtable is swapped for an empty one and
+    /// queued for the background flush; the write doesn't wait for it.
+    /// Writes are ordered by the log lock alone, and readers never wait on
+    /// them.
+    async fn write(&self, make_record: impl FnOnce(u64) -> WalRecord) -> Result<(), LsmError> {
+        self.delay_write().await?;
+
+        let mut wal = self.wal.lock().await;
+        // only writers change the active memtable, and they hold the log lock
+        let memtables = self.memtables.load();
+        let (active_id, active_memtable) = &memtables[0];
+
+        let seq = self.last_sequence.load(Ordering::SeqCst) + 1;
+        let record = make_record(seq);
+        wal.append(&record)?;
+        self.row_cache.invalidate(record.key(), seq);
+        let before = active_memtable.memory_usage();
+        LsmDatabase::apply_record(active_memtable, record);
+        self.write_buffer.reserve(active_memtable.memory_usage() - before);
+        self.last_sequence.store(seq, Ordering::SeqCst);
+
+        // a full write buffer manager flushes whichever memtable it catches
+        // writing, so databases sharing it flush in proportion to their writes
+        if active_memtable.at_capacity() || self.write_buffer.manager().should_flush() {
+            let new_id = Uuid::new_v4();
+            let full = PendingFlush {
+                id: *active_id,
+                memtable: active_memtable.clone(),
+                segment: wal.rotate(new_id)?,
             };
+            self.write_buffer.mark_immutable(full.memtable.memory_usage());
+            let active = LsmDatabase::new_memtable(&self.memtable_builder);
+            // a flush may be dropping a memtable from the list at the same time
+            self.memtables.rcu(|list| {
+                let mut list = MemTableList::clone(list);
+                list.insert(0, (new_id, active.clone()));
+                list
+            });
+            drop(wal);
 
-            let compaction_start = std::time::Instant::now();
+            if self.flush_queue.push(full) {
+                let db = self.clone();
+                tokio::spawn(async move { db.run_flushes().await });
+            }
+        }
+        Ok(())
+    }
 
-            match self.insert_new_table(sstable, 0).await {
-                Ok(_) => log::info!("Compaction completed in {:?}", compaction_start.elapsed()),
-                Err(e) => {
-                    log::error!("Compaction error: {:?}", e);
-                    return Err(e);
+    // Holds a write back while flushes fall behind: it waits for a flush
+    // while `max_memtables` memtables are queued or level 0 has reached
+    // `level0_stop_tables`, and is delayed a little once level 0 reaches
+    // `level0_slowdown_tables`.
+    async fn delay_write(&self) -> Result<(), LsmError> {
+        let started = std::time::Instant::now();
+        let mut stopped = false;
+        loop {
+            let progress = self.flush_queue.progress();
+            let level0 = self.levels.read().await[0].inner.len();
+            // only the flush task compacts level 0, so without one running
+            // there is nothing to wait for
+            let level0_stop = level0 >= self.level0_stop_tables && !self.flush_queue.is_idle();
+            if self.flush_queue.len() >= self.max_memtables || level0_stop {
+                // the flush it would wait for is failing; the task keeps
+                // retrying, but the write doesn't wait on it
+                self.flush_queue.check()?;
+                stopped = true;
+                progress.await;
+                continue;
+            }
+            let slowed = !stopped && level0 >= self.level0_slowdown_tables;
+            if slowed {
+                tokio::time::sleep(SLOWDOWN).await;
+            }
+            if stopped || slowed {
+                let micros = started.elapsed().as_micros() as u64;
+                self.flush_queue.record_stall(stopped, micros);
+            }
+            return Ok(());
+        }
+    }
+
+    // Flushes queued memtables, oldest first, until the queue is empty. The
+    // table goes into the tree before the memtable leaves the list, so a
+    // read always finds the data in one or the other. A failed flush keeps
+    // its memtable queued and is retried with a growing backoff; later
+    // memtables wait behind it, so the tables always hold a prefix of the
+    // log.
+    async fn run_flushes(self) {
+        while let Some(PendingFlush {
+            id,
+            memtable,
+            segment,
+        }) = self.flush_queue.next()
+        {
+            let started = std::time::Instant::now();
+            let mut retry = FLUSH_RETRY;
+            let final_level_flag = loop {
+                let result = async {
+                    let sstable = LsmDatabase::flash_memtable(
+                        self.parent_directory.clone(),
+                        memtable.clone(),
+                        self.snapshots.sequences(),
+                        self.table_features(0),
+                    )
+                    .await?;
+                    self.install_table(sstable, 0, Vec::new()).await
+                }
+                .await;
+                match result {
+                    Ok(final_level_flag) => break final_level_flag,
+                    Err(e) => {
+                        log::error!("Flush of memtable {} failed, retrying in {:?}: {:?}", id, retry, e);
+                        self.flush_queue.fail(&e);
+                        tokio::time::sleep(retry).await;
+                        retry = (retry * 2).min(FLUSH_RETRY_MAX);
+                    }
                 }
+            };
+            // the memtable is in the tree now; a compaction that fails leaves
+            // level 0 deeper, for the next flush to compact
+            if let Err(e) = self.compact_from(0, final_level_flag).await {
+                log::error!("Compaction after flush of memtable {} failed: {:?}", id, e);
+            }
+            // the table is synced and recorded in the manifest, so its log is
+            // no longer needed; one left behind is skipped on replay
+            if let Err(e) = segment.delete() {
+                log::warn!("Could not delete log segment {}: {:?}", segment.path.display(), e);
             }
 
-            if let Ok(mut memtables) = memtables_ref.try_lock() {
-                memtables.retain(|(id, _)| id != &full_id);
+            self.memtables.rcu(|list| {
+                let mut list = MemTableList::clone(list);
+                list.retain(|(current, _)| current != &id);
+                list
+            });
+            self.write_buffer.free(memtable.memory_usage());
+            self.flush_queue.finish();
+            log::info!("Flushed memtable {} in {:?}", id, started.elapsed());
+        }
+    }
 
-            } else {
-                log::error!("Warning: Couldn't get lock to remove memtable {}", full_id);
+    /// Waits until every memtable queued so far is flushed, or reports why
+    /// the last flush failed while one is being retried. Call it before
+    /// dropping a database whose directory is opened again in this process.
+    pub async fn wait_for_flushes(&self) -> Result<(), LsmError> {
+        loop {
+            let progress = self.flush_queue.progress();
+            self.flush_queue.check()?;
+            if self.flush_queue.is_idle() {
+                return Ok(());
             }
+            progress.await;
+        }
+    }
 
-        };
-        Ok(())
+    /// Immutable memtables waiting for their flush.
+    pub fn pending_flushes(&self) -> usize {
+        self.flush_queue.len()
+    }
+
+    /// How often writes were held back for flushes to catch up.
+    pub fn stall_stats(&self) -> WriteStallStats {
+        self.flush_queue.stall_stats()
     }
 
-    pub async fn delete(&self, key: String) -> Result<(), LsmError> {
-        self.put(key, "d34db33f".into()).await
+    pub async fn delete(&self, key: impl AsRef<[u8]>) -> Result<(), LsmError> {
+        LsmDatabase::check_entry(key.as_ref(), None)?;
+        let key = Arc::from(key.as_ref());
+        self.write(|seq| WalRecord::Delete { seq, key }).await
     }
 
     pub async fn range(
         &self,
-        from_m: String,
-        to_n: String,
+        from_m: impl AsRef<[u8]>,
+        to_n: impl AsRef<[u8]>,
     ) -> Result<Vec<Box<KeyValue>>, LsmError> {
-        let mut results = Vec::new();
-        let mut found_first_key = false;
-        let mut start_key = from_m.clone();
+        self.range_at_sequence(from_m.as_ref(), to_n.as_ref(), u64::MAX)
+            .await
+    }
 
-        let memtables = &self.memtables.lock().await;
+    /// Reads the keys in `[from_m, to_n]` as they were when `snapshot` was
+    /// taken.
+    pub async fn range_at(
+        &self,
+        from_m: impl AsRef<[u8]>,
+        to_n: impl AsRef<[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<Box<KeyValue>>, LsmError> {
+        self.range_at_sequence(from_m.as_ref(), to_n.as_ref(), snapshot.sequence())
+            .await
+    }
 
-        for (_, memtable) in memtables.iter() {
-            let (mut mem_results, flag) = memtable.range(&from_m, &to_n);
+    /// The live entries whose keys start with `prefix`. Tables whose key
+    /// range or prefix filter rules the prefix out are not read. Needs
+    /// bytewise key order. An empty prefix matches every key.
+    pub async fn prefix_scan(
+        &self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<Vec<Box<KeyValue>>, LsmError> {
+        self.prefix_scan_at_sequence(prefix.as_ref(), u64::MAX).await
+    }
 
-            match flag {
-                memtable::RangeResult::FullSetFound => {
-                    // If any memtable has the full set, we're done
-                    return Ok(mem_results);
-                }
-                memtable::RangeResult::FirstKeyFound => {
-                    // Add these results and update our tracking
-                    results.append(&mut mem_results);
-                    found_first_key = true;
-                    // Update start_key to last key found
-                    if let Some(last) = results.last() {
-                        start_key = last.key.clone();
-                    }
-                }
-                memtable::RangeResult::KeyNotFound => {
-                    // This memtable didn't have what we needed, continue to next
+    /// Like `prefix_scan`, as the database was when `snapshot` was taken.
+    pub async fn prefix_scan_at(
+        &self,
+        prefix: impl AsRef<[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<Box<KeyValue>>, LsmError> {
+        self.prefix_scan_at_sequence(prefix.as_ref(), snapshot.sequence())
+            .await
+    }
+
+    /// An iterator over the live entries with keys in `[from_m, to_n]`. It
+    /// starts before `from_m` and can seek and move both ways within the
+    /// bounds.
+    pub async fn iter(
+        &self,
+        from_m: impl AsRef<[u8]>,
+        to_n: impl AsRef<[u8]>,
+    ) -> Result<DbIterator, LsmError> {
+        self.iter_at_sequence(from_m.as_ref(), to_n.as_ref(), u64::MAX)
+            .await
+    }
+
+    /// Like `iter`, but sees the database as it was when `snapshot` was taken.
+    pub async fn iter_at(
+        &self,
+        from_m: impl AsRef<[u8]>,
+        to_n: impl AsRef<[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<DbIterator, LsmError> {
+        self.iter_at_sequence(from_m.as_ref(), to_n.as_ref(), snapshot.sequence())
+            .await
+    }
+
+    async fn iter_at_sequence(
+        &self,
+        from_m: &[u8],
+        to_n: &[u8],
+        seq: u64,
+    ) -> Result<DbIterator, LsmError> {
+        // sources are numbered oldest first: the deepest level, up to the
+        // newest memtable. Tables only hold an `Arc`, so a compaction that
+        // retires one while we read keeps its file until we are done.
+        // Tables that cannot hold a key of the range are left out. The
+        // memtables are taken first: a flush puts its table in the tree
+        // before dropping the memtable, so nothing falls between the two.
+        let memtables = self.memtables.load_full();
+        let mut sources: Vec<Box<dyn Source>> = Vec::new();
+
+        let levels_guard = self.levels.read().await;
+        for level in levels_guard.iter().rev() {
+            for sstable in &level.inner {
+                if !sstable.may_overlap(from_m, to_n) {
                     continue;
                 }
+                sources.push(Box::new(sstable.cursor()));
             }
         }
+        drop(levels_guard);
 
-        // After checking all memtables, proceed based on what we found
-        if found_first_key {
-            // We found the first key in at least one memtable
-            // Proceed with SSTable search from the start_key
-            let levels_guard = self.levels.read().await;
+        for (_, memtable) in memtables.iter().rev() {
+            let (mem_results, _) = memtable.range_at(from_m, Some(to_n), seq);
+            let entries = mem_results.into_iter().map(|kv| *kv).collect();
+            sources.push(Box::new(MemTableSource::new(
+                entries,
+                self.comparator.clone(),
+            )));
+        }
 
-            'outer: for level in levels_guard.iter() {
-                for sstable in &level.inner {
-                    let (sst_entries, found_end) =
-                        sstable.get_until(&to_n).map_err(LsmError::SSTable)?;
+        Ok(DbIterator::new(
+            sources,
+            self.comparator.clone(),
+            seq,
+            from_m,
+            to_n,
+        ))
+    }
 
-                    for box_kv in sst_entries {
-                        let k = &box_kv.key;
-                        if k.as_str() > start_key.as_str() && k.as_str() >= from_m.as_str() {
-                            results.push(box_kv);
-                        }
-                    }
+    async fn prefix_scan_at_sequence(
+        &self,
+        prefix: &[u8],
+        seq: u64,
+    ) -> Result<Vec<Box<KeyValue>>, LsmError> {
+        if self.comparator.name() != BytewiseComparator.name() {
+            return Err(LsmError::InvalidArgument(
+                "prefix scans need bytewise key order".to_string(),
+            ));
+        }
+        let upper = prefix_successor(prefix);
 
-                    if found_end {
-                        break 'outer;
-                    }
-                }
-            }
+        // oldest first, as in `iter_at_sequence`, with each table giving
+        // only its run of keys with the prefix
+        let memtables = self.memtables.load_full();
+        let extractor = self.prefix_extractor.as_deref();
+        let tables: Vec<Arc<SSTable>> = self
+            .levels
+            .read()
+            .await
+            .iter()
+            .rev()
+            .flat_map(|level| level.inner.iter())
+            .filter(|sstable| sstable.may_contain_prefix(extractor, prefix))
+            .cloned()
+            .collect();
+        let mut runs: Vec<Vec<KeyValue>> = Vec::new();
+        for sstable in tables {
+            runs.push(sstable.prefix_scan(prefix)?);
+        }
+        for (_, memtable) in memtables.iter().rev() {
+            let (mem_results, _) = memtable.range_at(prefix, upper.as_deref(), seq);
+            runs.push(
+                mem_results
+                    .into_iter()
+                    .map(|kv| *kv)
+                    .filter(|kv| kv.key.starts_with(prefix))
+                    .collect(),
+            );
+        }
+
+        // every run holds only keys with the prefix, so without a successor
+        // the largest of them bounds the merge just as well
+        let upper = match upper {
+            Some(upper) => upper,
+            None => runs
+                .iter()
+                .filter_map(|run| run.last())
+                .map(|kv| kv.key.to_vec())
+                .max()
+                .unwrap_or_else(|| prefix.to_vec()),
+        };
+        let sources: Vec<Box<dyn Source>> = runs
+            .into_iter()
+            .map(|run| {
+                Box::new(MemTableSource::new(run, self.comparator.clone())) as Box<dyn Source>
+            })
+            .collect();
+        let results = DbIterator::new(sources, self.comparator.clone(), seq, prefix, &upper)
+            .map(|kv| kv.map(Box::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        if results.is_empty() {
+            Err(LsmError::KeyNotFound)
         } else {
-            // We didn't find the first key in any memtable
-            // Proceed with full SSTable search
-            let levels_guard = self.levels.read().await;
-
-            'outer: for level in levels_guard.iter() {
-                for sstable in &level.inner {
-                    match sstable.get(from_m.clone()) {
-                        Ok(_) => {
-                            for kv_res in sstable.iter() {
-                                let kv = kv_res.map_err(LsmError::SSTable)?;
-
-                                if kv.key < from_m {
-                                    continue;
-                                }
-                                if kv.key > to_n {
-                                    break 'outer;
-                                }
-
-                                results.push(Box::new(kv.clone()));
-                            }
-                            break 'outer;
-                        }
-                        Err(SSTableError::KeyNotfound) => continue,
-                        Err(e) => return Err(LsmError::SSTable(e)),
-                    }
-                }
-            }
+            Ok(results)
         }
+    }
 
+    async fn range_at_sequence(
+        &self,
+        from_m: &[u8],
+        to_n: &[u8],
+        seq: u64,
+    ) -> Result<Vec<Box<KeyValue>>, LsmError> {
+        let results = self
+            .iter_at_sequence(from_m, to_n, seq)
+            .await?
+            .map(|kv| kv.map(Box::new))
+            .collect::<Result<Vec<_>, _>>()?;
         if results.is_empty() {
             Err(LsmError::KeyNotFound)
         } else {
@@ -252,6 +1073,15 @@ impl LsmDatabase {
     }
 }
 
+// the smallest key past every key that starts with `prefix`, `None` when
+// `prefix` is all `0xff` bytes
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != 0xff)?;
+    let mut upper = prefix[..=last].to_vec();
+    upper[last] += 1;
+    Some(upper)
+}
+
 impl Clone for LsmDatabase {
     fn clone(&self) -> Self {
         Self {
@@ -261,6 +1091,1068 @@ impl Clone for LsmDatabase {
             capacity_expansion_factor: self.capacity_expansion_factor,
             base_fpr: self.base_fpr,
             max_memtables: self.max_memtables,
+            level0_slowdown_tables: self.level0_slowdown_tables,
+            level0_stop_tables: self.level0_stop_tables,
+            wal: Arc::clone(&self.wal),
+            manifest: Arc::clone(&self.manifest),
+            last_sequence: Arc::clone(&self.last_sequence),
+            snapshots: Arc::clone(&self.snapshots),
+            comparator: Arc::clone(&self.comparator),
+            block_cache: Arc::clone(&self.block_cache),
+            row_cache: Arc::clone(&self.row_cache),
+            table_cache: Arc::clone(&self.table_cache),
+            use_mmap: self.use_mmap,
+            filter_policies: self.filter_policies.clone(),
+            range_filters: self.range_filters,
+            prefix_extractor: self.prefix_extractor.clone(),
+            index_partition_blocks: self.index_partition_blocks,
+            learned_index: self.learned_index,
+            memtable_builder: self.memtable_builder.clone(),
+            write_buffer: Arc::clone(&self.write_buffer),
+            flush_queue: Arc::clone(&self.flush_queue),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_wal_replay_on_open() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..10 {
+            db.put(format!("key-{}", i), format!("value-{}", i)).await?;
+        }
+        db.delete("key-3").await?;
+        drop(db);
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert_eq!(db.get("key-7").await?.value_str(), Some("value-7"));
+        assert!(matches!(
+            db.get("key-3").await,
+            Err(LsmError::KeyNotFound)
+        ));
+
+        // writes after a reopen land in the same segment
+        db.put("key-10", "value-10").await?;
+        drop(db);
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert_eq!(db.get("key-10").await?.value_str(), Some("value-10"));
+        assert_eq!(WalSegment::list(temp_dir.path())?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_empty_key_is_rejected() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert!(matches!(db.put("", "value").await, Err(LsmError::InvalidArgument(_))));
+        assert!(matches!(db.delete("").await, Err(LsmError::InvalidArgument(_))));
+
+        // nothing of it reached the log, so flushes and replays still work
+        for i in 0..1000 {
+            db.put(format!("key-{:04}", i), "value").await?;
+        }
+        db.wait_for_flushes().await?;
+        drop(db);
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        db.put("key-1000", "value").await?;
+        assert_eq!(db.get("key-0999").await?.value_str(), Some("value"));
+        assert_eq!(db.get("key-1000").await?.value_str(), Some("value"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlong_entries_are_rejected() {
+        // checked on lengths alone, before anything is copied or logged
+        let rejected = LsmDatabase::check_entry(b"key", Some(MAX_VALUE_LEN + 1));
+        assert!(matches!(rejected, Err(LsmError::InvalidArgument(_))));
+        assert!(LsmDatabase::check_entry(b"key", Some(MAX_VALUE_LEN)).is_ok());
+        assert!(LsmDatabase::check_entry(b"key", None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_wal_segment_deleted_after_flush() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..1000 {
+            db.put(format!("key-{:04}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+
+        let segments = WalSegment::list(temp_dir.path())?;
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].memtable_id, db.memtables.load()[0].0);
+        assert_eq!(db.levels.read().await[0].inner.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_flushed_segment_is_not_replayed() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let backup = temp_dir.path().join("segment.bak");
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        db.put("key-a", "old").await?;
+        for i in 0..998 {
+            db.put(format!("fill-{:04}", i), "x").await?;
+        }
+        // keep the segment as it was just before its memtable filled up
+        let segment = db.wal.lock().await.segment().clone();
+        std::fs::copy(&segment.path, &backup)?;
+        db.put("fill-0998", "x").await?;
+
+        // the second flush compacts both tables below level 0
+        db.put("key-a", "new").await?;
+        for i in 0..999 {
+            db.put(format!("more-{:04}", i), "x").await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(!segment.path.exists());
+        assert!(db.levels.read().await[0].inner.is_empty());
+        drop(db);
+
+        // as if the process died after the flush but before the segment was
+        // removed
+        std::fs::rename(&backup, &segment.path)?;
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert_eq!(db.get("key-a").await?.value_str(), Some("new"));
+        assert_eq!(db.multi_get(&["key-a"]).await[0].as_ref().unwrap().value_str(), Some("new"));
+        assert!(!segment.path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_restores_levels() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..5500 {
+            db.put(format!("key-{:05}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        let before: Vec<(usize, usize, Vec<String>)> = db
+            .levels
+            .read()
+            .await
+            .iter()
+            .map(|level| {
+                (
+                    level.depth,
+                    level.width,
+                    level
+                        .inner
+                        .iter()
+                        .map(|table| LsmDatabase::table_file_name(table))
+                        .collect(),
+                )
+            })
+            .collect();
+        assert!(before.len() > 1);
+        drop(db);
+
+        // a stray table that was never installed gets cleaned up
+        let orphan = temp_dir.path().join(format!("{}{}", TABLE_PREFIX, Uuid::new_v4()));
+        std::fs::write(&orphan, b"SSTB")?;
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        let after: Vec<(usize, usize, Vec<String>)> = db
+            .levels
+            .read()
+            .await
+            .iter()
+            .map(|level| {
+                (
+                    level.depth,
+                    level.width,
+                    level
+                        .inner
+                        .iter()
+                        .map(|table| LsmDatabase::table_file_name(table))
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(before, after);
+        assert!(!orphan.exists());
+
+        for i in (0..5500).step_by(97) {
+            let key = format!("key-{:05}", i);
+            assert_eq!(
+                db.get(key).await?.value_str(),
+                Some(format!("value-{}", i).as_str())
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_without_manifest_keeps_tables() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..1500 {
+            db.put(format!("key-{:04}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        drop(db);
+        let tables = LsmDatabase::table_files(temp_dir.path())?;
+        assert!(!tables.is_empty());
+
+        // tables the manifest can't account for are not taken for orphans
+        let manifest = temp_dir.path().join(MANIFEST_FILE);
+        let moved = temp_dir.path().join("MANIFEST.moved");
+        std::fs::rename(&manifest, &moved)?;
+        assert!(matches!(
+            LsmDatabase::open(temp_dir.path(), None).await,
+            Err(LsmError::Other(_))
+        ));
+        assert_eq!(LsmDatabase::table_files(temp_dir.path())?, tables);
+
+        std::fs::rename(&moved, &manifest)?;
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert_eq!(db.get("key-0007").await?.value_str(), Some("value-7"));
+        assert_eq!(db.get("key-1499").await?.value_str(), Some("value-1499"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_reports_corrupted_table() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..1000 {
+            db.put(format!("key-{:04}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        let table_path = db.levels.read().await[0].inner[0].file_path().to_path_buf();
+
+        // flip a bit inside the first entry of the first data block
+        let mut contents = std::fs::read(&table_path)?;
+        contents[8] ^= 0x01;
+        std::fs::write(&table_path, &contents)?;
+
+        assert!(matches!(
+            db.get("key-0000").await,
+            Err(LsmError::SSTable(SSTableError::Corruption { block: 0, .. }))
+        ));
+        // a key asked for twice reports the same error twice
+        for result in db.multi_get(&["key-0000", "key-0000"]).await {
+            assert!(matches!(
+                result,
+                Err(LsmError::SSTable(SSTableError::Corruption { block: 0, .. }))
+            ));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_shadow_older_values() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..1000 {
+            db.put(format!("key-{:04}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        assert_eq!(db.levels.read().await[0].inner.len(), 1);
+
+        // the old sentinel is just another value now
+        db.put("key-0001", "d34db33f").await?;
+        db.delete("key-0005").await?;
+        assert_eq!(db.get("key-0001").await?.value_str(), Some("d34db33f"));
+        assert!(matches!(
+            db.get("key-0005").await,
+            Err(LsmError::KeyNotFound)
+        ));
+
+        // flush the tombstone into a table on top of the one holding the value
+        for i in 0..998 {
+            db.put(format!("other-{:04}", i), "x").await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.memtables.load()[0].1.current_length() < 1000);
+
+        assert_eq!(db.get("key-0001").await?.value_str(), Some("d34db33f"));
+        assert!(matches!(
+            db.get("key-0005").await,
+            Err(LsmError::KeyNotFound)
+        ));
+        let keys: Vec<Arc<[u8]>> = db
+            .range("key-0004", "key-0006")
+            .await?
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect();
+        assert_eq!(keys, vec![Arc::from(&b"key-0004"[..]), Arc::from(&b"key-0006"[..])]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_get_matches_single_gets() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..1000 {
+            db.put(format!("key-{:04}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        assert_eq!(db.levels.read().await[0].inner.len(), 1);
+        let snapshot = db.snapshot().await;
+
+        db.put("key-0001", "new").await?;
+        db.delete("key-0005").await?;
+        db.put("key-2000", "fresh").await?;
+
+        // unsorted, with a duplicate and a key that was never written
+        let keys = ["key-2000", "key-0500", "key-0005", "nope", "key-0001", "key-0500"];
+        let values: Vec<Option<String>> = db
+            .multi_get(&keys)
+            .await
+            .into_iter()
+            .map(|result| match result {
+                Ok(kv) => Some(kv.value_str().unwrap().to_string()),
+                Err(LsmError::KeyNotFound) => None,
+                Err(e) => panic!("unexpected error: {e}"),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                Some("fresh".to_string()),
+                Some("value-500".to_string()),
+                None,
+                None,
+                Some("new".to_string()),
+                Some("value-500".to_string()),
+            ]
+        );
+        for (key, value) in keys.iter().zip(&values) {
+            assert_eq!(db.get(key).await.ok().map(|kv| kv.value_str().unwrap().to_string()), *value);
+        }
+
+        let old = db.multi_get_at(&["key-0001", "key-0005", "key-2000"], &snapshot).await;
+        assert_eq!(old[0].as_ref().unwrap().value_str(), Some("value-1"));
+        assert_eq!(old[1].as_ref().unwrap().value_str(), Some("value-5"));
+        assert!(matches!(old[2], Err(LsmError::KeyNotFound)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sequence_numbers_survive_reopen() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..1000 {
+            db.put(format!("key-{:04}", i), "old").await?;
+        }
+        db.put("key-0042", "new").await?;
+        assert_eq!(db.last_sequence.load(Ordering::SeqCst), 1001);
+        assert_eq!(db.get("key-0042").await?.seq, 1001);
+        db.wait_for_flushes().await?;
+        drop(db);
+
+        // one flushed table plus a log segment to replay
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert_eq!(db.last_sequence.load(Ordering::SeqCst), 1001);
+        db.put("key-0042", "newer").await?;
+        let kv = db.get("key-0042").await?;
+        assert_eq!((kv.value_str(), kv.seq), (Some("newer"), 1002));
+        assert_eq!(db.get("key-0007").await?.seq, 8);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_survives_flush_and_compaction() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        db.put("key-a", "v1").await?;
+        db.put("key-b", "v1").await?;
+        let snapshot = db.snapshot().await;
+        db.put("key-a", "v2").await?;
+        db.delete("key-b").await?;
+        db.put("key-c", "v1").await?;
+
+        // two flushes fill level 0 and compact it into a new bottom level
+        for i in 0..2000 {
+            db.put(format!("other-{:04}", i), "x").await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.len() > 1);
+
+        assert_eq!(db.get_at("key-a", &snapshot).await?.value_str(), Some("v1"));
+        assert_eq!(db.get_at("key-b", &snapshot).await?.value_str(), Some("v1"));
+        assert!(matches!(
+            db.get_at("key-c", &snapshot).await,
+            Err(LsmError::KeyNotFound)
+        ));
+        assert_eq!(db.get("key-a").await?.value_str(), Some("v2"));
+        assert!(matches!(db.get("key-b").await, Err(LsmError::KeyNotFound)));
+
+        let seen: Vec<String> = db
+            .range_at("key-a", "key-c", &snapshot)
+            .await?
+            .iter()
+            .map(|kv| format!("{}={}", kv.key_str().unwrap(), kv.value_str().unwrap()))
+            .collect();
+        assert_eq!(seen, vec!["key-a=v1", "key-b=v1"]);
+        let latest: Vec<String> = db
+            .range("key-a", "key-c")
+            .await?
+            .iter()
+            .map(|kv| format!("{}={}", kv.key_str().unwrap(), kv.value_str().unwrap()))
+            .collect();
+        assert_eq!(latest, vec!["key-a=v2", "key-c=v1"]);
+
+        drop(snapshot);
+        assert!(db.snapshots.sequences().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_range_merges_levels_and_applies_tombstones() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..10 {
+            db.put(format!("key-{}", i), "old").await?;
+        }
+        // push the first versions down into the tables
+        for i in 0..2000 {
+            db.put(format!("other-{:04}", i), "x").await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.len() > 1);
+
+        db.put("key-2", "new").await?;
+        db.delete("key-4").await?;
+        db.delete("key-5").await?;
+        db.put("key-5", "again").await?;
+
+        let seen: Vec<String> = db
+            .range("key-1", "key-6")
+            .await?
+            .iter()
+            .map(|kv| format!("{}={}", kv.key_str().unwrap(), kv.value_str().unwrap()))
+            .collect();
+        assert_eq!(
+            seen,
+            vec!["key-1=old", "key-2=new", "key-3=old", "key-5=again", "key-6=old"]
+        );
+
+        let mut iter = db.iter("other-1998", "zzz").await?;
+        assert_eq!(iter.next().unwrap()?.key_str(), Some("other-1998"));
+        assert_eq!(iter.next().unwrap()?.key_str(), Some("other-1999"));
+        assert!(iter.next().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_iterator_seeks_and_moves_both_ways() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        for i in 0..2000 {
+            db.put(format!("key-{:04}", i), "old").await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.iter().any(|level| !level.inner.is_empty()));
+        let snapshot = db.snapshot().await;
+        for i in (1000..1010).step_by(2) {
+            db.delete(format!("key-{:04}", i)).await?;
+        }
+        db.put("key-1003", "new").await?;
+
+        let key = |kv: Option<Result<KeyValue, LsmError>>| {
+            kv.map(|kv| kv.unwrap().key_str().unwrap().to_string())
+        };
+        let mut iter = db.iter("key-0500", "key-1500").await?;
+
+        // the latest three entries before key-1006
+        iter.seek_for_prev("key-1006");
+        let latest: Vec<_> = (0..3).filter_map(|_| key(iter.prev())).collect();
+        assert_eq!(latest, vec!["key-1005", "key-1003", "key-1001"]);
+
+        // turning around returns the entry just passed
+        assert_eq!(key(iter.next()).as_deref(), Some("key-1001"));
+        assert_eq!(iter.next().unwrap()?.value_str(), Some("new"));
+        assert_eq!(key(iter.prev()).as_deref(), Some("key-1003"));
+
+        iter.seek("key-1004");
+        assert_eq!(key(iter.next()).as_deref(), Some("key-1005"));
+        iter.seek("key-0000");
+        assert_eq!(key(iter.next()).as_deref(), Some("key-0500"));
+        assert_eq!(key(iter.prev()).as_deref(), Some("key-0500"));
+        assert!(iter.prev().is_none());
+        iter.seek_to_last();
+        assert_eq!(key(iter.prev()).as_deref(), Some("key-1500"));
+        assert_eq!(key(iter.next()).as_deref(), Some("key-1500"));
+        assert!(iter.next().is_none());
+
+        let mut iter = db.iter_at("key-1000", "key-1009", &snapshot).await?;
+        iter.seek_to_last();
+        let mut backwards = Vec::new();
+        while let Some(kv) = key(iter.prev()) {
+            backwards.push(kv);
+        }
+        assert_eq!(backwards.len(), 10);
+        assert_eq!(backwards.first().map(String::as_str), Some("key-1009"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mmap_reads_survive_reopen() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = || DbOptions {
+            use_mmap: true,
+            table_cache_capacity: 2,
+            ..DbOptions::default()
+        };
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        for i in 0..2500 {
+            db.put(format!("key-{:04}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.iter().any(|level| !level.inner.is_empty()));
+        assert_eq!(db.get("key-0042").await?.value_str(), Some("value-42"));
+        drop(db);
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        assert_eq!(db.get("key-1999").await?.value_str(), Some("value-1999"));
+        assert_eq!(db.range("key-0100", "key-0199").await?.len(), 100);
+        assert_eq!(db.block_cache.stats().usage, 0);
+        assert_eq!(db.table_cache.stats().open_files, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_indexes_survive_reopen() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = || DbOptions {
+            index_partition_blocks: Some(2),
+            ..DbOptions::default()
+        };
+        // every table with more than one partition's worth of blocks, and
+        // there is at least one
+        let partitioned = |levels: &[Level]| {
+            let tables = || levels.iter().flat_map(|level| level.inner.iter());
+            tables().all(|table| table.block_count() <= 2 || table.index_partitions() > 1)
+                && tables().any(|table| table.index_partitions() > 1)
+        };
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        for i in 0..5500 {
+            db.put(format!("key-{:05}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.iter().any(|level| !level.inner.is_empty()));
+        assert!(partitioned(&db.levels.read().await));
+        drop(db);
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        assert!(partitioned(&db.levels.read().await));
+        for i in (0..5500).step_by(250) {
+            let key = format!("key-{:05}", i);
+            assert_eq!(db.get(&key).await?.value_str(), Some(format!("value-{}", i).as_str()));
+        }
+        assert_eq!(db.range("key-01000", "key-01999").await?.len(), 1000);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_learned_index_lookups() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = || DbOptions {
+            learned_index: Some(4),
+            ..DbOptions::default()
+        };
+        let key = |i: u64| (i * 7919).to_be_bytes();
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        for i in 0..5500 {
+            db.put(key(i), i.to_string()).await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.iter().any(|level| !level.inner.is_empty()));
+        drop(db);
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        for i in (0..5500).step_by(97) {
+            assert_eq!(db.get(key(i)).await?.value_str(), Some(i.to_string().as_str()));
+            assert!(matches!(db.get((i * 7919 + 1).to_be_bytes()).await, Err(LsmError::KeyNotFound)));
+        }
+        assert_eq!(db.range(key(1000), key(1099)).await?.len(), 100);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_row_cache_follows_writes() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = DbOptions {
+            row_cache_capacity: 1 << 20,
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options).await?;
+        let fill = |from: usize| {
+            let db = &db;
+            async move {
+                // enough writes to flush the memtable and compact
+                for i in from..from + 2500 {
+                    db.put(format!("key-{:05}", i), format!("value-{}", i)).await?;
+                }
+                db.wait_for_flushes().await?;
+                Ok::<_, LsmError>(())
+            }
+        };
+        fill(0).await?;
+        assert_eq!(db.row_cache.capacity(), 1 << 20);
+
+        let block_reads = || {
+            let stats = db.block_cache.stats();
+            stats.hits + stats.misses
+        };
+        assert_eq!(db.get("key-00042").await?.value_str(), Some("value-42"));
+        let (reads, hits) = (block_reads(), db.row_cache.stats().hits);
+        assert_eq!(db.get("key-00042").await?.value_str(), Some("value-42"));
+        assert_eq!(db.row_cache.stats().hits, hits + 1);
+        assert_eq!(block_reads(), reads);
+
+        let before = db.snapshot().await;
+        db.put("key-00042", "new").await?;
+        assert_eq!(db.get("key-00042").await?.value_str(), Some("new"));
+        fill(10_000).await?;
+        assert_eq!(db.get("key-00042").await?.value_str(), Some("new"));
+        assert_eq!(db.get("key-00042").await?.value_str(), Some("new"));
+        // snapshot reads never use the cache
+        assert_eq!(db.get_at("key-00042", &before).await?.value_str(), Some("value-42"));
+        drop(before);
+
+        db.delete("key-00042").await?;
+        fill(20_000).await?;
+        assert!(matches!(db.get("key-00042").await, Err(LsmError::KeyNotFound)));
+        assert!(matches!(db.get("key-00042").await, Err(LsmError::KeyNotFound)));
+
+        // multi_get fills the cache and is served from it like get
+        let found = db.multi_get(&["key-00042", "key-00100", "key-00100"]).await;
+        assert!(matches!(found[0], Err(LsmError::KeyNotFound)));
+        assert_eq!(found[2].as_ref().unwrap().value_str(), Some("value-100"));
+        let hits = db.row_cache.stats().hits;
+        assert_eq!(db.get("key-00100").await?.value_str(), Some("value-100"));
+        let found = db.multi_get(&["key-00100", "key-00101"]).await;
+        assert_eq!(found[0].as_ref().unwrap().value_str(), Some("value-100"));
+        assert_eq!(db.row_cache.stats().hits, hits + 2);
+        assert!(db.row_cache.stats().usage > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_every_memtable_type() -> Result<(), LsmError> {
+        use crate::MemTableType;
+
+        for memtable_type in [
+            MemTableType::Vector,
+            MemTableType::SkipList,
+            MemTableType::ConcurrentHashmap,
+            MemTableType::ArenaSkipList,
+        ] {
+            let temp_dir = tempdir().unwrap();
+            let options = || DbOptions {
+                memtable_type,
+                ..DbOptions::default()
+            };
+
+            let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+            for i in 0..2500 {
+                db.put(format!("key-{:05}", i), format!("value-{}", i)).await?;
+            }
+            db.wait_for_flushes().await?;
+            let before = db.snapshot().await;
+            db.put("key-02400", "new").await?;
+            db.delete("key-02401").await?;
+            assert_eq!(db.get("key-02400").await?.value_str(), Some("new"));
+            assert_eq!(db.get_at("key-02400", &before).await?.value_str(), Some("value-2400"));
+            assert!(matches!(db.get("key-02401").await, Err(LsmError::KeyNotFound)));
+            // the range spans flushed tables and the active memtable
+            let found = db.range("key-01990", "key-02409").await?;
+            assert_eq!(found.len(), 419, "{:?}", memtable_type);
+            assert_eq!(found[410].value_str(), Some("new"));
+            db.wait_for_flushes().await?;
+            drop(before);
+            drop(db);
+
+            let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+            assert_eq!(db.get("key-02400").await?.value_str(), Some("new"));
+            assert!(matches!(db.get("key-02401").await, Err(LsmError::KeyNotFound)));
+            assert_eq!(db.get("key-00007").await?.value_str(), Some("value-7"));
         }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memtables_flush_by_size() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = DbOptions {
+            write_buffer_size: 64 << 10,
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options).await?;
+        let value = "x".repeat(4096);
+        for i in 0..40 {
+            db.put(format!("key-{:05}", i), &value).await?;
+            let usage = db.memtables.load()[0].1.memory_usage();
+            assert!(usage < 64 << 10);
+            assert_eq!(db.write_buffer_manager().mutable_memory_usage(), usage);
+        }
+        db.wait_for_flushes().await?;
+        assert_eq!(db.write_buffer_manager().memory_usage(), 8 * (9 + 4096 + 48));
+        // far short of the entry limit, every 16 entries were flushed
+        let flushed: usize = db
+            .levels
+            .read()
+            .await
+            .iter()
+            .flat_map(|level| level.inner.iter())
+            .map(|table| table.actual_item_count)
+            .sum();
+        assert_eq!(flushed, 32);
+        assert_eq!(db.memtables.load()[0].1.current_length(), 8);
+        assert_eq!(db.get("key-00003").await?.value_str(), Some(value.as_str()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_buffer_manager_caps_memtables() -> Result<(), LsmError> {
+        let manager = Arc::new(WriteBufferManager::new(32 << 10));
+        let options = || DbOptions {
+            write_buffer_manager: Some(manager.clone()),
+            ..DbOptions::default()
+        };
+        let (first_dir, second_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let first = LsmDatabase::open_with_options(first_dir.path(), None, options()).await?;
+        let second = LsmDatabase::open_with_options(second_dir.path(), None, options()).await?;
+
+        let value = "x".repeat(1024);
+        for i in 0..100 {
+            let db = if i % 3 == 0 { &first } else { &second };
+            db.put(format!("key-{:05}", i), &value).await?;
+            // the write that reaches the cap flushes, so only one entry
+            // ever goes past it
+            assert!(manager.mutable_memory_usage() < (32 << 10) + 2048);
+        }
+        for db in [&first, &second] {
+            db.wait_for_flushes().await?;
+            let levels = db.levels.read().await;
+            assert!(levels.iter().any(|level| !level.inner.is_empty()));
+        }
+        assert_eq!(first.get("key-00099").await?.value_str(), Some(value.as_str()));
+        assert_eq!(second.get("key-00098").await?.value_str(), Some(value.as_str()));
+        assert!(manager.memory_usage() > 0);
+        assert!(manager.memory_usage() < 32 << 10);
+
+        // closing the databases hands their memtables' memory back
+        drop(first);
+        drop(second);
+        assert_eq!(manager.memory_usage(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_writes_stall_while_flushes_catch_up() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = DbOptions {
+            write_buffer_size: 16 << 10,
+            max_memtables: 1,
+            level0_slowdown_tables: 1,
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options).await?;
+        let value = "x".repeat(1024);
+        for i in 0..100 {
+            db.put(format!("key-{:05}", i), &value).await?;
+            assert!(db.pending_flushes() <= 1);
+            assert!(db.memtables.load().len() <= 2);
+        }
+        // a second full memtable waits for the first to flush, and a table
+        // in level 0 delays writes until it is compacted
+        let stalls = db.stall_stats();
+        assert!(stalls.stops > 0, "{:?}", stalls);
+        assert!(stalls.slowdowns > 0, "{:?}", stalls);
+        assert!(stalls.stall_micros >= stalls.slowdowns * 1000);
+
+        db.wait_for_flushes().await?;
+        assert_eq!(db.pending_flushes(), 0);
+        assert_eq!(db.memtables.load().len(), 1);
+        assert_eq!(db.range("key-00000", "key-00099").await?.len(), 100);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_is_retried() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let (path, moved) = (temp_dir.path().join("db"), temp_dir.path().join("moved"));
+        let options = DbOptions {
+            max_memtable_entries: 50,
+            max_memtables: 1,
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(&path, None, options.clone()).await?;
+        let mut written = 0;
+        while db.pending_flushes() == 0 {
+            db.put(format!("key-{:03}", written), "v").await?;
+            written += 1;
+        }
+        // with a file where its directory was, the flush can't write a table
+        std::fs::rename(&path, &moved).unwrap();
+        std::fs::write(&path, b"").unwrap();
+        assert!(db.wait_for_flushes().await.is_err());
+        // a write that would wait for the failing flush gets its error
+        assert!(db.put("key-999", "v").await.is_err());
+        assert_eq!(db.pending_flushes(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::rename(&moved, &path).unwrap();
+        while db.wait_for_flushes().await.is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(db.pending_flushes(), 0);
+        db.put("key-999", "v").await?;
+        drop(db);
+
+        let db = LsmDatabase::open_with_options(&path, None, options).await?;
+        assert_eq!(db.range("key-000", "key-999").await?.len(), written + 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_reads_see_writes_across_memtable_rotations() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = DbOptions {
+            max_memtable_entries: 50,
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options).await?;
+
+        // every writer reads back what it just wrote while the others keep
+        // rotating and flushing memtables under it
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for i in 0..250 {
+                        let key = format!("key-{}-{:03}", writer, i);
+                        db.put(&key, format!("v{}", i)).await?;
+                        let value = format!("v{}", i);
+                        assert_eq!(db.get(&key).await?.value_str(), Some(value.as_str()));
+                        let first = format!("key-{}-000", writer);
+                        assert_eq!(db.range(&first, &key).await?.len(), i + 1);
+                    }
+                    Ok::<_, LsmError>(())
+                })
+            })
+            .collect();
+        let snapshot = loop {
+            let snapshot = db.snapshot().await;
+            if snapshot.sequence() >= 500 {
+                break snapshot;
+            }
+            tokio::task::yield_now().await;
+        };
+        for writer in writers {
+            writer.await.unwrap()?;
+        }
+
+        // a snapshot sees exactly the writes published before it
+        let seen = db.range_at("key-0", "key-9", &snapshot).await?.len();
+        assert_eq!(seen as u64, snapshot.sequence());
+        assert_eq!(db.range("key-0", "key-9").await?.len(), 1000);
+        db.wait_for_flushes().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_policy_per_level() -> Result<(), LsmError> {
+        use sstable::filter::{RibbonPolicy, XorPolicy};
+
+        let temp_dir = tempdir().unwrap();
+        let options = || DbOptions {
+            filter_policies: vec![Arc::new(RibbonPolicy), Arc::new(XorPolicy)],
+            ..DbOptions::default()
+        };
+        let policies = |levels: &[Level]| -> Vec<(usize, Option<u8>)> {
+            levels
+                .iter()
+                .flat_map(|level| {
+                    level
+                        .inner
+                        .iter()
+                        .map(|table| (level.depth, table.filter().map(|f| f.policy_id())))
+                })
+                .collect()
+        };
+
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options()).await?;
+        for i in 0..5500 {
+            db.put(format!("key-{:05}", i), format!("value-{}", i)).await?;
+        }
+        db.wait_for_flushes().await?;
+        let before = policies(&db.levels.read().await);
+        // tables of the last level are written without a filter
+        for &(depth, policy) in &before {
+            match depth {
+                0 => assert_eq!(policy, Some(RibbonPolicy.id())),
+                _ => assert!(policy.is_none() || policy == Some(XorPolicy.id())),
+            }
+        }
+        assert!(before.iter().any(|&(_, policy)| policy == Some(XorPolicy.id())));
+        drop(db);
+
+        // the policy is read back from each table, whatever the options say
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        assert_eq!(policies(&db.levels.read().await), before);
+        for i in (0..5500).step_by(250) {
+            let key = format!("key-{:05}", i);
+            assert_eq!(db.get(&key).await?.value_str(), Some(format!("value-{}", i).as_str()));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_range_scans_skip_tables() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let options = DbOptions {
+            range_filters: true,
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options).await?;
+        let key = |i: u64| (i * 1000).to_be_bytes();
+        for i in 1..=5500 {
+            db.put(key(i), i.to_string()).await?;
+        }
+        db.wait_for_flushes().await?;
+        let reads = || {
+            let stats = db.block_cache.stats();
+            stats.hits + stats.misses
+        };
+
+        // past either end of every table, or in the gaps between keys
+        let before = reads();
+        assert!(matches!(db.range(key(0), key(0)).await, Err(LsmError::KeyNotFound)));
+        assert!(matches!(db.range(key(6000), key(7000)).await, Err(LsmError::KeyNotFound)));
+        assert_eq!(reads(), before);
+        let mut matched = 0;
+        for i in (1..5000u64).step_by(50) {
+            let before = reads();
+            let gap = db.range((i * 1000 + 100).to_be_bytes(), (i * 1000 + 900).to_be_bytes());
+            assert!(matches!(gap.await, Err(LsmError::KeyNotFound)));
+            matched += (reads() != before) as usize;
+        }
+        assert!(matched < 10, "{} of 100 gap scans read a block", matched);
+
+        let found = db.range(key(100), key(120)).await?;
+        assert_eq!(found.len(), 21);
+        assert_eq!(found[0].value_str(), Some("100"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prefix_scan() -> Result<(), LsmError> {
+        use sstable::prefix::DelimitedPrefix;
+
+        let temp_dir = tempdir().unwrap();
+        let options = DbOptions {
+            prefix_extractor: Some(Arc::new(DelimitedPrefix::new(b':', 2))),
+            ..DbOptions::default()
+        };
+        let db = LsmDatabase::open_with_options(temp_dir.path(), None, options).await?;
+        let key = |tenant: usize, object: usize, field: usize| {
+            format!("t{:02}:o{:03}:f{}", tenant, object, field)
+        };
+        // only even objects exist
+        for tenant in 0..10 {
+            for object in (0..200).step_by(2) {
+                for field in 0..3 {
+                    db.put(key(tenant, object, field), "old").await?;
+                }
+            }
+        }
+        db.wait_for_flushes().await?;
+        db.put(key(4, 20, 1), "new").await?;
+        db.delete(key(4, 20, 2)).await?;
+
+        let found = db.prefix_scan("t04:o020:").await?;
+        let found: Vec<_> = found
+            .iter()
+            .map(|kv| (kv.key.to_vec(), kv.value_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (key(4, 20, 0).into_bytes(), "old".to_string()),
+                (key(4, 20, 1).into_bytes(), "new".to_string()),
+            ]
+        );
+        assert_eq!(db.prefix_scan("t07:").await?.len(), 300);
+        assert_eq!(db.prefix_scan("t07:o19").await?.len(), 15);
+
+        // absent prefixes are mostly ruled out without reading a block
+        let reads = || {
+            let stats = db.block_cache.stats();
+            stats.hits + stats.misses
+        };
+        let mut matched = 0;
+        for object in (1..200).step_by(2) {
+            let before = reads();
+            let prefix = format!("t05:o{:03}:", object);
+            assert!(matches!(db.prefix_scan(prefix).await, Err(LsmError::KeyNotFound)));
+            matched += (reads() != before) as usize;
+        }
+        assert!(matched < 10, "{} of 100 absent prefixes read a block", matched);
+
+        // prefixes without a successor have no upper bound
+        assert_eq!(db.prefix_scan("").await?.len(), 2999);
+        db.put([0xff, 0xff], "a").await?;
+        db.put([0xff, 0xff, 0x00, 0x01], "b").await?;
+        db.put([0xff, 0xfe], "c").await?;
+        let found = db.prefix_scan([0xff, 0xff]).await?;
+        assert_eq!(
+            found.iter().map(|kv| kv.key.to_vec()).collect::<Vec<_>>(),
+            vec![vec![0xff, 0xff], vec![0xff, 0xff, 0x00, 0x01]]
+        );
+        assert_eq!(db.prefix_scan("").await?.len(), 3002);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_i64_keys_range_in_numeric_order() -> Result<(), LsmError> {
+        use key_value::comparator::{decode_i64, encode_i64};
+
+        let temp_dir = tempdir().unwrap();
+
+        let db = LsmDatabase::open(temp_dir.path(), None).await?;
+        // enough to land keys in tables as well as the memtable
+        for i in -1200i64..1200 {
+            db.put(encode_i64(i * 7), i.to_string()).await?;
+        }
+        db.wait_for_flushes().await?;
+        assert!(db.levels.read().await.iter().any(|level| !level.inner.is_empty()));
+
+        let keys: Vec<i64> = db
+            .range(encode_i64(-21), encode_i64(70))
+            .await?
+            .iter()
+            .map(|kv| decode_i64(&kv.key).unwrap())
+            .collect();
+        assert_eq!(keys, (-3..=10).map(|i| i * 7).collect::<Vec<_>>());
+        assert_eq!(db.get(encode_i64(-8393)).await?.value_str(), Some("-1199"));
+        Ok(())
     }
 }
@@ -0,0 +1,276 @@
+use integer_encoding::{VarInt, VarIntReader};
+use sstable::sync_dir;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::LsmError,
+    log_record::{read_records, write_record},
+};
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// A single change to the shape of the tree. Edits that belong together (a
+/// compaction removing its inputs and adding its output) are written as one
+/// record, so they are either all replayed or not at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestEdit {
+    AddLevel { depth: usize, width: usize },
+    AddTable { level: usize, file_name: String },
+    RemoveTable { level: usize, file_name: String },
+}
+
+impl ManifestEdit {
+    const ADD_LEVEL: u8 = 1;
+    const ADD_TABLE: u8 = 2;
+    const REMOVE_TABLE: u8 = 3;
+
+    fn encode_batch(edits: &[ManifestEdit]) -> Vec<u8> {
+        let mut payload = edits.len().encode_var_vec();
+        for edit in edits {
+            match edit {
+                ManifestEdit::AddLevel { depth, width } => {
+                    payload.push(Self::ADD_LEVEL);
+                    payload.extend_from_slice(&depth.encode_var_vec());
+                    payload.extend_from_slice(&width.encode_var_vec());
+                }
+                ManifestEdit::AddTable { level, file_name } => {
+                    payload.push(Self::ADD_TABLE);
+                    payload.extend_from_slice(&level.encode_var_vec());
+                    payload.extend_from_slice(&file_name.len().encode_var_vec());
+                    payload.extend_from_slice(file_name.as_bytes());
+                }
+                ManifestEdit::RemoveTable { level, file_name } => {
+                    payload.push(Self::REMOVE_TABLE);
+                    payload.extend_from_slice(&level.encode_var_vec());
+                    payload.extend_from_slice(&file_name.len().encode_var_vec());
+                    payload.extend_from_slice(file_name.as_bytes());
+                }
+            }
+        }
+        payload
+    }
+
+    fn decode_batch(payload: &[u8]) -> Option<Vec<ManifestEdit>> {
+        let mut cursor = Cursor::new(payload);
+        let count: usize = cursor.read_varint().ok()?;
+        let mut edits = Vec::with_capacity(count);
+
+        let read_name = |cursor: &mut Cursor<&[u8]>| -> Option<String> {
+            let len: usize = cursor.read_varint().ok()?;
+            let mut name = vec![0u8; len];
+            cursor.read_exact(&mut name).ok()?;
+            String::from_utf8(name).ok()
+        };
+
+        for _ in 0..count {
+            let mut tag = [0u8; 1];
+            cursor.read_exact(&mut tag).ok()?;
+            let edit = match tag[0] {
+                Self::ADD_LEVEL => ManifestEdit::AddLevel {
+                    depth: cursor.read_varint().ok()?,
+                    width: cursor.read_varint().ok()?,
+                },
+                Self::ADD_TABLE => ManifestEdit::AddTable {
+                    level: cursor.read_varint().ok()?,
+                    file_name: read_name(&mut cursor)?,
+                },
+                Self::REMOVE_TABLE => ManifestEdit::RemoveTable {
+                    level: cursor.read_varint().ok()?,
+                    file_name: read_name(&mut cursor)?,
+                },
+                _ => return None,
+            };
+            edits.push(edit);
+        }
+        Some(edits)
+    }
+}
+
+/// The layout of one level as recorded in the manifest. Table names are
+/// relative to the data directory and kept in insertion order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelLayout {
+    pub depth: usize,
+    pub width: usize,
+    pub tables: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Manifest {
+    writer: BufWriter<File>,
+}
+
+impl Manifest {
+    /// Replays the manifest in `dir` into the level layout it describes.
+    /// Returns `None` if the directory has no manifest yet.
+    pub fn recover(dir: &Path) -> Result<Option<Vec<LevelLayout>>, LsmError> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read(&path)?;
+        let (batches, valid_len) = read_records(&data, ManifestEdit::decode_batch);
+        if valid_len < data.len() {
+            log::warn!(
+                "Discarding {} trailing bytes of {}",
+                data.len() - valid_len,
+                path.display()
+            );
+        }
+
+        let mut levels: Vec<LevelLayout> = Vec::new();
+        for edit in batches.into_iter().flatten() {
+            match edit {
+                ManifestEdit::AddLevel { depth, width } => {
+                    if depth != levels.len() {
+                        return Err(LsmError::Other(format!(
+                            "manifest adds level {} after {} levels",
+                            depth,
+                            levels.len()
+                        )));
+                    }
+                    levels.push(LevelLayout {
+                        depth,
+                        width,
+                        tables: Vec::new(),
+                    });
+                }
+                ManifestEdit::AddTable { level, file_name } => {
+                    let layout = levels.get_mut(level).ok_or_else(|| {
+                        LsmError::Other(format!("manifest adds table to missing level {}", level))
+                    })?;
+                    layout.tables.push(file_name);
+                }
+                ManifestEdit::RemoveTable { level, file_name } => {
+                    if let Some(layout) = levels.get_mut(level) {
+                        layout.tables.retain(|name| name != &file_name);
+                    }
+                }
+            }
+        }
+
+        Ok(Some(levels))
+    }
+
+    /// Starts a fresh manifest holding just `snapshot`. The new file is
+    /// written next to the old one and renamed over it, so a crash leaves
+    /// either the old manifest or the new one, never a mix.
+    pub fn create(dir: &Path, snapshot: &[ManifestEdit]) -> Result<Self, LsmError> {
+        let path = dir.join(MANIFEST_FILE);
+        let temp_path: PathBuf = dir.join(format!("{}.tmp", MANIFEST_FILE));
+
+        let mut temp = BufWriter::new(File::create(&temp_path)?);
+        write_record(&mut temp, &ManifestEdit::encode_batch(snapshot))?;
+        temp.flush()?;
+        temp.get_ref().sync_data()?;
+        drop(temp);
+        fs::rename(&temp_path, &path)?;
+        // the rename only lasts once the directory is synced
+        sync_dir(dir)?;
+
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn log(&mut self, edits: &[ManifestEdit]) -> Result<(), LsmError> {
+        if edits.is_empty() {
+            return Ok(());
+        }
+        write_record(&mut self.writer, &ManifestEdit::encode_batch(edits))?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_recover_applies_edits() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        assert_eq!(Manifest::recover(temp_dir.path())?, None);
+
+        let mut manifest = Manifest::create(
+            temp_dir.path(),
+            &[ManifestEdit::AddLevel { depth: 0, width: 2 }],
+        )?;
+        manifest.log(&[
+            ManifestEdit::AddTable {
+                level: 0,
+                file_name: "a".to_string(),
+            },
+            ManifestEdit::AddTable {
+                level: 0,
+                file_name: "b".to_string(),
+            },
+        ])?;
+        manifest.log(&[
+            ManifestEdit::AddLevel { depth: 1, width: 3 },
+            ManifestEdit::RemoveTable {
+                level: 0,
+                file_name: "a".to_string(),
+            },
+            ManifestEdit::RemoveTable {
+                level: 0,
+                file_name: "b".to_string(),
+            },
+            ManifestEdit::AddTable {
+                level: 1,
+                file_name: "c".to_string(),
+            },
+        ])?;
+        drop(manifest);
+
+        let levels = Manifest::recover(temp_dir.path())?.unwrap();
+        assert_eq!(
+            levels,
+            vec![
+                LevelLayout {
+                    depth: 0,
+                    width: 2,
+                    tables: vec![],
+                },
+                LevelLayout {
+                    depth: 1,
+                    width: 3,
+                    tables: vec!["c".to_string()],
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_batch_is_ignored() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let mut manifest = Manifest::create(
+            temp_dir.path(),
+            &[ManifestEdit::AddLevel { depth: 0, width: 2 }],
+        )?;
+        manifest.log(&[ManifestEdit::AddTable {
+            level: 0,
+            file_name: "a".to_string(),
+        }])?;
+        drop(manifest);
+
+        // chop the last batch in half
+        let path = temp_dir.path().join(MANIFEST_FILE);
+        let data = fs::read(&path)?;
+        fs::write(&path, &data[..data.len() - 3])?;
+
+        let levels = Manifest::recover(temp_dir.path())?.unwrap();
+        assert_eq!(levels.len(), 1);
+        assert!(levels[0].tables.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use key_value::comparator::{default_comparator, Comparator};
+use memtable::mem_table_builder::MemTableType;
+use sstable::{filter::FilterPolicy, prefix::PrefixExtractor};
+use std::sync::Arc;
+
+use crate::write_buffer_manager::WriteBufferManager;
+
+/// Settings fixed for as long as a database is open.
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// Key order of the memtables, tables and compaction. A database must
+    /// always be opened with the comparator it was created with.
+    pub comparator: Arc<dyn Comparator>,
+    /// Byte budget of the block cache shared by all tables. Zero turns
+    /// caching off.
+    pub block_cache_capacity: usize,
+    /// Byte budget of the row cache, which keeps the newest version of
+    /// recently read keys so hot lookups skip the tables. Zero, the default,
+    /// turns it off.
+    pub row_cache_capacity: usize,
+    /// Most table files kept open at once.
+    pub table_cache_capacity: usize,
+    /// Read tables through memory mappings instead of the block cache.
+    pub use_mmap: bool,
+    /// Key filter of the tables of each level, by depth. Levels past the
+    /// end use the last policy; with none given every level gets a blocked
+    /// bloom filter.
+    pub filter_policies: Vec<Arc<dyn FilterPolicy>>,
+    /// Give every table a range filter as well, so that short range scans
+    /// skip tables with no key in the range. Only takes effect with
+    /// bytewise key order; the key range of each table is always checked.
+    pub range_filters: bool,
+    /// Adds key prefixes to every new table's key filter, so that
+    /// `prefix_scan` skips tables without the prefix.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Cut the index of every new table into partitions of this many data
+    /// blocks, read through the block cache as needed, instead of keeping
+    /// whole indexes in memory. Worth it once tables get very large.
+    pub index_partition_blocks: Option<usize>,
+    /// Give every new table a learned index, off by at most this many
+    /// restart runs, so lookups search only around its prediction. Meant
+    /// for integer-like keys that differ in their first eight bytes; only
+    /// takes effect with bytewise key order.
+    pub learned_index: Option<usize>,
+    /// Structure of the memtables. The skiplist suits any workload; the
+    /// concurrent hash map takes writes faster and suits loads read by
+    /// point lookups, since each range read has to sort it.
+    pub memtable_type: MemTableType,
+    /// Approximate memory a memtable may take, keys and values included,
+    /// before it is flushed.
+    pub write_buffer_size: usize,
+    /// Most versions a memtable holds before it is flushed, however small.
+    pub max_memtable_entries: usize,
+    /// Caps the memory of every memtable of the database, immutable ones
+    /// waiting on their flush included. Databases given the same manager
+    /// share its cap. With none, the memory is counted but never capped.
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
+    /// Immutable memtables that may wait for the background flush before
+    /// writes stop until one is flushed.
+    pub max_memtables: usize,
+    /// Tables in level 0 at which each write is delayed a little, to let
+    /// compaction catch up.
+    pub level0_slowdown_tables: usize,
+    /// Tables in level 0 at which writes stop until a flush and its
+    /// compaction finish.
+    pub level0_stop_tables: usize,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            comparator: default_comparator(),
+            block_cache_capacity: 8 << 20,
+            row_cache_capacity: 0,
+            table_cache_capacity: 500,
+            use_mmap: false,
+            filter_policies: Vec::new(),
+            range_filters: false,
+            prefix_extractor: None,
+            index_partition_blocks: None,
+            learned_index: None,
+            memtable_type: MemTableType::default(),
+            write_buffer_size: 4 << 20,
+            max_memtable_entries: 1000,
+            write_buffer_manager: None,
+            max_memtables: 10,
+            level0_slowdown_tables: 8,
+            level0_stop_tables: 12,
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use key_value::KeyValue;
+use lru::LruCache;
+use sstable::filter::key_hash;
+
+const SHARD_BITS: u32 = 4;
+const SHARDS: usize = 1 << SHARD_BITS;
+// groups of keys each shard remembers the last write to
+const STRIPES: usize = 64;
+// rough cost of a row on top of its key and value
+const ROW_OVERHEAD: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RowCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Bytes of keys and values held right now, with a small overhead per
+    /// row.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+#[derive(Debug)]
+struct Shard {
+    rows: LruCache<Arc<[u8]>, Arc<KeyValue>>,
+    usage: usize,
+    capacity: usize,
+    // sequence number of the latest write to any key of each stripe
+    written: [u64; STRIPES],
+}
+
+fn charge(kv: &KeyValue) -> usize {
+    kv.key.len() + kv.value.len() + ROW_OVERHEAD
+}
+
+/// The newest version of recently read keys, as found in the tables, so a
+/// repeated `get` of a hot key doesn't decode a run of any block. Keyed by
+/// user key, with the byte budget split over shards like the block cache.
+///
+/// Flushes and compactions only ever drop versions older than the newest
+/// one of a key, so what the cache holds stays right through them. Every
+/// write invalidates its key instead. A read only fills the cache if no key
+/// of the same stripe was written since the read began, so a version read
+/// just before an overwrite is never cached after it.
+#[derive(Debug)]
+pub struct RowCache {
+    shards: Vec<Mutex<Shard>>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RowCache {
+    /// A cache holding up to `capacity` bytes of rows. Zero turns it off.
+    pub fn new(capacity: usize) -> Self {
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    rows: LruCache::unbounded(),
+                    usage: 0,
+                    capacity: capacity / SHARDS,
+                    written: [0; STRIPES],
+                })
+            })
+            .collect();
+        Self {
+            shards,
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> RowCacheStats {
+        RowCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            usage: self
+                .shards
+                .iter()
+                .map(|shard| shard.lock().unwrap().usage)
+                .sum(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// The cached newest version of `key`, which may be a tombstone.
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Arc<KeyValue>> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let (shard, _) = self.locate(key);
+        let found = shard.lock().unwrap().rows.get(key).cloned();
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Caches `kv`, the newest version of its key in the tables, unless a
+    /// write to the key may have come after `read_sequence`, the last
+    /// sequence number written when the read began.
+    pub(crate) fn insert(&self, kv: Arc<KeyValue>, read_sequence: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let (shard, stripe) = self.locate(&kv.key);
+        let mut shard = shard.lock().unwrap();
+        if shard.written[stripe] > read_sequence || charge(&kv) > shard.capacity {
+            return;
+        }
+        shard.usage += charge(&kv);
+        if let Some(old) = shard.rows.put(kv.key.clone(), kv) {
+            shard.usage -= charge(&old);
+        }
+        while shard.usage > shard.capacity
+            && let Some((_, evicted)) = shard.rows.pop_lru()
+        {
+            shard.usage -= charge(&evicted);
+        }
+    }
+
+    /// Drops `key`, which the write with sequence number `sequence` just
+    /// changed.
+    pub(crate) fn invalidate(&self, key: &[u8], sequence: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let (shard, stripe) = self.locate(key);
+        let mut shard = shard.lock().unwrap();
+        shard.written[stripe] = shard.written[stripe].max(sequence);
+        if let Some(old) = shard.rows.pop(key) {
+            shard.usage -= charge(&old);
+        }
+    }
+
+    fn locate(&self, key: &[u8]) -> (&Mutex<Shard>, usize) {
+        let hash = key_hash(key);
+        let shard = &self.shards[(hash >> (64 - SHARD_BITS)) as usize];
+        (shard, hash as usize % STRIPES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(key: &str, value: &str, seq: u64) -> Arc<KeyValue> {
+        Arc::new(KeyValue {
+            seq,
+            ..KeyValue::new(key, value)
+        })
+    }
+
+    #[test]
+    fn test_writes_invalidate_rows() {
+        let cache = RowCache::new(SHARDS * 1024);
+        assert!(cache.get(b"a").is_none());
+        cache.insert(row("a", "1", 1), 5);
+        assert_eq!(cache.get(b"a").map(|kv| kv.seq), Some(1));
+
+        cache.invalidate(b"a", 6);
+        assert!(cache.get(b"a").is_none());
+        // a read that began before the write can't bring the old row back
+        cache.insert(row("a", "1", 1), 5);
+        assert!(cache.get(b"a").is_none());
+        cache.insert(row("a", "2", 6), 6);
+        assert_eq!(cache.get(b"a").map(|kv| kv.seq), Some(6));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 3));
+        assert_eq!(stats.usage, 2 + ROW_OVERHEAD);
+
+        let off = RowCache::new(0);
+        off.insert(row("a", "1", 1), 5);
+        assert!(off.get(b"a").is_none());
+        assert_eq!(off.stats(), RowCacheStats::default());
+    }
+
+    #[test]
+    fn test_rows_evicted_past_capacity() {
+        let cache = RowCache::new(SHARDS * 1000);
+        for i in 0..2000 {
+            cache.insert(row(&format!("key-{:04}", i), &"x".repeat(100), 1), 1);
+        }
+        let stats = cache.stats();
+        assert!(stats.usage <= stats.capacity);
+        assert!(stats.usage > stats.capacity / 2);
+        assert_eq!(cache.capacity(), SHARDS * 1000);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Sequence numbers of the snapshots that are still alive. Several snapshots
+/// can share a sequence number, so each one is reference counted.
+#[derive(Debug, Default)]
+pub struct SnapshotList {
+    live: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    /// Registers a snapshot at the current `last_sequence`. It is read under
+    /// the lock, so `sequences()` can't be taken between the read and the
+    /// registration.
+    pub(crate) fn acquire(list: &Arc<SnapshotList>, last_sequence: &AtomicU64) -> Snapshot {
+        let mut live = list.live.lock().unwrap();
+        let seq = last_sequence.load(Ordering::SeqCst);
+        *live.entry(seq).or_insert(0) += 1;
+        drop(live);
+        Snapshot {
+            seq,
+            list: Arc::clone(list),
+        }
+    }
+
+    fn release(&self, seq: u64) {
+        let mut live = self.live.lock().unwrap();
+        if let Some(count) = live.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&seq);
+            }
+        }
+    }
+
+    /// Sequence numbers of all live snapshots, oldest first.
+    pub fn sequences(&self) -> Vec<u64> {
+        self.live.lock().unwrap().keys().copied().collect()
+    }
+}
+
+/// A point-in-time view of the database, taken with `LsmDatabase::snapshot`.
+/// Reads through it see every write up to and including `sequence()` and
+/// nothing after. Flushes and compactions keep the versions it can see until
+/// it is dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    seq: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
@@ -0,0 +1,318 @@
+use integer_encoding::{VarInt, VarIntReader};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use sstable::sync_dir;
+use uuid::Uuid;
+
+use crate::{
+    error::LsmError,
+    log_record::{read_records, write_record},
+};
+
+const SEGMENT_PREFIX: &str = "wal-";
+const SEGMENT_SUFFIX: &str = ".log";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalRecord {
+    Put {
+        seq: u64,
+        key: Arc<[u8]>,
+        value: Arc<[u8]>,
+    },
+    Delete {
+        seq: u64,
+        key: Arc<[u8]>,
+    },
+}
+
+impl WalRecord {
+    const PUT: u8 = 1;
+    const DELETE: u8 = 2;
+
+    pub fn seq(&self) -> u64 {
+        match self {
+            WalRecord::Put { seq, .. } | WalRecord::Delete { seq, .. } => *seq,
+        }
+    }
+
+    pub fn key(&self) -> &Arc<[u8]> {
+        match self {
+            WalRecord::Put { key, .. } | WalRecord::Delete { key, .. } => key,
+        }
+    }
+
+    // payload: kind | varint seq | varint key len | key | varint value len | value
+    fn encode(&self) -> Vec<u8> {
+        let (kind, seq, key, value) = match self {
+            WalRecord::Put { seq, key, value } => (Self::PUT, *seq, &key[..], &value[..]),
+            WalRecord::Delete { seq, key } => (Self::DELETE, *seq, &key[..], &[][..]),
+        };
+
+        let mut payload = Vec::with_capacity(
+            1 + seq.required_space()
+                + key.len().required_space()
+                + key.len()
+                + value.len().required_space()
+                + value.len(),
+        );
+        payload.push(kind);
+        payload.extend_from_slice(&seq.encode_var_vec());
+        payload.extend_from_slice(&key.len().encode_var_vec());
+        payload.extend_from_slice(key);
+        payload.extend_from_slice(&value.len().encode_var_vec());
+        payload.extend_from_slice(value);
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let (&kind, rest) = payload.split_first()?;
+        let mut cursor = Cursor::new(rest);
+
+        let seq: u64 = cursor.read_varint().ok()?;
+        let key_len: usize = cursor.read_varint().ok()?;
+        let mut key = vec![0u8; key_len];
+        cursor.read_exact(&mut key).ok()?;
+        let value_len: usize = cursor.read_varint().ok()?;
+        let mut value = vec![0u8; value_len];
+        cursor.read_exact(&mut value).ok()?;
+
+        let key: Arc<[u8]> = key.into();
+        match kind {
+            Self::PUT => Some(WalRecord::Put {
+                seq,
+                key,
+                value: value.into(),
+            }),
+            Self::DELETE => Some(WalRecord::Delete { seq, key }),
+            _ => None,
+        }
+    }
+}
+
+/// A log segment on disk. Every memtable owns exactly one segment, so the
+/// segment can be dropped as soon as that memtable has been flushed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalSegment {
+    pub number: u64,
+    pub memtable_id: Uuid,
+    pub path: PathBuf,
+}
+
+impl WalSegment {
+    pub fn new(dir: &Path, number: u64, memtable_id: Uuid) -> Self {
+        Self {
+            number,
+            memtable_id,
+            path: dir.join(format!(
+                "{}{:010}-{}{}",
+                SEGMENT_PREFIX, number, memtable_id, SEGMENT_SUFFIX
+            )),
+        }
+    }
+
+    fn parse(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let stem = name
+            .strip_prefix(SEGMENT_PREFIX)?
+            .strip_suffix(SEGMENT_SUFFIX)?;
+        let (number, id) = stem.split_once('-')?;
+        Some(Self {
+            number: number.parse().ok()?,
+            memtable_id: Uuid::parse_str(id).ok()?,
+            path,
+        })
+    }
+
+    /// All segments in `dir`, oldest first.
+    pub fn list(dir: &Path) -> Result<Vec<WalSegment>, LsmError> {
+        let mut segments = Vec::new();
+        if !dir.exists() {
+            return Ok(segments);
+        }
+        for entry in fs::read_dir(dir)? {
+            if let Some(segment) = WalSegment::parse(entry?.path()) {
+                segments.push(segment);
+            }
+        }
+        segments.sort_by_key(|segment| segment.number);
+        Ok(segments)
+    }
+
+    /// Reads every intact record in the segment. Reading stops at the first
+    /// torn or corrupt record, which can only be the tail of the log since
+    /// records are appended one at a time. The returned length is the offset
+    /// just past the last good record.
+    pub fn replay(&self) -> Result<(Vec<WalRecord>, u64), LsmError> {
+        let data = fs::read(&self.path)?;
+        let (records, offset) = read_records(&data, WalRecord::decode);
+
+        if offset < data.len() {
+            log::warn!(
+                "Discarding {} trailing bytes of {}",
+                data.len() - offset,
+                self.path.display()
+            );
+        }
+
+        Ok((records, offset as u64))
+    }
+
+    pub fn delete(&self) -> Result<(), LsmError> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(LsmError::FileSystemError(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WalWriter {
+    dir: PathBuf,
+    segment: WalSegment,
+    writer: BufWriter<File>,
+}
+
+impl WalWriter {
+    pub fn create(dir: &Path, number: u64, memtable_id: Uuid) -> Result<Self, LsmError> {
+        fs::create_dir_all(dir)?;
+        let segment = WalSegment::new(dir, number, memtable_id);
+        let file = File::create(&segment.path)?;
+        // a segment holding acknowledged writes must not vanish in a crash
+        sync_dir(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segment,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Continues writing to an existing segment, cutting off anything past
+    /// `valid_len` so a torn tail doesn't end up in the middle of the log.
+    pub fn reopen(dir: &Path, segment: WalSegment, valid_len: u64) -> Result<Self, LsmError> {
+        let file = OpenOptions::new().append(true).open(&segment.path)?;
+        file.set_len(valid_len)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segment,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn segment(&self) -> &WalSegment {
+        &self.segment
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> Result<(), LsmError> {
+        write_record(&mut self.writer, &record.encode())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Seals the current segment and starts a new one for `memtable_id`.
+    /// Returns the sealed segment so it can be deleted after its flush.
+    pub fn rotate(&mut self, memtable_id: Uuid) -> Result<WalSegment, LsmError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        let next = WalWriter::create(&self.dir, self.segment.number + 1, memtable_id)?;
+        let sealed = std::mem::replace(self, next);
+        Ok(sealed.segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_append_and_replay() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let mut wal = WalWriter::create(temp_dir.path(), 0, Uuid::new_v4())?;
+
+        let records = vec![
+            WalRecord::Put {
+                seq: 1,
+                key: Arc::from(&b"key-1"[..]),
+                value: Arc::from(&b"value-1"[..]),
+            },
+            WalRecord::Delete {
+                seq: 2,
+                key: Arc::from(&b"key-1"[..]),
+            },
+            WalRecord::Put {
+                seq: 3,
+                key: Arc::from(&b"key-2"[..]),
+                value: Arc::from(&[][..]),
+            },
+            WalRecord::Put {
+                seq: 4,
+                key: Arc::from(&[0xff, 0x00, 0xc3][..]),
+                value: Arc::from(&[0xfe, 0xff][..]),
+            },
+        ];
+        for record in &records {
+            wal.append(record)?;
+        }
+
+        let (replayed, _) = wal.segment().replay()?;
+        assert_eq!(replayed, records);
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let mut wal = WalWriter::create(temp_dir.path(), 0, Uuid::new_v4())?;
+        wal.append(&WalRecord::Put {
+            seq: 1,
+            key: Arc::from(&b"key-1"[..]),
+            value: Arc::from(&b"value-1"[..]),
+        })?;
+        let segment = wal.segment().clone();
+        let good_len = fs::metadata(&segment.path)?.len();
+
+        // half-written second record
+        let mut file = OpenOptions::new().append(true).open(&segment.path)?;
+        file.write_all(&[0xde, 0xad, 0xbe, 0xef, 0x10])?;
+
+        let (replayed, valid_len) = segment.replay()?;
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(valid_len, good_len);
+
+        let mut wal = WalWriter::reopen(temp_dir.path(), segment.clone(), valid_len)?;
+        wal.append(&WalRecord::Delete {
+            seq: 2,
+            key: Arc::from(&b"key-1"[..]),
+        })?;
+        let (replayed, _) = segment.replay()?;
+        assert_eq!(replayed.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_orders_segments() -> Result<(), LsmError> {
+        let temp_dir = tempdir().unwrap();
+        let first_id = Uuid::new_v4();
+        let second_id = Uuid::new_v4();
+        let mut wal = WalWriter::create(temp_dir.path(), 0, first_id)?;
+
+        let sealed = wal.rotate(second_id)?;
+        assert_eq!(sealed.memtable_id, first_id);
+        assert_eq!(wal.segment().memtable_id, second_id);
+
+        let segments = WalSegment::list(temp_dir.path())?;
+        assert_eq!(
+            segments.iter().map(|s| s.memtable_id).collect::<Vec<_>>(),
+            vec![first_id, second_id]
+        );
+
+        sealed.delete()?;
+        assert_eq!(WalSegment::list(temp_dir.path())?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Counts the memory held by memtables across every database that shares
+/// it, active and immutable memtables alike, until they are flushed. Once
+/// the active memtables alone reach the cap, the next write to any of those
+/// databases flushes its active memtable, even short of its own
+/// `write_buffer_size`.
+#[derive(Debug)]
+pub struct WriteBufferManager {
+    capacity: usize,
+    usage: AtomicUsize,
+    // part of `usage` held by immutable memtables waiting on their flush
+    immutable: AtomicUsize,
+}
+
+impl WriteBufferManager {
+    /// A manager capping memtables at `capacity` bytes in total. Zero only
+    /// keeps count, without ever forcing a flush.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            usage: AtomicUsize::new(0),
+            immutable: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Approximate bytes held by memtables that are not yet flushed.
+    pub fn memory_usage(&self) -> usize {
+        self.usage.load(Ordering::Relaxed)
+    }
+
+    /// Approximate bytes held by memtables still taking writes.
+    pub fn mutable_memory_usage(&self) -> usize {
+        self.memory_usage()
+            .saturating_sub(self.immutable.load(Ordering::Relaxed))
+    }
+
+    /// Whether the memtables still taking writes hold as much memory as the
+    /// cap allows. Memory already on its way to disk doesn't count, or every
+    /// write would flush while a flush runs.
+    pub fn should_flush(&self) -> bool {
+        self.capacity > 0 && self.mutable_memory_usage() >= self.capacity
+    }
+}
+
+/// The share of a `WriteBufferManager` held by one database's memtables.
+/// Whatever is still reserved when the database closes is handed back.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    manager: Arc<WriteBufferManager>,
+    bytes: AtomicUsize,
+    immutable: AtomicUsize,
+}
+
+impl Reservation {
+    pub fn new(manager: Arc<WriteBufferManager>) -> Self {
+        Self {
+            manager,
+            bytes: AtomicUsize::new(0),
+            immutable: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn manager(&self) -> &Arc<WriteBufferManager> {
+        &self.manager
+    }
+
+    pub fn reserve(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.manager.usage.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Marks `bytes` as held by a memtable that takes no more writes.
+    pub fn mark_immutable(&self, bytes: usize) {
+        self.immutable.fetch_add(bytes, Ordering::Relaxed);
+        self.manager.immutable.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Hands back the `bytes` of an immutable memtable that is now flushed.
+    pub fn free(&self, bytes: usize) {
+        self.immutable.fetch_sub(bytes, Ordering::Relaxed);
+        self.manager.immutable.fetch_sub(bytes, Ordering::Relaxed);
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+        self.manager.usage.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.manager
+            .immutable
+            .fetch_sub(*self.immutable.get_mut(), Ordering::Relaxed);
+        self.manager
+            .usage
+            .fetch_sub(*self.bytes.get_mut(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservations_share_the_cap() {
+        let manager = Arc::new(WriteBufferManager::new(1000));
+        let first = Reservation::new(manager.clone());
+        let second = Reservation::new(manager.clone());
+
+        first.reserve(600);
+        assert!(!manager.should_flush());
+        second.reserve(400);
+        assert!(manager.should_flush());
+        // memory waiting on a flush still counts, but forces no more flushes
+        first.mark_immutable(600);
+        assert_eq!(manager.memory_usage(), 1000);
+        assert!(!manager.should_flush());
+        first.free(600);
+        assert_eq!(manager.memory_usage(), 400);
+
+        // a closing database hands back what its memtables still held
+        second.mark_immutable(100);
+        drop(second);
+        assert_eq!(manager.memory_usage(), 0);
+        assert_eq!(manager.mutable_memory_usage(), 0);
+
+        let uncapped = WriteBufferManager::new(0);
+        assert!(!uncapped.should_flush());
+        assert_eq!(uncapped.capacity(), 0);
+    }
+}
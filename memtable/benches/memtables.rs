@@ -0,0 +1,86 @@
+// Compares the arena skiplist with the crossbeam one: concurrent inserts,
+// point reads and a full scan over the same keys.
+//
+//   cargo bench -p memtable
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use memtable::{
+    mem_table_builder::{MemTableBuilder, MemTableType},
+    MemTable, MemTableOperations,
+};
+
+const THREADS: usize = 4;
+const ENTRIES: usize = 1_000_000;
+const VALUE: &[u8] = &[0x5a; 100];
+
+fn key(n: usize) -> Arc<[u8]> {
+    // spread writes over the key space rather than appending
+    Arc::from(format!("key-{:016x}", n.wrapping_mul(0x9e37_79b9_7f4a_7c15)).as_bytes())
+}
+
+fn timed(f: impl FnOnce()) -> Duration {
+    let started = Instant::now();
+    f();
+    started.elapsed()
+}
+
+fn run(memtable_type: MemTableType) {
+    let memtable = Arc::new(
+        MemTableBuilder::default()
+            .memtable_type(memtable_type)
+            .max_entries(ENTRIES)
+            .max_bytes(1 << 30)
+            .build(),
+    );
+    let keys: Arc<Vec<Arc<[u8]>>> = Arc::new((0..ENTRIES).map(key).collect());
+
+    let insert = timed(|| {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let memtable: Arc<MemTable> = memtable.clone();
+                let keys = keys.clone();
+                thread::spawn(move || {
+                    for n in (thread..ENTRIES).step_by(THREADS) {
+                        memtable.insert(keys[n].clone(), Arc::from(VALUE), n as u64 + 1);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+
+    let get = timed(|| {
+        for key in keys.iter() {
+            assert!(memtable.get(key).is_some());
+        }
+    });
+
+    let scan = timed(|| {
+        let (entries, _) = memtable.range_at(b"", None, u64::MAX);
+        assert_eq!(entries.len(), ENTRIES);
+    });
+
+    let per_op = |elapsed: Duration| elapsed.as_nanos() / ENTRIES as u128;
+    println!(
+        "{:<14} insert {:>5} ns/op   get {:>5} ns/op   scan {:>5} ns/entry   {:>4} MiB",
+        format!("{:?}", memtable_type),
+        per_op(insert),
+        per_op(get),
+        per_op(scan),
+        memtable.memory_usage() >> 20,
+    );
+}
+
+fn main() {
+    println!("{} entries, {} writer threads", ENTRIES, THREADS);
+    for memtable_type in [MemTableType::SkipList, MemTableType::ArenaSkipList] {
+        run(memtable_type);
+    }
+}
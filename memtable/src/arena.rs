@@ -0,0 +1,99 @@
+use std::{
+    alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout},
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+// every allocation is aligned for the `u64`s and pointers of a node
+const ALIGN: usize = 8;
+
+#[derive(Debug)]
+struct Blocks {
+    // every block ever allocated; the bump block is the last one allocated
+    // at `block_size`
+    all: Vec<(NonNull<u8>, Layout)>,
+    current: Option<NonNull<u8>>,
+    used: usize,
+}
+
+/// A bump allocator that hands out zeroed memory from large blocks. Nothing
+/// is freed until the arena drops, so an allocation stays where it is for as
+/// long as the arena lives.
+#[derive(Debug)]
+pub(crate) struct Arena {
+    block_size: usize,
+    blocks: Mutex<Blocks>,
+    // bytes handed out so far, each allocation rounded up to `ALIGN`
+    allocated: AtomicUsize,
+}
+
+// Safety: the blocks are only reached through the mutex, and the memory
+// they hand out is only freed when the arena drops.
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            block_size: block_size.next_multiple_of(ALIGN),
+            blocks: Mutex::new(Blocks {
+                all: Vec::new(),
+                current: None,
+                used: 0,
+            }),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// `size` zeroed bytes, aligned to eight. Allocations larger than a
+    /// quarter block get a block of their own, so they waste no tail.
+    pub fn allocate(&self, size: usize) -> NonNull<u8> {
+        let size = size.max(1).next_multiple_of(ALIGN);
+        let mut blocks = self.blocks.lock().unwrap();
+        self.allocated.fetch_add(size, Ordering::Relaxed);
+        if size > self.block_size / 4 {
+            return self.new_block(&mut blocks, size);
+        }
+        let current = match blocks.current {
+            Some(current) if blocks.used + size <= self.block_size => current,
+            _ => {
+                let current = self.new_block(&mut blocks, self.block_size);
+                blocks.current = Some(current);
+                blocks.used = 0;
+                current
+            }
+        };
+        // Safety: `used + size` is within the block
+        let allocated = unsafe { current.add(blocks.used) };
+        blocks.used += size;
+        allocated
+    }
+
+    /// Bytes handed out so far. The unused tail of each block is left
+    /// out, so a fresh arena reports next to nothing rather than a block.
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    fn new_block(&self, blocks: &mut Blocks, size: usize) -> NonNull<u8> {
+        let layout = Layout::from_size_align(size, ALIGN).expect("arena block too large");
+        // Safety: `layout` has a non-zero size
+        let block = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+        blocks.all.push((block, layout));
+        block
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (block, layout) in self.blocks.get_mut().unwrap().all.drain(..) {
+            // Safety: allocated by `new_block` with this layout, and the
+            // arena is going away along with everything handed out
+            unsafe { dealloc(block.as_ptr(), layout) };
+        }
+    }
+}
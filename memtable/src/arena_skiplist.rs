@@ -0,0 +1,585 @@
+use key_value::{comparator::Comparator, version_is_needed, KeyValue, ValueKind};
+use sstable::{builder::SSTableFeatures, streamed_builder::StreamedSSTableBuilder, SSTable};
+use std::{
+    cmp::Ordering,
+    mem::size_of,
+    path::PathBuf,
+    ptr,
+    sync::{
+        atomic::{self, AtomicPtr, AtomicU64, AtomicUsize},
+        Arc,
+    },
+};
+
+use crate::arena::Arena;
+use crate::{MemTableOperations, RangeResult};
+
+const MAX_HEIGHT: usize = 12;
+// value length of a tombstone
+const TOMBSTONE: u32 = u32::MAX;
+
+/// The longest key an `ArenaSkipList` can hold.
+pub const MAX_KEY_LEN: usize = u32::MAX as usize;
+/// The longest value an `ArenaSkipList` can hold; `u32::MAX` itself marks a
+/// tombstone.
+pub const MAX_VALUE_LEN: usize = TOMBSTONE as usize - 1;
+
+// A node lives in the arena as this header, then `height` next pointers,
+// then the key, then the value. Nodes are never moved nor freed before the
+// arena, so a pointer to one stays good for as long as the list does.
+#[repr(C)]
+struct Node {
+    seq: u64,
+    key_len: u32,
+    value_len: u32,
+    height: usize,
+}
+
+const POINTER: usize = size_of::<AtomicPtr<Node>>();
+
+// Safety for the helpers below: `node` points at a node of this list that
+// is fully written, which holds for every node reachable from the head.
+unsafe fn next<'a>(node: *const Node, level: usize) -> &'a AtomicPtr<Node> {
+    unsafe { &*(node.cast::<u8>().add(size_of::<Node>() + level * POINTER) as *const AtomicPtr<Node>) }
+}
+
+unsafe fn key<'a>(node: *const Node) -> &'a [u8] {
+    unsafe {
+        let start = node.cast::<u8>().add(size_of::<Node>() + (*node).height * POINTER);
+        std::slice::from_raw_parts(start, (*node).key_len as usize)
+    }
+}
+
+unsafe fn value<'a>(node: *const Node) -> Option<&'a [u8]> {
+    unsafe {
+        if (*node).value_len == TOMBSTONE {
+            return None;
+        }
+        let start = key(node).as_ptr().add((*node).key_len as usize);
+        Some(std::slice::from_raw_parts(start, (*node).value_len as usize))
+    }
+}
+
+// The value length a node stores for `value_len`, `None` being a tombstone.
+// Panics if the key or the value is too long for the node header.
+fn checked_value_len(key_len: usize, value_len: Option<usize>) -> u32 {
+    assert!(
+        key_len <= MAX_KEY_LEN,
+        "key of {} bytes is too long for an arena memtable",
+        key_len
+    );
+    match value_len {
+        Some(len) if len > MAX_VALUE_LEN => {
+            panic!("value of {} bytes is too long for an arena memtable", len)
+        }
+        Some(len) => len as u32,
+        None => TOMBSTONE,
+    }
+}
+
+/// One version in an `ArenaSkipList`, borrowed from its arena rather than
+/// copied out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryView<'a> {
+    pub key: &'a [u8],
+    /// `None` for a tombstone.
+    pub value: Option<&'a [u8]>,
+    pub seq: u64,
+}
+
+impl EntryView<'_> {
+    /// An owned copy, for handing the version beyond the memtable.
+    pub fn to_key_value(&self) -> KeyValue {
+        match self.value {
+            Some(value) => KeyValue {
+                key: Arc::from(self.key),
+                value: Arc::from(value),
+                kind: ValueKind::Put,
+                seq: self.seq,
+            },
+            None => KeyValue {
+                key: Arc::from(self.key),
+                value: Arc::from(&[][..]),
+                kind: ValueKind::Delete,
+                seq: self.seq,
+            },
+        }
+    }
+}
+
+/// The versions of an `ArenaSkipList` in order, from some starting point.
+pub struct Iter<'a> {
+    node: *const Node,
+    list: &'a ArenaSkipList,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = EntryView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node.is_null() {
+            return None;
+        }
+        let view = self.list.view(self.node);
+        self.node = unsafe { next(self.node, 0) }.load(atomic::Ordering::Acquire);
+        Some(view)
+    }
+}
+
+// A concurrent skiplist whose nodes, keys and values are all carved out of
+// one arena per memtable. Versions sort by key under the comparator, then
+// newest first, like the other memtables. Writers link a node in level by
+// level with compare-and-swap, so neither writers nor readers take a lock
+// beyond the arena's, and memory usage is what the arena has handed out.
+// Lengths are kept as `u32`: adding a key longer than `MAX_KEY_LEN` or a
+// value longer than `MAX_VALUE_LEN` panics, so callers check them first.
+#[derive(Debug)]
+pub struct ArenaSkipList {
+    arena: Arena,
+    head: *mut Node,
+    // tallest node so far; searches start there
+    height: AtomicUsize,
+    len: AtomicUsize,
+    // state of the splitmix64 generator picking node heights
+    rng: AtomicU64,
+    max_entries: usize,
+    max_bytes: usize,
+    comparator: Arc<dyn Comparator>,
+}
+
+// Safety: nodes are only written before they are published, and are then
+// shared read-only but for their next pointers, which are atomic.
+unsafe impl Send for ArenaSkipList {}
+unsafe impl Sync for ArenaSkipList {}
+
+impl ArenaSkipList {
+    pub fn new(max_entries: usize, max_bytes: usize, comparator: Arc<dyn Comparator>) -> Self {
+        // blocks of an eighth of the budget keep the unused tail of the last
+        // one small next to the memtable as a whole
+        let arena = Arena::new((max_bytes / 8).clamp(4 << 10, 1 << 20));
+        let head = Self::allocate(&arena, MAX_HEIGHT, 0, 0);
+        Self {
+            arena,
+            head,
+            height: AtomicUsize::new(1),
+            len: AtomicUsize::new(0),
+            rng: AtomicU64::new(0x2545_f491_4f6c_dd1d),
+            max_entries,
+            max_bytes,
+            comparator,
+        }
+    }
+
+    /// The newest version of `key` written at or before sequence `seq`,
+    /// borrowed from the memtable.
+    pub fn get_view(&self, key: &[u8], seq: u64) -> Option<EntryView<'_>> {
+        let node = self.find_greater_or_equal(key, seq, None);
+        if node.is_null() {
+            return None;
+        }
+        let view = self.view(node);
+        self.comparator.compare(view.key, key).is_eq().then_some(view)
+    }
+
+    /// Every version, in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            node: unsafe { next(self.head, 0) }.load(atomic::Ordering::Acquire),
+            list: self,
+        }
+    }
+
+    /// Versions from the first one at or after `key` at sequence `seq` on.
+    pub fn iter_from(&self, key: &[u8], seq: u64) -> Iter<'_> {
+        Iter {
+            node: self.find_greater_or_equal(key, seq, None),
+            list: self,
+        }
+    }
+
+    fn allocate(arena: &Arena, height: usize, key_len: usize, value_len: usize) -> *mut Node {
+        let size = size_of::<Node>() + height * POINTER + key_len + value_len;
+        let node = arena.allocate(size).as_ptr().cast::<Node>();
+        // Safety: the arena hands out zeroed memory aligned for a node, and
+        // null next pointers are all zeroes
+        unsafe {
+            node.write(Node {
+                seq: 0,
+                key_len: key_len as u32,
+                value_len: 0,
+                height,
+            })
+        };
+        node
+    }
+
+    fn view<'a>(&'a self, node: *const Node) -> EntryView<'a> {
+        // Safety: `node` was reached from the head, so is fully written and
+        // lives as long as the arena
+        unsafe {
+            EntryView {
+                key: key(node),
+                value: value(node),
+                seq: (*node).seq,
+            }
+        }
+    }
+
+    // where `node` sorts against the version `seq` of `key`
+    fn compare(&self, node: *const Node, key_: &[u8], seq: u64) -> Ordering {
+        let view = self.view(node);
+        self.comparator
+            .compare(view.key, key_)
+            .then(seq.cmp(&view.seq))
+    }
+
+    fn random_height(&self) -> usize {
+        let mut x = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, atomic::Ordering::Relaxed);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        // a node reaches each level above the first with probability 1/4
+        let mut height = 1;
+        while height < MAX_HEIGHT && x & 3 == 0 {
+            height += 1;
+            x >>= 2;
+        }
+        height
+    }
+
+    // The first node at or after version `seq` of `key`, or null. With
+    // `before`, also the last node before it on each level searched.
+    fn find_greater_or_equal(
+        &self,
+        key_: &[u8],
+        seq: u64,
+        mut before: Option<&mut [*mut Node; MAX_HEIGHT]>,
+    ) -> *mut Node {
+        let mut node = self.head;
+        let mut level = self.height.load(atomic::Ordering::Relaxed) - 1;
+        loop {
+            let next = unsafe { next(node, level) }.load(atomic::Ordering::Acquire);
+            if !next.is_null() && self.compare(next, key_, seq).is_lt() {
+                node = next;
+                continue;
+            }
+            if let Some(before) = before.as_deref_mut() {
+                before[level] = node;
+            }
+            if level == 0 {
+                return next;
+            }
+            level -= 1;
+        }
+    }
+
+    fn add(&self, key_: &[u8], value_: Option<&[u8]>, seq: u64) {
+        let stored_value_len = checked_value_len(key_.len(), value_.map(<[u8]>::len));
+        let height = self.random_height();
+        let value_len = value_.map_or(0, <[u8]>::len);
+        let node = Self::allocate(&self.arena, height, key_.len(), value_len);
+        // Safety: the node is not yet published, so nothing else reads it
+        unsafe {
+            (*node).seq = seq;
+            (*node).value_len = stored_value_len;
+            let key_start = key(node).as_ptr().cast_mut();
+            ptr::copy_nonoverlapping(key_.as_ptr(), key_start, key_.len());
+            if let Some(value) = value_ {
+                ptr::copy_nonoverlapping(value.as_ptr(), key_start.add(key_.len()), value.len());
+            }
+        }
+        self.height.fetch_max(height, atomic::Ordering::Relaxed);
+
+        // levels above the list's height before this node have only the
+        // head before them
+        let mut before = [self.head; MAX_HEIGHT];
+        self.find_greater_or_equal(key_, seq, Some(&mut before));
+        for (level, before) in before.iter_mut().enumerate().take(height) {
+            loop {
+                // another writer may have linked nodes in since the search
+                let mut after = unsafe { next(*before, level) }.load(atomic::Ordering::Acquire);
+                while !after.is_null() && self.compare(after, key_, seq).is_lt() {
+                    *before = after;
+                    after = unsafe { next(after, level) }.load(atomic::Ordering::Acquire);
+                }
+                unsafe { next(node, level) }.store(after, atomic::Ordering::Relaxed);
+                if unsafe { next(*before, level) }
+                    .compare_exchange(
+                        after,
+                        node,
+                        atomic::Ordering::Release,
+                        atomic::Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    break;
+                }
+            }
+        }
+        self.len.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    fn same_key(&self, a: &[u8], b: &[u8]) -> bool {
+        self.comparator.compare(a, b).is_eq()
+    }
+}
+
+impl MemTableOperations for ArenaSkipList {
+    fn put(&mut self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.add(&key, Some(&value), seq);
+    }
+
+    fn insert(&self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.add(&key, Some(&value), seq);
+    }
+
+    fn delete(&self, key: Arc<[u8]>, seq: u64) {
+        self.add(&key, None, seq);
+    }
+
+    fn get_at(&self, key: &[u8], seq: u64) -> Option<Box<KeyValue>> {
+        self.get_view(key, seq)
+            .map(|view| Box::new(view.to_key_value()))
+    }
+
+    fn range_at(
+        &self,
+        from: &[u8],
+        to: Option<&[u8]>,
+        seq: u64,
+    ) -> (Vec<Box<KeyValue>>, RangeResult) {
+        let mut results: Vec<Box<KeyValue>> = Vec::new();
+        let mut saw_to = false;
+        // only the versions handed back are copied out of the arena
+        let mut last: Option<&[u8]> = None;
+
+        for view in self.iter_from(from, u64::MAX) {
+            if view.seq > seq || last.is_some_and(|last| self.same_key(last, view.key)) {
+                // too new for this read, or older than a version we already have
+                continue;
+            }
+            match to.map_or(Ordering::Less, |to| self.comparator.compare(view.key, to)) {
+                Ordering::Greater => return (results, RangeResult::FirstKeyFound),
+                ordering => {
+                    results.push(Box::new(view.to_key_value()));
+                    last = Some(view.key);
+                    saw_to |= ordering.is_eq();
+                }
+            }
+        }
+
+        if results.is_empty() {
+            (results, RangeResult::KeyNotFound)
+        } else if saw_to {
+            (results, RangeResult::FullSetFound)
+        } else {
+            (results, RangeResult::FirstKeyFound)
+        }
+    }
+
+    fn at_capacity(&self) -> bool {
+        self.current_length() >= self.max_entries || self.memory_usage() >= self.max_bytes
+    }
+
+    fn current_length(&self) -> usize {
+        self.len.load(atomic::Ordering::Relaxed)
+    }
+
+    fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Exact bytes taken from the arena: every node with its key and value.
+    /// The unused tail of the arena's current block is left out.
+    fn memory_usage(&self) -> usize {
+        self.arena.allocated()
+    }
+
+    fn flush(
+        &self,
+        path: PathBuf,
+        table_params: SSTableFeatures,
+        snapshots: &[u64],
+    ) -> Result<Arc<SSTable>, crate::error::MemTableError> {
+        let mut builder = StreamedSSTableBuilder::new(table_params, true, &path)?
+            .with_comparator(self.comparator.clone());
+
+        // versions a snapshot can no longer see are left behind
+        let mut newer: Option<EntryView<'_>> = None;
+        for view in self.iter() {
+            let newer_seq = newer
+                .filter(|newer| self.same_key(newer.key, view.key))
+                .map(|newer| newer.seq);
+            if version_is_needed(view.seq, newer_seq, snapshots) {
+                builder.add_from_kv(view.to_key_value())?;
+            }
+            newer = Some(view);
+        }
+
+        let table = builder.finalize()?;
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_value::comparator::default_comparator;
+    use std::thread;
+
+    fn list() -> ArenaSkipList {
+        ArenaSkipList::new(usize::MAX, 64 << 20, default_comparator())
+    }
+
+    fn bytes(s: &str) -> Arc<[u8]> {
+        Arc::from(s.as_bytes())
+    }
+
+    #[test]
+    fn test_versions_in_order() {
+        let list = list();
+        for (seq, k) in [(4, "b"), (1, "c"), (2, "a"), (6, "b"), (3, "")].into_iter() {
+            list.insert(bytes(k), bytes(&format!("{}{}", k, seq)), seq);
+        }
+        list.delete(bytes("a"), 5);
+
+        let order: Vec<(&[u8], u64)> = list.iter().map(|view| (view.key, view.seq)).collect();
+        assert_eq!(
+            order,
+            vec![(&b""[..], 3), (b"a", 5), (b"a", 2), (b"b", 6), (b"b", 4), (b"c", 1)]
+        );
+        assert_eq!(list.current_length(), 6);
+
+        assert_eq!(list.get_view(b"b", u64::MAX).unwrap().value, Some(&b"b6"[..]));
+        assert_eq!(list.get_view(b"b", 5).unwrap().value, Some(&b"b4"[..]));
+        assert_eq!(list.get_view(b"b", 3), None);
+        assert_eq!(list.get_view(b"a", 5).unwrap().value, None);
+        assert_eq!(list.get_view(b"a", 4).unwrap().value, Some(&b"a2"[..]));
+        assert_eq!(list.get_view(b"d", u64::MAX), None);
+        assert!(list.get(b"a").unwrap().is_tombstone());
+        assert_eq!(list.get(b"").unwrap().value_str(), Some("3"));
+
+        let from: Vec<u64> = list.iter_from(b"a", 3).map(|view| view.seq).collect();
+        assert_eq!(from, vec![2, 6, 4, 1]);
+    }
+
+    #[test]
+    fn test_range_at() {
+        let list = list();
+        for i in 0..100u64 {
+            list.insert(bytes(&format!("key-{:03}", i)), bytes("old"), i + 1);
+        }
+        list.insert(bytes("key-050"), bytes("new"), 200);
+        list.delete(bytes("key-051"), 201);
+
+        let (entries, result) = list.range_at(b"key-049", Some(b"key-052"), u64::MAX);
+        let found: Vec<_> = entries.iter().map(|kv| (kv.key.to_vec(), kv.seq)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (b"key-049".to_vec(), 50),
+                (b"key-050".to_vec(), 200),
+                (b"key-051".to_vec(), 201),
+                (b"key-052".to_vec(), 53),
+            ]
+        );
+        assert_eq!(result, RangeResult::FirstKeyFound);
+        assert!(entries[2].is_tombstone());
+
+        let (entries, _) = list.range_at(b"key-049", Some(b"key-052"), 100);
+        assert_eq!(entries[1].value_str(), Some("old"));
+        assert!(!entries[2].is_tombstone());
+
+        let (entries, result) = list.range_at(b"key-098", None, u64::MAX);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(result, RangeResult::FirstKeyFound);
+        let (entries, result) = list.range_at(b"x", Some(b"z"), u64::MAX);
+        assert!(entries.is_empty());
+        assert_eq!(result, RangeResult::KeyNotFound);
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        const THREADS: u64 = 8;
+        const PER_THREAD: u64 = 5000;
+
+        let list = Arc::new(list());
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    // threads interleave their keys, and every key gets two
+                    // versions
+                    for i in 0..PER_THREAD {
+                        let n = i * THREADS + thread;
+                        let key = bytes(&format!("key-{:06}", n));
+                        list.insert(key.clone(), bytes("first"), 2 * n + 1);
+                        list.insert(key, bytes(&n.to_string()), 2 * n + 2);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let total = THREADS * PER_THREAD;
+        assert_eq!(list.current_length(), 2 * total as usize);
+        let views: Vec<_> = list.iter().collect();
+        assert_eq!(views.len(), 2 * total as usize);
+        assert!(views.windows(2).all(|pair| {
+            (pair[0].key, std::cmp::Reverse(pair[0].seq))
+                < (pair[1].key, std::cmp::Reverse(pair[1].seq))
+        }));
+        for n in (0..total).step_by(97) {
+            let key = format!("key-{:06}", n);
+            let newest = list.get_view(key.as_bytes(), u64::MAX).unwrap();
+            assert_eq!(newest.value, Some(n.to_string().as_bytes()));
+            let older = list.get_view(key.as_bytes(), 2 * n + 1).unwrap();
+            assert_eq!(older.value, Some(&b"first"[..]));
+        }
+
+        let (entries, _) = list.range(b"key-001000", b"key-001999");
+        assert_eq!(entries.len(), 1000);
+    }
+
+    #[test]
+    fn test_memory_usage_counts_allocations() {
+        // the arena's blocks are a megabyte each, none of which counts
+        // until it is handed out
+        let list = list();
+        let fresh = list.memory_usage();
+        assert!(fresh < 1 << 10, "{}", fresh);
+
+        let mut usage = fresh;
+        for (seq, key) in [(1, "a"), (2, "b"), (3, "c")] {
+            list.insert(bytes(key), bytes("value"), seq);
+            assert!(list.memory_usage() > usage);
+            usage = list.memory_usage();
+        }
+        assert!(usage < 1 << 10, "{}", usage);
+
+        list.insert(bytes("d"), Arc::from(vec![0; 300 << 10]), 4);
+        assert!(list.memory_usage() >= usage + (300 << 10));
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn test_value_length_of_tombstone_is_rejected() {
+        checked_value_len(3, Some(u32::MAX as usize));
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn test_long_key_is_rejected() {
+        checked_value_len(1 << 32, Some(0));
+    }
+
+    #[test]
+    fn test_value_lengths() {
+        assert_eq!(checked_value_len(3, Some(u32::MAX as usize - 1)), u32::MAX - 1);
+        assert_eq!(checked_value_len(3, None), TOMBSTONE);
+    }
+}
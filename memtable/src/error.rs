@@ -1,7 +1,7 @@
 use sstable::error::SSTableError;
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Error, Clone)]
 pub enum MemTableError {
     #[error("SSTable error: {0}")]
     SSTable(#[from] SSTableError),
@@ -0,0 +1,269 @@
+use dashmap::DashMap;
+use key_value::{comparator::Comparator, version_is_needed, KeyValue, ValueKind};
+use sstable::{builder::SSTableFeatures, streamed_builder::StreamedSSTableBuilder, SSTable};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{range_of_sorted, MemTableOperations, MemoryUsage, RangeResult};
+
+// a `None` value is a tombstone
+type Version = (u64, Option<Arc<[u8]>>);
+
+// Keys live in a concurrent hash map, each with its versions newest first,
+// so writes and point reads never wait on one another. Nothing is kept in
+// key order: a flush sorts everything once, and a range read sorts the keys
+// it matches. Keys are told apart by their bytes, so the comparator must
+// only call equal keys that are byte for byte the same.
+#[derive(Debug)]
+pub struct HashMemTable {
+    inner: DashMap<Arc<[u8]>, Vec<Version>>,
+    // number of versions, across every key
+    len: AtomicUsize,
+    max_entries: usize,
+    max_bytes: usize,
+    usage: MemoryUsage,
+    comparator: Arc<dyn Comparator>,
+}
+
+fn to_key_value(key: &Arc<[u8]>, (seq, value): &Version) -> KeyValue {
+    match value {
+        Some(value) => KeyValue {
+            key: key.clone(),
+            value: value.clone(),
+            kind: ValueKind::Put,
+            seq: *seq,
+        },
+        None => KeyValue {
+            key: key.clone(),
+            value: Arc::from(&[][..]),
+            kind: ValueKind::Delete,
+            seq: *seq,
+        },
+    }
+}
+
+// the newest version written at or before `seq`
+fn visible(versions: &[Version], seq: u64) -> Option<&Version> {
+    versions.iter().find(|(version, _)| *version <= seq)
+}
+
+impl HashMemTable {
+    pub fn new(max_entries: usize, max_bytes: usize, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            inner: DashMap::new(),
+            len: AtomicUsize::new(0),
+            max_entries,
+            max_bytes,
+            usage: MemoryUsage::default(),
+            comparator,
+        }
+    }
+
+    fn add(&self, key: Arc<[u8]>, version: Version) {
+        self.usage.add(&key, version.1.as_deref().unwrap_or_default());
+        let mut versions = self.inner.entry(key).or_default();
+        // writes mostly arrive in sequence order, but a replay need not
+        let at = versions.partition_point(|(seq, _)| *seq > version.0);
+        versions.insert(at, version);
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl MemTableOperations for HashMemTable {
+    fn put(&mut self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.add(key, (seq, Some(value)));
+    }
+
+    fn insert(&self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.add(key, (seq, Some(value)));
+    }
+
+    fn delete(&self, key: Arc<[u8]>, seq: u64) {
+        self.add(key, (seq, None));
+    }
+
+    fn get_at(&self, key: &[u8], seq: u64) -> Option<Box<KeyValue>> {
+        let entry = self.inner.get(key)?;
+        visible(entry.value(), seq).map(|version| Box::new(to_key_value(entry.key(), version)))
+    }
+
+    fn range_at(
+        &self,
+        from_m: &[u8],
+        to_n: Option<&[u8]>,
+        seq: u64,
+    ) -> (Vec<Box<KeyValue>>, RangeResult) {
+        // the first key past the range tells the caller the range ended here
+        let mut newest: Vec<KeyValue> = Vec::new();
+        let mut past_range: Option<KeyValue> = None;
+        for entry in self.inner.iter() {
+            if self.comparator.compare(entry.key(), from_m).is_lt() {
+                continue;
+            }
+            let Some(version) = visible(entry.value(), seq) else {
+                continue;
+            };
+            let kv = to_key_value(entry.key(), version);
+            if to_n.is_none_or(|to_n| self.comparator.compare(&kv.key, to_n).is_le()) {
+                newest.push(kv);
+            } else if past_range
+                .as_ref()
+                .is_none_or(|first| self.comparator.compare(&kv.key, &first.key).is_lt())
+            {
+                past_range = Some(kv);
+            }
+        }
+        newest.sort_by(|a, b| self.comparator.compare(&a.key, &b.key));
+        newest.extend(past_range);
+        range_of_sorted(self.comparator.as_ref(), &newest, from_m, to_n)
+    }
+
+    fn at_capacity(&self) -> bool {
+        self.current_length() >= self.max_entries || self.memory_usage() >= self.max_bytes
+    }
+
+    fn current_length(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.usage.get()
+    }
+
+    fn flush(
+        &self,
+        path: PathBuf,
+        table_params: SSTableFeatures,
+        snapshots: &[u64],
+    ) -> Result<Arc<SSTable>, crate::error::MemTableError> {
+        let mut keys: Vec<Arc<[u8]>> = self.inner.iter().map(|entry| entry.key().clone()).collect();
+        keys.sort_by(|a, b| self.comparator.compare(a, b));
+
+        let mut builder = StreamedSSTableBuilder::new(table_params, true, &path)?
+            .with_comparator(self.comparator.clone());
+        for key in keys {
+            let Some(entry) = self.inner.get(&key) else {
+                continue;
+            };
+            // versions a snapshot can no longer see are left behind
+            let mut newer_seq = None;
+            for version in entry.value() {
+                if version_is_needed(version.0, newer_seq, snapshots) {
+                    builder.add_from_kv(to_key_value(&key, version))?;
+                }
+                newer_seq = Some(version.0);
+            }
+        }
+
+        let table = builder.finalize()?;
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_value::comparator::default_comparator;
+    use tempfile::tempdir;
+
+    fn key(k: &str) -> Arc<[u8]> {
+        Arc::from(k.as_bytes())
+    }
+
+    fn keys(entries: &[Box<KeyValue>]) -> Vec<&[u8]> {
+        entries.iter().map(|kv| &kv.key[..]).collect()
+    }
+
+    #[test]
+    fn test_get_at_picks_visible_version() {
+        let table = HashMemTable::new(100, usize::MAX, default_comparator());
+        // out of order, as a replay may write them
+        table.insert(key("a"), key("three"), 3);
+        table.insert(key("a"), key("one"), 1);
+        table.delete(key("a"), 5);
+        table.insert(key("a"), key("seven"), 7);
+
+        let value_at = |seq| table.get_at(b"a", seq).map(|kv| (kv.seq, kv.is_tombstone()));
+        assert_eq!(value_at(0), None);
+        assert_eq!(value_at(1), Some((1, false)));
+        assert_eq!(value_at(4), Some((3, false)));
+        assert_eq!(value_at(5), Some((5, true)));
+        assert_eq!(value_at(6), Some((5, true)));
+        assert_eq!(table.get(b"a").unwrap().value_str(), Some("seven"));
+        assert_eq!(table.get(b"b"), None);
+        assert_eq!(table.current_length(), 4);
+    }
+
+    #[test]
+    fn test_range_at_is_sorted() {
+        let table = HashMemTable::new(100, usize::MAX, default_comparator());
+        for (seq, k) in ["k5", "k1", "k9", "k3", "k7", "k2"].into_iter().enumerate() {
+            table.insert(key(k), key("v"), seq as u64 + 1);
+        }
+        table.delete(key("k3"), 10);
+
+        let (entries, result) = table.range_at(b"k2", Some(b"k7"), u64::MAX);
+        assert_eq!(keys(&entries), vec![&b"k2"[..], b"k3", b"k5", b"k7"]);
+        // k9 showed the range ended
+        assert_eq!(result, RangeResult::FirstKeyFound);
+        // the tombstone comes back so it can shadow older tables
+        assert!(entries[1].is_tombstone());
+
+        // an earlier read sees the value under the tombstone, and nothing
+        // written after it
+        let (entries, _) = table.range_at(b"k2", Some(b"k7"), 4);
+        assert_eq!(keys(&entries), vec![&b"k3"[..], b"k5"]);
+        assert!(!entries[0].is_tombstone());
+
+        let (entries, result) = table.range_at(b"k6", Some(b"k9"), u64::MAX);
+        assert_eq!(keys(&entries), vec![&b"k7"[..], b"k9"]);
+        assert_eq!(result, RangeResult::FullSetFound);
+        let (entries, _) = table.range_at(b"k6", None, u64::MAX);
+        assert_eq!(keys(&entries), vec![&b"k7"[..], b"k9"]);
+
+        let (entries, result) = table.range_at(b"x", Some(b"z"), u64::MAX);
+        assert!(entries.is_empty());
+        assert_eq!(result, RangeResult::KeyNotFound);
+    }
+
+    #[test]
+    fn test_flush_writes_keys_in_order() -> Result<(), crate::error::MemTableError> {
+        let temp_dir = tempdir().unwrap();
+        let table = HashMemTable::new(1000, usize::MAX, default_comparator());
+        for i in (0..200).rev() {
+            table.insert(key(&format!("key-{:03}", i)), key("old"), i + 1);
+        }
+        table.insert(key("key-050"), key("new"), 500);
+        table.delete(key("key-100"), 501);
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 202,
+            ..Default::default()
+        };
+        // a snapshot at 300 still reads the old value of key-050
+        let sstable = table.flush(temp_dir.path().join("flushed.sst"), features, &[300])?;
+        let mut cursor = sstable.cursor();
+        cursor.seek_to_first()?;
+        let mut flushed = Vec::new();
+        while let Some(kv) = cursor.current() {
+            flushed.push((String::from_utf8(kv.key.to_vec()).unwrap(), kv.seq));
+            cursor.advance()?;
+        }
+
+        assert_eq!(flushed.len(), 202);
+        assert!(flushed.is_sorted_by(|a, b| a.0 < b.0 || (a.0 == b.0 && a.1 > b.1)));
+        assert_eq!(flushed[50..52], [("key-050".to_string(), 500), ("key-050".to_string(), 51)]);
+        assert!(sstable.get(b"key-100")?.is_tombstone());
+        Ok(())
+    }
+}
@@ -1,11 +1,22 @@
+mod arena;
+pub mod arena_skiplist;
 pub mod error;
+mod hash_mem_table;
 pub mod mem_table_builder;
 mod skiplist;
 mod vector_mem_table;
 
-use key_value::KeyValue;
+use arena_skiplist::ArenaSkipList;
+use hash_mem_table::HashMemTable;
+use key_value::{comparator::Comparator, KeyValue};
 use sstable::{builder::SSTableFeatures, SSTable};
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 use vector_mem_table::VectorMemTable;
 use skiplist::CrossBeam;
 
@@ -17,25 +28,114 @@ pub enum RangeResult {
 }
 
 pub trait MemTableOperations {
-    fn put(&mut self, key: String, value: String);
-    fn insert(&self, key: String, value: String);
-    fn get(&self, key: &str) -> Option<Box<KeyValue>>;
-    fn range(&self, from_m: &str, to_n: &str) -> (Vec<Box<KeyValue>>, RangeResult);
+    fn put(&mut self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64);
+    fn insert(&self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64);
+    /// Records a tombstone for `key`. `get` and `range` hand it back like any
+    /// other entry so that it can shadow older values further down the tree.
+    fn delete(&self, key: Arc<[u8]>, seq: u64);
+    /// The version of `key` with the highest sequence number.
+    fn get(&self, key: &[u8]) -> Option<Box<KeyValue>> {
+        self.get_at(key, u64::MAX)
+    }
+    /// The newest version of `key` written at or before sequence `seq`.
+    fn get_at(&self, key: &[u8], seq: u64) -> Option<Box<KeyValue>>;
+    fn range(&self, from_m: &[u8], to_n: &[u8]) -> (Vec<Box<KeyValue>>, RangeResult) {
+        self.range_at(from_m, Some(to_n), u64::MAX)
+    }
+    /// Like `range`, but only sees versions written at or before `seq`. With
+    /// `to_n` of `None` the range runs to the last key.
+    fn range_at(&self, from_m: &[u8], to_n: Option<&[u8]>, seq: u64)
+        -> (Vec<Box<KeyValue>>, RangeResult);
+    /// Whether the memtable holds `max_entries` versions, or its memory
+    /// usage has reached its byte budget.
     fn at_capacity(&self) -> bool;
     fn current_length(&self) -> usize;
     fn max_entries(&self) -> usize;
+    /// Approximate bytes held: the key and value of every version, plus a
+    /// fixed overhead per version.
+    fn memory_usage(&self) -> usize;
+    /// Writes the memtable out as a table. Besides the newest version of
+    /// each key, older versions that one of `snapshots` can still read are
+    /// kept.
     fn flush(
         &self,
         path: PathBuf,
         table_params: SSTableFeatures,
+        snapshots: &[u64],
     ) -> Result<Arc<SSTable>, crate::error::MemTableError>;
 }
 
+// rough cost of a version beyond its key and value: its sequence number and
+// kind, and the pointers of the structure holding it
+const VERSION_OVERHEAD: usize = 48;
+
+#[derive(Debug, Default)]
+struct MemoryUsage(AtomicUsize);
+
+impl MemoryUsage {
+    fn add(&self, key: &[u8], value: &[u8]) {
+        self.0
+            .fetch_add(key.len() + value.len() + VERSION_OVERHEAD, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// The newest version of every key in `[from, to]`, or from `from` on without
+// `to`. `versions` are sorted
+// by key with the newest version of each key first, and hold nothing newer
+// than the read.
+#[allow(clippy::vec_box)]
+fn range_of_sorted<'a>(
+    comparator: &dyn Comparator,
+    versions: impl IntoIterator<Item = &'a KeyValue>,
+    from: &[u8],
+    to: Option<&[u8]>,
+) -> (Vec<Box<KeyValue>>, RangeResult) {
+    let mut entries: Vec<Box<KeyValue>> = Vec::new();
+    let mut saw_to = false;
+
+    for kv in versions {
+        if comparator.compare(&kv.key, from).is_lt()
+            || entries
+                .last()
+                .is_some_and(|last| comparator.compare(&last.key, &kv.key).is_eq())
+        {
+            // before the range, or older than a version we already have
+            continue;
+        }
+        match to.map_or(std::cmp::Ordering::Less, |to| comparator.compare(&kv.key, to)) {
+            std::cmp::Ordering::Greater => {
+                // first key > to
+                return (entries, RangeResult::FirstKeyFound);
+            }
+            ordering => {
+                saw_to |= ordering.is_eq();
+                entries.push(Box::new(kv.clone()));
+            }
+        }
+    }
+
+    if entries.is_empty() {
+        (entries, RangeResult::KeyNotFound)
+    } else if saw_to {
+        (entries, RangeResult::FullSetFound)
+    } else {
+        // we found some entries but never hit to,
+        // and there was no key > to to stop us
+        (entries, RangeResult::FirstKeyFound)
+    }
+}
+
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum DataStructure {
     Vector(VectorMemTable),
-    SkipList(CrossBeam)
-
+    SkipList(CrossBeam),
+    ConcurrentHashmap(HashMemTable),
+    ArenaSkipList(ArenaSkipList),
 }
 
 impl Default for DataStructure {
@@ -50,33 +150,54 @@ pub struct MemTable {
 }
 
 impl MemTableOperations for MemTable {
-    fn put(&mut self, key: String, value: String) {
+    fn put(&mut self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
         match &mut self.inner {
             //take exclusive reference to self.inner
-            DataStructure::Vector(memtable) => memtable.put(key, value),
-            DataStructure::SkipList(memtable) => memtable.put(key, value),
+            DataStructure::Vector(memtable) => memtable.put(key, value, seq),
+            DataStructure::SkipList(memtable) => memtable.put(key, value, seq),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.put(key, value, seq),
+            DataStructure::ArenaSkipList(memtable) => memtable.put(key, value, seq),
         }
     }
-    fn insert(&self, key: String, value: String) {
+    fn insert(&self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
         match &self.inner {
             //take exclusive reference to self.inner
-            DataStructure::Vector(memtable) => memtable.insert(key, value),
-            DataStructure::SkipList(memtable) => memtable.insert(key, value),
+            DataStructure::Vector(memtable) => memtable.insert(key, value, seq),
+            DataStructure::SkipList(memtable) => memtable.insert(key, value, seq),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.insert(key, value, seq),
+            DataStructure::ArenaSkipList(memtable) => memtable.insert(key, value, seq),
         }
     }
-    fn get(&self, key: &str) -> Option<Box<KeyValue>> {
+    fn delete(&self, key: Arc<[u8]>, seq: u64) {
+        match &self.inner {
+            DataStructure::Vector(memtable) => memtable.delete(key, seq),
+            DataStructure::SkipList(memtable) => memtable.delete(key, seq),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.delete(key, seq),
+            DataStructure::ArenaSkipList(memtable) => memtable.delete(key, seq),
+        }
+    }
+    fn get_at(&self, key: &[u8], seq: u64) -> Option<Box<KeyValue>> {
         match &self.inner {
             //shared reference
-            DataStructure::Vector(memtable) => memtable.get(key),
-            DataStructure::SkipList(memtable) => memtable.get(key),
+            DataStructure::Vector(memtable) => memtable.get_at(key, seq),
+            DataStructure::SkipList(memtable) => memtable.get_at(key, seq),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.get_at(key, seq),
+            DataStructure::ArenaSkipList(memtable) => memtable.get_at(key, seq),
         }
     }
 
-    fn range(&self, from_m: &str, to_n: &str) -> (Vec<Box<KeyValue>>, RangeResult) {
+    fn range_at(
+        &self,
+        from_m: &[u8],
+        to_n: Option<&[u8]>,
+        seq: u64,
+    ) -> (Vec<Box<KeyValue>>, RangeResult) {
         match &self.inner {
             //shared reference
-            DataStructure::Vector(memtable) => memtable.range(from_m, to_n),
-            DataStructure::SkipList(memtable) => memtable.range(from_m, to_n),
+            DataStructure::Vector(memtable) => memtable.range_at(from_m, to_n, seq),
+            DataStructure::SkipList(memtable) => memtable.range_at(from_m, to_n, seq),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.range_at(from_m, to_n, seq),
+            DataStructure::ArenaSkipList(memtable) => memtable.range_at(from_m, to_n, seq),
         }
     }
 
@@ -84,6 +205,8 @@ impl MemTableOperations for MemTable {
         match &self.inner {
             DataStructure::Vector(memtable) => memtable.at_capacity(),
             DataStructure::SkipList(memtable) => memtable.at_capacity(),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.at_capacity(),
+            DataStructure::ArenaSkipList(memtable) => memtable.at_capacity(),
         }
     }
 
@@ -91,6 +214,8 @@ impl MemTableOperations for MemTable {
         match &self.inner {
             DataStructure::Vector(memtable) => memtable.current_length(),
             DataStructure::SkipList(memtable) => memtable.current_length(),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.current_length(),
+            DataStructure::ArenaSkipList(memtable) => memtable.current_length(),
         }
     }
 
@@ -98,6 +223,17 @@ impl MemTableOperations for MemTable {
         match &self.inner {
             DataStructure::Vector(memtable) => memtable.max_entries(),
             DataStructure::SkipList(memtable) => memtable.max_entries(),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.max_entries(),
+            DataStructure::ArenaSkipList(memtable) => memtable.max_entries(),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        match &self.inner {
+            DataStructure::Vector(memtable) => memtable.memory_usage(),
+            DataStructure::SkipList(memtable) => memtable.memory_usage(),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.memory_usage(),
+            DataStructure::ArenaSkipList(memtable) => memtable.memory_usage(),
         }
     }
 
@@ -105,10 +241,13 @@ impl MemTableOperations for MemTable {
         &self,
         path: PathBuf,
         table_params: SSTableFeatures,
+        snapshots: &[u64],
     ) -> Result<Arc<SSTable>, crate::error::MemTableError> {
         match &self.inner {
-            DataStructure::Vector(memtable) => memtable.flush(path, table_params),
-            DataStructure::SkipList(memtable) => memtable.flush(path, table_params),
+            DataStructure::Vector(memtable) => memtable.flush(path, table_params, snapshots),
+            DataStructure::SkipList(memtable) => memtable.flush(path, table_params, snapshots),
+            DataStructure::ConcurrentHashmap(memtable) => memtable.flush(path, table_params, snapshots),
+            DataStructure::ArenaSkipList(memtable) => memtable.flush(path, table_params, snapshots),
         }
     }
 }
@@ -1,23 +1,43 @@
+use key_value::comparator::{default_comparator, Comparator};
+use std::sync::Arc;
+
+use crate::arena_skiplist::ArenaSkipList;
 use crate::skiplist::CrossBeam;
 use crate::{MemTable, DataStructure};
-use crate::VectorMemTable;
+use crate::{HashMemTable, VectorMemTable};
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum MemTableType {
+    /// Unsorted, sorted on every range read and flush. Only for testing.
     Vector,
+    /// Kept in key order; the general purpose choice.
+    #[default]
     SkipList,
+    /// A concurrent hash map, sorted once at flush time. Fastest for
+    /// write-heavy loads whose reads are point lookups; range reads sort on
+    /// demand.
     ConcurrentHashmap,
+    /// A skiplist of its own that keeps keys and values in a per-memtable
+    /// arena rather than one allocation each, and counts the memory it
+    /// takes from the arena exactly.
+    ArenaSkipList,
 }
 
+#[derive(Debug, Clone)]
 pub struct MemTableBuilder {
     memtable_type: MemTableType,
     max_entries: usize,
+    max_bytes: usize,
+    comparator: Arc<dyn Comparator>,
 }
 
 impl Default for MemTableBuilder {
     fn default() -> Self {
         Self {
-            memtable_type: MemTableType::SkipList,
+            memtable_type: MemTableType::default(),
             max_entries: 1000,
+            max_bytes: usize::MAX,
+            comparator: default_comparator(),
         }
     }
 }
@@ -32,13 +52,36 @@ impl MemTableBuilder {
         self
     }
 
+    /// Counts the memtable as full once its approximate memory usage
+    /// reaches `max_bytes`, even short of `max_entries`.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
     pub fn build(self) -> MemTable {
         let inner = match self.memtable_type {
-            MemTableType::Vector => DataStructure::Vector(VectorMemTable::new(self.max_entries)),
-            MemTableType::SkipList => DataStructure::SkipList(CrossBeam::new(self.max_entries)),
-            MemTableType::ConcurrentHashmap => {
-                unimplemented!("Concurrent Hashmap not impemented yet")
+            MemTableType::Vector => {
+                DataStructure::Vector(VectorMemTable::new(self.max_entries, self.max_bytes, self.comparator))
+            }
+            MemTableType::SkipList => {
+                DataStructure::SkipList(CrossBeam::new(self.max_entries, self.max_bytes, self.comparator))
             }
+            MemTableType::ConcurrentHashmap => DataStructure::ConcurrentHashmap(HashMemTable::new(
+                self.max_entries,
+                self.max_bytes,
+                self.comparator,
+            )),
+            MemTableType::ArenaSkipList => DataStructure::ArenaSkipList(ArenaSkipList::new(
+                self.max_entries,
+                self.max_bytes,
+                self.comparator,
+            )),
         };
         MemTable { inner }
     }
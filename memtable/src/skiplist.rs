@@ -1,24 +1,95 @@
-use crate::{MemTableOperations, RangeResult};
+use crate::{MemTableOperations, MemoryUsage, RangeResult};
 use crossbeam_skiplist::SkipMap;
-use key_value::KeyValue;
+use key_value::{comparator::Comparator, version_is_needed, KeyValue, ValueKind};
 use sstable::{
     builder::SSTableFeatures, streamed_builder::StreamedSSTableBuilder, SSTable
 };
-use std::{path::PathBuf, sync::Arc};
+use std::{cmp::Ordering, path::PathBuf, sync::Arc};
+
+// Every version of a key is kept. Versions sort by key under the memtable's
+// comparator, then newest first, so the first entry at or after (key, MAX) is
+// the latest version of that key.
+#[derive(Debug, Clone)]
+struct VersionedKey {
+    key: Arc<[u8]>,
+    seq: u64,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Ord for VersionedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&self.key, &other.key)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for VersionedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for VersionedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for VersionedKey {}
+
+// a `None` value is a tombstone
+fn to_key_value(
+    VersionedKey { key, seq, .. }: &VersionedKey,
+    value: &Option<Arc<[u8]>>,
+) -> KeyValue {
+    match value {
+        Some(value) => KeyValue {
+            key: key.clone(),
+            value: value.clone(),
+            kind: ValueKind::Put,
+            seq: *seq,
+        },
+        None => KeyValue {
+            key: key.clone(),
+            value: Arc::from(&[][..]),
+            kind: ValueKind::Delete,
+            seq: *seq,
+        },
+    }
+}
 
 #[derive(Debug)]
 pub struct CrossBeam {
-    inner: SkipMap<String, String>,
+    inner: SkipMap<VersionedKey, Option<Arc<[u8]>>>,
     pub max_entries: usize,
+    max_bytes: usize,
+    usage: MemoryUsage,
+    comparator: Arc<dyn Comparator>,
 }
 
 impl CrossBeam {
-    pub fn new(max_entries: usize) -> Self {
+    pub fn new(max_entries: usize, max_bytes: usize, comparator: Arc<dyn Comparator>) -> Self {
         CrossBeam {
             inner: SkipMap::new(),
             max_entries,
+            max_bytes,
+            usage: MemoryUsage::default(),
+            comparator,
+        }
+    }
+
+    fn versioned(&self, key: impl Into<Arc<[u8]>>, seq: u64) -> VersionedKey {
+        VersionedKey {
+            key: key.into(),
+            seq,
+            comparator: self.comparator.clone(),
         }
     }
+
+    fn same_key(&self, a: &[u8], b: &[u8]) -> bool {
+        self.comparator.compare(a, b).is_eq()
+    }
 }
 
 impl MemTableOperations for CrossBeam {
@@ -27,41 +98,52 @@ impl MemTableOperations for CrossBeam {
     }
 
     fn at_capacity(&self) -> bool {
-        self.current_length() >= self.max_entries
+        self.current_length() >= self.max_entries || self.memory_usage() >= self.max_bytes
     }
-    fn put(&mut self, key: String, value: String) {
-        self.inner.insert(key, value);
+    fn put(&mut self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.usage.add(&key, &value);
+        self.inner.insert(self.versioned(key, seq), Some(value));
     }
 
-    fn insert(&self, key: String, value: String) {
-        self.inner.insert(key, value);
+    fn insert(&self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.usage.add(&key, &value);
+        self.inner.insert(self.versioned(key, seq), Some(value));
     }
 
-    fn get(&self, key: &str) -> Option<Box<KeyValue>> {
-        self.inner.get(key).map(|entry| {
-            Box::new(KeyValue {
-                key: entry.key().clone(),
-                value: entry.value().clone(),
-            })
-        })
+    fn delete(&self, key: Arc<[u8]>, seq: u64) {
+        self.usage.add(&key, &[]);
+        self.inner.insert(self.versioned(key, seq), None);
     }
 
-    fn range(&self, from: &str, to: &str) -> (Vec<Box<KeyValue>>, RangeResult) {
-        let mut results = Vec::new();
+    fn get_at(&self, key: &[u8], seq: u64) -> Option<Box<KeyValue>> {
+        self.inner
+            .range(self.versioned(key, seq)..)
+            .next()
+            .filter(|entry| self.same_key(&entry.key().key, key))
+            .map(|entry| Box::new(to_key_value(entry.key(), entry.value())))
+    }
+
+    fn range_at(
+        &self,
+        from: &[u8],
+        to: Option<&[u8]>,
+        seq: u64,
+    ) -> (Vec<Box<KeyValue>>, RangeResult) {
+        let mut results: Vec<Box<KeyValue>> = Vec::new();
         let mut saw_to = false;
 
-        for entry in self.inner.range(from.to_string()..) {
-            let k = entry.key();
-            if k.as_str() <= to {
-                results.push(Box::new(KeyValue {
-                    key: k.clone(),
-                    value: entry.value().clone(),
-                }));
-                if k == to {
-                    saw_to = true;
+        for entry in self.inner.range(self.versioned(from, u64::MAX)..) {
+            let VersionedKey { key: k, seq: version, .. } = entry.key();
+            if *version > seq || results.last().is_some_and(|last| self.same_key(&last.key, k)) {
+                // too new for this read, or older than a version we already have
+                continue;
+            }
+            match to.map_or(Ordering::Less, |to| self.comparator.compare(k, to)) {
+                Ordering::Greater => return (results, RangeResult::FirstKeyFound),
+                ordering => {
+                    results.push(Box::new(to_key_value(entry.key(), entry.value())));
+                    saw_to |= ordering.is_eq();
                 }
-            } else {
-                return (results, RangeResult::FirstKeyFound);
             }
         }
 
@@ -78,15 +160,23 @@ impl MemTableOperations for CrossBeam {
         &self,
         path: PathBuf,
         table_params: SSTableFeatures,
+        snapshots: &[u64],
     ) -> Result<Arc<SSTable>, crate::error::MemTableError> {
-        let mut builder = StreamedSSTableBuilder::new(table_params, true, &path)?;
+        let mut builder = StreamedSSTableBuilder::new(table_params, true, &path)?
+            .with_comparator(self.comparator.clone());
 
+        // versions a snapshot can no longer see are left behind
+        let mut newer: Option<VersionedKey> = None;
         for entry in self.inner.iter() {
-            let kv = KeyValue {
-                key: entry.key().clone(),
-                value: entry.value().clone(),
-            };
-            builder.add_from_kv(kv)?;
+            let VersionedKey { key, seq, .. } = entry.key();
+            let newer_seq = newer
+                .as_ref()
+                .filter(|newer| self.same_key(&newer.key, key))
+                .map(|newer| newer.seq);
+            if version_is_needed(*seq, newer_seq, snapshots) {
+                builder.add_from_kv(to_key_value(entry.key(), entry.value()))?;
+            }
+            newer = Some(entry.key().clone());
         }
 
         let table = builder.finalize()?;
@@ -97,4 +187,8 @@ impl MemTableOperations for CrossBeam {
         self.max_entries
     }
 
+    fn memory_usage(&self) -> usize {
+        self.usage.get()
+    }
+
 }
@@ -1,113 +1,194 @@
-use key_value::KeyValue;
+use key_value::{
+    comparator::{default_comparator, Comparator},
+    version_is_needed, KeyValue, ValueKind,
+};
 use sstable::{
     builder::{SSTableBuilder, SSTableFeatures},
     SSTable,
 };
-use std::collections::BTreeMap;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
 
-use crate::{MemTableOperations, RangeResult};
+use crate::{range_of_sorted, MemTableOperations, MemoryUsage, RangeResult};
 
 #[derive(Debug)]
 pub struct VectorMemTable {
-    data: Vec<KeyValue>,
+    data: RwLock<Vec<KeyValue>>,
     max_entries: usize,
+    max_bytes: usize,
+    usage: MemoryUsage,
+    comparator: Arc<dyn Comparator>,
 }
 
 impl VectorMemTable {
-    pub fn new(max_entries: usize) -> Self {
+    pub fn new(max_entries: usize, max_bytes: usize, comparator: Arc<dyn Comparator>) -> Self {
         Self {
-            data: Vec::with_capacity(max_entries),
+            data: RwLock::new(Vec::new()),
             max_entries,
+            max_bytes,
+            usage: MemoryUsage::default(),
+            comparator,
         }
     }
+
+    // versions written at or before `seq`, in key order with the newest
+    // version of each key first
+    fn sorted_versions<'a>(&self, data: &'a [KeyValue], seq: u64) -> Vec<&'a KeyValue> {
+        let mut versions: Vec<&KeyValue> = data.iter().filter(|kv| kv.seq <= seq).collect();
+        versions.sort_by(|a, b| {
+            self.comparator
+                .compare(&a.key, &b.key)
+                .then(b.seq.cmp(&a.seq))
+        });
+        versions
+    }
 }
 
 impl Default for VectorMemTable {
     fn default() -> Self {
         Self {
-            data: Vec::with_capacity(1000),
-            max_entries: 1000
+            data: RwLock::new(Vec::with_capacity(1000)),
+            max_entries: 1000,
+            max_bytes: usize::MAX,
+            usage: MemoryUsage::default(),
+            comparator: default_comparator(),
         }
     }
 }
 
 impl MemTableOperations for VectorMemTable {
-    fn put(&mut self, key: String, value: String) {
-        self.data.push(KeyValue { key, value })
+    fn put(&mut self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.usage.add(&key, &value);
+        self.data.get_mut().unwrap().push(KeyValue {
+            key,
+            value,
+            kind: ValueKind::Put,
+            seq,
+        })
+    }
+    fn insert(&self, key: Arc<[u8]>, value: Arc<[u8]>, seq: u64) {
+        self.usage.add(&key, &value);
+        self.data.write().unwrap().push(KeyValue {
+            key,
+            value,
+            kind: ValueKind::Put,
+            seq,
+        })
     }
-    fn insert(&self, _key: String, _value: String) {
-        todo!()
+    fn delete(&self, key: Arc<[u8]>, seq: u64) {
+        self.usage.add(&key, &[]);
+        self.data.write().unwrap().push(KeyValue {
+            key,
+            value: Arc::from(&[][..]),
+            kind: ValueKind::Delete,
+            seq,
+        })
     }
 
-    fn get(&self, key: &str) -> Option<Box<KeyValue>> {
+    fn get_at(&self, key: &[u8], seq: u64) -> Option<Box<KeyValue>> {
         self.data
+            .read()
+            .unwrap()
             .iter()
-            .find(|kv| kv.key == key)
+            .filter(|kv| self.comparator.compare(&kv.key, key).is_eq() && kv.seq <= seq)
+            .max_by_key(|kv| kv.seq)
             .map(|kv| Box::new(kv.clone()))
     }
 
-    fn range(&self, from_m: &str, to_n: &str) -> (Vec<Box<KeyValue>>, RangeResult) {
-        let mut deduped = BTreeMap::new();
-        for kv in &self.data {
-            deduped.insert(kv.key.clone(), kv.clone());
-        }
-
-        let mut entries = Vec::new();
-        let mut saw_to = false;
-
-        // scan from the first key ≥ from_m
-        for (key, kv) in deduped.range(from_m.to_owned()..) {
-            if key.as_str() <= to_n {
-                entries.push(Box::new(kv.clone()));
-                if key == to_n {
-                    saw_to = true;
-                }
-            } else {
-                // first key > to_n
-                return (entries, RangeResult::FirstKeyFound);
-            }
-        }
-
-        if entries.is_empty() {
-            (entries, RangeResult::KeyNotFound)
-        } else if saw_to {
-            (entries, RangeResult::FullSetFound)
-        } else {
-            // we found some entries but never hit to_n,
-            // and there was no key > to_n to stop us
-            (entries, RangeResult::FirstKeyFound)
-        }
+    fn range_at(
+        &self,
+        from_m: &[u8],
+        to_n: Option<&[u8]>,
+        seq: u64,
+    ) -> (Vec<Box<KeyValue>>, RangeResult) {
+        let data = self.data.read().unwrap();
+        range_of_sorted(self.comparator.as_ref(), self.sorted_versions(&data, seq), from_m, to_n)
     }
 
     fn at_capacity(&self) -> bool {
-        self.data.len() >= self.max_entries
+        self.current_length() >= self.max_entries || self.memory_usage() >= self.max_bytes
     }
 
     fn current_length(&self) -> usize {
-        self.data.len()
+        self.data.read().unwrap().len()
     }
 
     fn max_entries(&self) -> usize {
         self.max_entries
     }
 
+    fn memory_usage(&self) -> usize {
+        self.usage.get()
+    }
+
     fn flush(
         &self,
         path: PathBuf,
         table_params: SSTableFeatures,
+        snapshots: &[u64],
     ) -> Result<Arc<SSTable>, crate::error::MemTableError> {
-        let mut builder = SSTableBuilder::new(table_params, &path)?;
-
-        let mut deduped: BTreeMap<String, KeyValue> = BTreeMap::new();
-        for kv in &self.data {
-            deduped.insert(kv.key.clone(), kv.clone());
-        }
-
-        for (_key, kv) in deduped {
-            builder.add_from_kv(kv)?;
+        let mut builder =
+            SSTableBuilder::new(table_params, &path)?.with_comparator(self.comparator.clone());
+
+        let data = self.data.read().unwrap();
+        let mut newer: Option<&KeyValue> = None;
+        for kv in self.sorted_versions(&data, u64::MAX) {
+            let newer_seq = newer.filter(|n| self.comparator.compare(&n.key, &kv.key).is_eq()).map(|n| n.seq);
+            if version_is_needed(kv.seq, newer_seq, snapshots) {
+                builder.add_from_kv(kv.clone())?;
+            }
+            newer = Some(kv);
         }
         let table = builder.build()?;
         Ok(table)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_inserts_and_reads() {
+        let table = Arc::new(VectorMemTable::new(100_000, usize::MAX, default_comparator()));
+        let writers: Vec<_> = (0..4u64)
+            .map(|writer| {
+                let table = table.clone();
+                thread::spawn(move || {
+                    for i in 0..500u64 {
+                        let seq = writer * 500 + i + 1;
+                        let key = format!("key-{:04}", seq);
+                        table.insert(Arc::from(key.as_bytes()), Arc::from(&b"value"[..]), seq);
+                    }
+                })
+            })
+            .collect();
+        // readers share the lock with the writers and only ever see whole
+        // entries
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let table = table.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let (entries, _) = table.range_at(b"key-", None, u64::MAX);
+                        assert!(entries.iter().all(|kv| kv.value_str() == Some("value")));
+                        assert!(entries.is_sorted_by(|a, b| a.key < b.key));
+                    }
+                })
+            })
+            .collect();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(table.current_length(), 2000);
+        let (entries, _) = table.range(b"key-0001", b"key-2000");
+        assert_eq!(entries.len(), 2000);
+        assert_eq!(table.get(b"key-1234").unwrap().seq, 1234);
+        // a read before a write doesn't see it
+        assert!(table.get_at(b"key-1234", 1233).is_none());
+    }
+}
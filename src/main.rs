@@ -1,6 +1,11 @@
 use std::sync::Arc;
 
-use lsm::lsm_database::LsmDatabase;
+use lsm::{
+    comparator::{decode_i64, encode_i64},
+    lsm_database::LsmDatabase,
+    options::DbOptions,
+    MemTableType,
+};
 use tokio::io::{AsyncBufReadExt, BufReader};
 
 #[derive(Default, Debug)]
@@ -21,7 +26,20 @@ pub struct WorkloadStats {
 #[tokio::main(flavor = "multi_thread", worker_threads = 20)]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let parent_directory = "./data".to_string();
-    let byron = Arc::new(LsmDatabase::new(parent_directory, None));
+    // the memtable structure to run the workload against
+    let memtable_type = match std::env::args().nth(1).as_deref() {
+        None | Some("skiplist") => MemTableType::SkipList,
+        Some("hashmap") => MemTableType::ConcurrentHashmap,
+        Some("vector") => MemTableType::Vector,
+        Some("arena") => MemTableType::ArenaSkipList,
+        Some(other) => return Err(format!("unknown memtable type {:?}", other).into()),
+    };
+    let options = DbOptions {
+        memtable_type,
+        ..DbOptions::default()
+    };
+    let byron = Arc::new(LsmDatabase::open_with_options(parent_directory, None, options).await?);
+    let started = std::time::Instant::now();
 
     let file = tokio::fs::File::open("workload.txt".to_string()).await?;
     let reader = BufReader::new(file);
@@ -38,7 +56,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "p" if parts.len() == 3 => {
                 let key = parts[1].parse::<i64>()?;
                 let value = parts[2].parse::<i64>()?;
-                match byron.put(key.to_string(), value.to_string()).await {
+                match byron.put(encode_i64(key), value.to_string()).await {
                     Ok(_) => stats.put_success += 1,
                     Err(e) => {
                         stats.put_fail += 1;
@@ -47,10 +65,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
             "g" if parts.len() == 2 => match parts[1].parse::<i64>() {
-                Ok(key) => match byron.get(key.to_string()).await {
+                Ok(key) => match byron.get(encode_i64(key)).await {
                     Ok(target) => {
                         stats.get_success += 1;
-                        println!("GET {} -> {}", key, target.value);
+                        println!("GET {} -> {}", key, String::from_utf8_lossy(&target.value));
                     }
                     Err(e) => {
                         stats.get_fail += 1;
@@ -60,7 +78,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Err(_) => stats.parse_errors += 1,
             },
             "d" if parts.len() == 2 => match parts[1].parse::<i64>() {
-                Ok(key) => match  byron.delete(key.to_string()).await {
+                Ok(key) => match  byron.delete(encode_i64(key)).await {
                     Ok(_) => stats.delete_success += 1,
                     Err(e) => {
                         stats.delete_fail += 1;
@@ -73,10 +91,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let from = parts[1].parse::<i64>()?;
                 let to = parts[2].parse::<i64>()?;
 
-                match byron.range(from.to_string(), to.to_string()).await {
+                match byron.range(encode_i64(from), encode_i64(to)).await {
                     Ok(target) => {
                         for value in target {
-                            println!("{} -> {}", value.key, value.value);
+                            println!(
+                                "{} -> {}",
+                                decode_i64(&value.key).unwrap_or_default(),
+                                String::from_utf8_lossy(&value.value)
+                            );
                         }
                         stats.range_success += 1;
                     }
@@ -92,8 +114,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
     }
+    // flushes run in the background; count them in the elapsed time
+    byron.wait_for_flushes().await?;
 
     println!("\n=== Workload Summary ===");
+    println!("Memtable:               {:?}", memtable_type);
+    println!("Elapsed:                {:?}", started.elapsed());
     println!("Total lines:            {}", stats.total_lines);
     println!(
         "PUT:    {} success / {} fail",
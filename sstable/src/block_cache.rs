@@ -0,0 +1,231 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use bytes::Bytes;
+use lru::LruCache;
+
+use crate::table_format::TableIndex;
+
+const SHARD_BITS: u32 = 4;
+const SHARDS: usize = 1 << SHARD_BITS;
+
+static NEXT_TABLE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A process-wide unique id for a table, used to key its cached blocks. Ids
+/// are never reused, so blocks of a deleted table just age out.
+pub(crate) fn next_table_id() -> u64 {
+    NEXT_TABLE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Bytes of block data held right now.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Cached {
+    Block(Bytes),
+    // a decoded index partition, charged at its encoded size
+    Index(Arc<TableIndex>, usize),
+}
+
+impl Cached {
+    fn charge(&self) -> usize {
+        match self {
+            Cached::Block(block) => block.len(),
+            Cached::Index(_, size) => *size,
+        }
+    }
+}
+
+impl From<Bytes> for Cached {
+    fn from(block: Bytes) -> Self {
+        Cached::Block(block)
+    }
+}
+
+#[derive(Debug)]
+struct Shard {
+    blocks: LruCache<(u64, usize), Cached>,
+    usage: usize,
+    capacity: usize,
+}
+
+impl Shard {
+    fn insert(&mut self, key: (u64, usize), entry: impl Into<Cached>) {
+        let entry = entry.into();
+        if entry.charge() > self.capacity {
+            return;
+        }
+        self.usage += entry.charge();
+        if let Some(old) = self.blocks.put(key, entry) {
+            self.usage -= old.charge();
+        }
+        while self.usage > self.capacity
+            && let Some((_, evicted)) = self.blocks.pop_lru()
+        {
+            self.usage -= evicted.charge();
+        }
+    }
+}
+
+/// Decoded data blocks keyed by (table id, block index), shared by every
+/// table of a database. The byte budget is split evenly over a fixed number
+/// of shards, each with its own lock and LRU order, so concurrent readers
+/// rarely contend.
+///
+/// Partitions of partitioned table indexes live here too, under an id of
+/// their own so they never collide with data blocks.
+#[derive(Debug)]
+pub struct BlockCache {
+    shards: Vec<Mutex<Shard>>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BlockCache {
+    /// A cache holding up to `capacity` bytes of block data. A block larger
+    /// than a shard's share is never cached.
+    pub fn new(capacity: usize) -> Self {
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    blocks: LruCache::unbounded(),
+                    usage: 0,
+                    capacity: capacity / SHARDS,
+                })
+            })
+            .collect();
+        Self {
+            shards,
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, table: u64, block: usize) -> Option<Bytes> {
+        match self.lookup(table, block) {
+            Some(Cached::Block(block)) => Some(block),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, table: u64, block: usize, data: Bytes) {
+        self.shard(table, block)
+            .lock()
+            .unwrap()
+            .insert((table, block), data);
+    }
+
+    pub(crate) fn get_index(&self, index: u64, partition: usize) -> Option<Arc<TableIndex>> {
+        match self.lookup(index, partition) {
+            Some(Cached::Index(partition, _)) => Some(partition),
+            _ => None,
+        }
+    }
+
+    /// Caches a decoded index partition, charged at `size`, its encoded size.
+    pub(crate) fn insert_index(
+        &self,
+        index: u64,
+        partition: usize,
+        entries: Arc<TableIndex>,
+        size: usize,
+    ) {
+        self.shard(index, partition)
+            .lock()
+            .unwrap()
+            .insert((index, partition), Cached::Index(entries, size));
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            usage: self
+                .shards
+                .iter()
+                .map(|shard| shard.lock().unwrap().usage)
+                .sum(),
+            capacity: self.capacity,
+        }
+    }
+
+    fn lookup(&self, table: u64, block: usize) -> Option<Cached> {
+        let found = self
+            .shard(table, block)
+            .lock()
+            .unwrap()
+            .blocks
+            .get(&(table, block))
+            .cloned();
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    fn shard(&self, table: u64, block: usize) -> &Mutex<Shard> {
+        let hash = (table.rotate_left(32) ^ block as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        &self.shards[(hash >> (64 - SHARD_BITS)) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(len: usize) -> Bytes {
+        Bytes::from(vec![7u8; len])
+    }
+
+    #[test]
+    fn test_hits_and_misses_are_counted() {
+        let cache = BlockCache::new(SHARDS * 1024);
+        assert!(cache.get(1, 0).is_none());
+        cache.insert(1, 0, block(100));
+        assert_eq!(cache.get(1, 0).map(|b| b.len()), Some(100));
+        assert!(cache.get(2, 0).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(stats.usage, 100);
+    }
+
+    #[test]
+    fn test_least_recently_used_block_is_evicted() {
+        // every block of one table lands in some shard; fill one shard past
+        // its share and check the usage never goes over
+        let cache = BlockCache::new(SHARDS * 1000);
+        for block_idx in 0..200 {
+            cache.insert(9, block_idx, block(300));
+        }
+        let stats = cache.stats();
+        assert!(stats.usage <= stats.capacity);
+        assert!(cache.shards.iter().all(|s| s.lock().unwrap().usage <= 1000));
+
+        let mut shard = Shard {
+            blocks: LruCache::unbounded(),
+            usage: 0,
+            capacity: 1000,
+        };
+        for block_idx in 0..3 {
+            shard.insert((1, block_idx), block(300));
+        }
+        shard.blocks.get(&(1, 0));
+        shard.insert((1, 3), block(300));
+        assert!(shard.blocks.contains(&(1, 0)));
+        assert!(!shard.blocks.contains(&(1, 1)));
+        assert_eq!(shard.usage, 900);
+
+        shard.insert((1, 4), block(5000));
+        assert!(!shard.blocks.contains(&(1, 4)));
+    }
+}
@@ -1,19 +1,38 @@
-use std::{io::{Cursor, Read}, sync::Arc};
-use integer_encoding::VarIntReader;
+use bytes::Bytes;
+use std::path::PathBuf;
 use key_value::{key_value_pair::DeltaEncodedKV, KeyValue};
 
+use crate::error::SSTableError;
+
 pub struct SSTableBlockIterator {
     offset: usize,
     previous: Option<KeyValue>,
-    block: Arc<[u8]>
+    block: Bytes,
+    file: PathBuf,
+    block_idx: usize,
+    block_start: usize,
 }
 
 impl  SSTableBlockIterator {
-    pub fn new(block:Arc<[u8]>) -> Self {
+    pub fn new(block: Bytes, file: PathBuf, block_idx: usize, block_start: usize) -> Self {
         Self {
             offset: 0,
             previous: None,
-            block
+            block,
+            file,
+            block_idx,
+            block_start,
+        }
+    }
+
+    fn corruption(&mut self) -> SSTableError {
+        let offset = self.block_start + self.offset;
+        // nothing after a bad entry can be decoded, so stop here
+        self.offset = self.block.len();
+        SSTableError::Corruption {
+            file: self.file.clone(),
+            block: self.block_idx,
+            offset,
         }
     }
 }
@@ -21,43 +40,21 @@ impl  SSTableBlockIterator {
 
 
 impl Iterator for SSTableBlockIterator {
-    type Item = KeyValue;
+    type Item = Result<KeyValue, SSTableError>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.offset >= self.block.len() {
             return None;
         }
-        let mut cursor = Cursor::new(&self.block[self.offset..]);
-        let start_offset = self.offset;
-        let shared_bytes = cursor.read_varint().ok()?;
-        let unshared_bytes = cursor.read_varint().ok()?;
-        let value_bytes = cursor.read_varint().ok()?;
-
-        let remaining = self.block.len() - start_offset;
-        if remaining < (cursor.position() as usize + unshared_bytes + value_bytes) {
-            return None;
-        }
 
-        let mut key_delta = vec![0u8; unshared_bytes];
-        if cursor.read_exact(&mut key_delta).is_err() {
-            return None;
-        }
-        let mut value = vec![0u8; value_bytes];
-        if cursor.read_exact(&mut value).is_err() {
-            return None;
-        }
-
-        self.offset += cursor.position() as usize;
-
-        let dkv = DeltaEncodedKV {
-            shared_bytes,
-            unshared_bytes,
-            value_bytes,
-            key_delta: key_delta.into_boxed_slice(),
-            value: value.into_boxed_slice(),
+        let Some((dkv, read)) = DeltaEncodedKV::decode(&self.block[self.offset..]) else {
+            return Some(Err(self.corruption()));
+        };
+        let Some(current) = dkv.reverse(self.previous.take()) else {
+            return Some(Err(self.corruption()));
         };
-        let current = dkv.reverse(self.previous.clone())?;
 
+        self.offset += read;
         self.previous = Some(current.clone());
-        Some(current)
+        Some(Ok(current))
     }
 }
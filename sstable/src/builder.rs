@@ -1,12 +1,23 @@
-use crate::{error::SSTableError, SSTable};
-use bloomfilter::Bloom;
-use key_value::{key_value_pair::DeltaEncodedKV, KeyValue};
+use crate::{
+    block_cache::next_table_id,
+    error::SSTableError,
+    filter::{default_filter_policy, key_hash, FilterPolicy, TableFilter},
+    learned_index::{LearnedIndex, LearnedIndexBuilder},
+    range_filter::{RangeFilter, RangeFilterBuilder},
+    table_format::{self, TableIndex, TableMeta, BLOCK_TRAILER_SIZE},
+    map_file, prefix::PrefixExtractor, sync_new_file, KeyRange, SSTable, TableReadOptions,
+};
+use key_value::{
+    comparator::{default_comparator, BytewiseComparator, Comparator},
+    key_value_pair::DeltaEncodedKV,
+    KeyValue,
+};
 use std::{
     collections::HashMap,
     fs::{self, File},
     io::{BufWriter, Write},
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    sync::{atomic::AtomicBool, Arc, Mutex},
 };
 
 const BLOCK_SIZE: usize = 4096; // 4KB block size
@@ -16,32 +27,69 @@ const RESTART_INTERVAL: usize = 16;
 pub struct SSTableFeatures {
     pub item_count: usize,
     pub fpr: f64,
+    /// How the finished table reads its file back.
+    pub read_options: TableReadOptions,
+    /// Builds the table's key filter. `None` uses the default blocked bloom
+    /// filter.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// Also builds a range filter, letting short range scans skip the table.
+    /// Dropped for tables not in bytewise key order.
+    pub range_filter: bool,
+    /// Also adds each key's prefix to the key filter, letting prefix scans
+    /// skip the table.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Cuts the index into partitions of this many data blocks, which stay
+    /// on disk and are read through the block cache when a lookup needs
+    /// them. `None` keeps the whole index in memory.
+    pub index_partition_blocks: Option<usize>,
+    /// Fits a learned index over the table's keys, off by at most this many
+    /// restart runs, that narrows the fence pointer and restart point
+    /// searches of lookups. Dropped for tables not in bytewise key order.
+    pub learned_index: Option<usize>,
 }
 
 pub struct SSTableBuilder {
-    pub fence_pointers: Vec<(Arc<str>, usize)>,
+    pub fence_pointers: Vec<(Arc<[u8]>, usize)>,
     pub last_key: Option<KeyValue>,
     pub file_name: PathBuf,
     pub blocks: Vec<Vec<DeltaEncodedKV>>, // Store entries in blocks
     pub current_block: Vec<DeltaEncodedKV>, // Current block being built
     pub current_block_size: usize,        // Current block size in bytes
-    pub page_hash_indices: Vec<HashMap<String, usize>>, // One hash index per block
+    pub page_hash_indices: Vec<HashMap<Arc<[u8]>, usize>>, // One hash index per block
     pub current_offset: usize,            // File offset
     pub restart_indices: Vec<Vec<usize>>, // Restart indices for each block
-    pub filter: Option<Bloom<String>>,
+    pub filter_policy: Arc<dyn FilterPolicy>,
+    pub fpr: f64,
+    pub key_hashes: Vec<u64>,
     pub entry_count: usize,
+    pub max_sequence: u64,
+    pub comparator: Arc<dyn Comparator>,
+    pub read_options: TableReadOptions,
+    // smallest and largest key added so far
+    pub key_range: Option<KeyRange>,
+    range_filter: Option<RangeFilterBuilder>,
+    learned_index: Option<LearnedIndexBuilder>,
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    pub index_partition_blocks: Option<usize>,
 }
 
 impl SSTableBuilder {
     pub fn new(
-        SSTableFeatures { item_count, fpr }: SSTableFeatures,
+        SSTableFeatures {
+            item_count,
+            fpr,
+            read_options,
+            filter_policy,
+            range_filter,
+            prefix_extractor,
+            index_partition_blocks,
+            learned_index,
+        }: SSTableFeatures,
         file_name: &Path,
     ) -> Result<Self, SSTableError> {
         if fpr <= 0.0 || fpr >= 1.0 {
             return Err(SSTableError::InvalidFalsePositiveRate(fpr));
         }
-        let filter = Bloom::new_for_fp_rate(item_count, fpr)
-            .map_err(|e| SSTableError::BloomFilterError(e.to_string()))?;
 
         Ok(Self {
             fence_pointers: Vec::new(),
@@ -53,23 +101,57 @@ impl SSTableBuilder {
             page_hash_indices: Vec::new(),
             current_offset: 4, // "SSTB"
             restart_indices: Vec::new(),
-            filter: Some(filter),
-            entry_count: 0
+            filter_policy: filter_policy.unwrap_or_else(default_filter_policy),
+            fpr,
+            key_hashes: Vec::with_capacity(item_count),
+            max_sequence: 0,
+            entry_count: 0,
+            comparator: default_comparator(),
+            read_options,
+            key_range: None,
+            range_filter: range_filter.then(RangeFilterBuilder::default),
+            learned_index: learned_index.map(LearnedIndexBuilder::new),
+            prefix_extractor,
+            index_partition_blocks,
         })
     }
 
+    /// Sets the order the table's keys are added in. Defaults to bytewise.
+    /// A range filter or learned index is only built for bytewise order.
+    pub fn with_comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        if comparator.name() != BytewiseComparator.name() {
+            self.range_filter = None;
+            self.learned_index = None;
+        }
+        self.comparator = comparator;
+        self
+    }
+
     pub fn add_from_kv(&mut self, key: KeyValue) -> Result<(), SSTableError> {
         if key.key.is_empty() {
             return Err(SSTableError::EmptyKey);
         }
-        // bloom filter key set
-        if let Some(ref mut filter) = self.filter {
-            filter.set(&key.key);
+        self.key_hashes.push(key_hash(&key.key));
+        self.add_prefix_hash(&key.key);
+
+        match &mut self.key_range {
+            Some((_, largest)) => *largest = key.key.clone(),
+            None => self.key_range = Some((key.key.clone(), key.key.clone())),
+        }
+        if let Some(range_filter) = &mut self.range_filter {
+            range_filter.add(&key.key);
         }
 
+        // versions of a key never straddle a block or a restart point, so a
+        // lookup finds all of them in a single run
+        let same_key = self
+            .last_key
+            .as_ref()
+            .is_some_and(|last| self.comparator.compare(&last.key, &key.key).is_eq());
+
         let tentative = DeltaEncodedKV::forward(self.last_key.clone(), key.clone());
         let entry_size = tentative.calculate_size();
-        if self.current_block_size + entry_size > BLOCK_SIZE && !self.current_block.is_empty() {
+        if !same_key && self.current_block_size + entry_size > BLOCK_SIZE && !self.current_block.is_empty() {
             self.seal_current_block();
         }
 
@@ -77,9 +159,9 @@ impl SSTableBuilder {
             self.restart_indices.push(vec![0]);
             self.page_hash_indices.push(HashMap::new());
             self.fence_pointers
-                .push((key.key.clone().into(), self.current_offset));
+                .push((key.key.clone(), self.current_offset));
             self.last_key = None;
-        } else if self.current_block.len() % RESTART_INTERVAL == 0 {
+        } else if !same_key && self.current_block.len().is_multiple_of(RESTART_INTERVAL) {
             if let Some(restart_points) = self.restart_indices.last_mut() {
                 restart_points.push(self.current_block_size);
             }
@@ -91,16 +173,40 @@ impl SSTableBuilder {
             }
             self.last_key = None;
         }
+        // a run starts wherever the delta encoding restarts
+        if self.last_key.is_none()
+            && let Some(learned_index) = &mut self.learned_index
+        {
+            learned_index.add_run(&key.key);
+        }
         // recompute is hacky but idk .
         let dkv = DeltaEncodedKV::forward(self.last_key.clone(), key.clone());
         let entry_size = dkv.calculate_size();
         self.current_block.push(dkv);
         self.entry_count += 1;
+        self.max_sequence = self.max_sequence.max(key.seq);
         self.current_block_size += entry_size;
         self.last_key = Some(key);
         Ok(())
     }
 
+    // adds the prefix of `key` to the filter, once per run of keys sharing it
+    fn add_prefix_hash(&mut self, key: &[u8]) {
+        let Some(extractor) = &self.prefix_extractor else {
+            return;
+        };
+        if !extractor.in_domain(key) {
+            return;
+        }
+        let prefix = extractor.transform(key);
+        let repeated = self.key_range.as_ref().is_some_and(|(_, last)| {
+            extractor.in_domain(last) && extractor.transform(last) == prefix
+        });
+        if !repeated {
+            self.key_hashes.push(key_hash(prefix));
+        }
+    }
+
     fn seal_current_block(&mut self) {
         if self.restart_indices.len() <= self.blocks.len() {
             self.restart_indices.push(Vec::new());
@@ -112,7 +218,7 @@ impl SSTableBuilder {
 
         let block = std::mem::take(&mut self.current_block);
 
-        self.current_offset += self.current_block_size;
+        self.current_offset += self.current_block_size + BLOCK_TRAILER_SIZE;
         self.blocks.push(block);
         self.current_block_size = 0;
     }
@@ -132,28 +238,104 @@ impl SSTableBuilder {
             .map_err(SSTableError::FileSystemError)?;
 
         for block in self.blocks.iter() {
+            let mut checksum = 0;
             for kv in block {
                 let kv_bytes = kv.to_str();
+                checksum = crc32c::crc32c_append(checksum, &kv_bytes);
                 writer
                     .write_all(&kv_bytes)
                     .map_err(SSTableError::FileSystemError)?;
             }
+            writer
+                .write_all(&checksum.to_le_bytes())
+                .map_err(SSTableError::FileSystemError)?;
         }
 
-        writer
-            .write_all(b"SSTB")
-            .map_err(SSTableError::FileSystemError)?;
+        let data_end = self.current_offset;
+        let filter = TableFilter::encode(self.filter_policy.as_ref(), &self.key_hashes, self.fpr);
+        let range_filter = self
+            .range_filter
+            .as_ref()
+            .map(RangeFilterBuilder::finish)
+            .unwrap_or_default();
+        let (smallest_key, largest_key) = self
+            .key_range
+            .clone()
+            .map(|(smallest, largest)| (smallest.to_vec(), largest.to_vec()))
+            .unwrap_or_default();
+        let prefix_extractor = self
+            .prefix_extractor
+            .as_ref()
+            .map(|extractor| extractor.name().to_string())
+            .unwrap_or_default();
+        let learned_index = self
+            .learned_index
+            .as_ref()
+            .map(|learned_index| learned_index.finish(&self.restart_indices));
+        let index = TableIndex {
+            fence_pointers: self.fence_pointers.clone(),
+            restart_indices: self.restart_indices.clone(),
+            page_hash_indices: self.page_hash_indices.clone(),
+        };
+        let partitions = table_format::write_table_tail(
+            &mut writer,
+            &index,
+            self.index_partition_blocks,
+            &filter,
+            TableMeta {
+                item_count: self.entry_count,
+                data_end,
+                max_sequence: self.max_sequence,
+                comparator: self.comparator.name().to_string(),
+                smallest_key,
+                largest_key,
+                range_filter: range_filter.clone(),
+                prefix_extractor: prefix_extractor.clone(),
+                learned_index: learned_index
+                    .as_ref()
+                    .map(LearnedIndex::encode)
+                    .unwrap_or_default(),
+                ..TableMeta::default()
+            },
+        )?;
         writer.flush().map_err(SSTableError::FileSystemError)?;
+        sync_new_file(writer.get_ref(), &self.file_name)?;
 
+        let mmap = match self.read_options.mmap {
+            true => Some(map_file(&File::open(&self.file_name)?)?),
+            false => None,
+        };
+        // a partitioned index is left on disk
+        let block_count = index.fence_pointers.len();
+        let index = match partitions.is_empty() {
+            true => index,
+            false => TableIndex::default(),
+        };
         Ok(Arc::new(SSTable {
             file_path: self.file_name.clone(),
-            fd: None,
-            page_hash_indices: self.page_hash_indices.clone(),
-            fence_pointers: self.fence_pointers.clone(),
-            restart_indices: self.restart_indices.clone(),
-            bloom_filter: Some(Arc::new(self.filter.take().expect("Filter taken"))),
+            mmap,
+            page_hash_indices: index.page_hash_indices,
+            fence_pointers: index.fence_pointers,
+            restart_indices: index.restart_indices,
+            partitions,
+            block_count,
+            filter: TableFilter::decode(&filter)?,
+            key_range: self.key_range.clone(),
+            prefix_extractor,
+            learned_index,
+            range_filter: match range_filter.is_empty() {
+                true => None,
+                false => Some(RangeFilter::decode(&range_filter)?),
+            },
             actual_item_count: self.entry_count,
-            deleted: Mutex::new(false)
+            data_end,
+            max_sequence: self.max_sequence,
+            comparator: self.comparator.clone(),
+            id: next_table_id(),
+            index_id: next_table_id(),
+            read_options: self.read_options.clone(),
+            deleted: Mutex::new(false),
+            obsolete: AtomicBool::new(false),
         }))
     }
 
@@ -182,10 +364,7 @@ mod tests {
     use tempfile::tempdir;
 
     fn create_test_kv(key: &str, value: &str) -> KeyValue {
-        KeyValue {
-            key: key.to_string(),
-            value: value.to_string(),
-        }
+        KeyValue::new(key, value)
     }
 
     #[test]
@@ -196,6 +375,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -217,6 +397,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -237,6 +418,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -247,7 +429,7 @@ mod tests {
             builder.add_from_kv(create_test_kv(&key, &value))?;
         }
 
-        assert!(builder.blocks.len() > 0);
+        assert!(!builder.blocks.is_empty());
 
         Ok(())
     }
@@ -260,6 +442,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -294,6 +477,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -317,6 +501,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -345,6 +530,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -368,6 +554,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 100,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
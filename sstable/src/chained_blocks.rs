@@ -1,20 +1,24 @@
+use std::sync::Arc;
+
 use key_value::KeyValue;
 
 use crate::{block_iter::SSTableBlockIterator, error::SSTableError, SSTable};
 
-pub struct SSTableIterator<'a> {
-    sstable: &'a SSTable,
+/// Walks a table's entries in order, one block at a time. It holds on to the
+/// table, so it can outlive whatever handed it out.
+pub struct SSTableIterator {
+    sstable: Arc<SSTable>,
     current_block : usize,
     total_blocks: usize,
     block_iter: Option<SSTableBlockIterator>
 }
 
-impl<'a> SSTableIterator<'a> {
-    pub fn new(sstable: &'a SSTable) -> Self {
-        let total_blocks = sstable.fence_pointers.len();
+impl SSTableIterator {
+    pub fn new(sstable: Arc<SSTable>, first_block: usize) -> Self {
+        let total_blocks = sstable.block_count();
         SSTableIterator{
             sstable,
-            current_block: 0,
+            current_block: first_block,
             total_blocks,
             block_iter: None
         }
@@ -22,25 +26,33 @@ impl<'a> SSTableIterator<'a> {
 }
 
 
-impl<'a> Iterator for SSTableIterator<'a> {
+impl Iterator for SSTableIterator {
     type Item = Result<KeyValue, SSTableError>;
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(ref mut iter) = self.block_iter {
-                if let Some(kv) = iter.next() {
-                    return Some(Ok(kv))
+            if let Some(ref mut iter) = self.block_iter
+                && let Some(kv) = iter.next()
+            {
+                if kv.is_err() {
+                    // a corrupt block ends the scan
+                    self.current_block = self.total_blocks;
                 }
+                return Some(kv);
             }
 
             if self.current_block >= self.total_blocks {
                 return None;
             }
 
-            let block_id = (self.current_block, self.current_block + 1);
+            let block_id = self.current_block;
             self.current_block += 1;
             match self.sstable.iter_block(block_id) {
                 Ok(block_iterator) => self.block_iter = Some(block_iterator),
-                Err(e) => return Some(Err(e))
+                Err(e) => {
+                    self.block_iter = None;
+                    self.current_block = self.total_blocks;
+                    return Some(Err(e));
+                }
             }
         }
     }
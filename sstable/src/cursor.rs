@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use key_value::{key_value_pair::DeltaEncodedKV, KeyValue};
+
+use crate::{error::SSTableError, SSTable};
+
+/// A position in a table that moves in both directions. Entries come in table
+/// order: by key, and newest version first within a key.
+///
+/// Seeks go straight to the block through the fence pointers, then to the
+/// restart run inside it with a binary search over the restart points. Only
+/// the run under the cursor is decoded. Any error leaves the cursor invalid.
+pub struct SSTableCursor {
+    sstable: Arc<SSTable>,
+    block: usize,
+    data: Bytes,
+    restarts: Vec<usize>,
+    run: usize,
+    entries: Vec<KeyValue>,
+    position: Option<usize>,
+}
+
+impl SSTableCursor {
+    pub(crate) fn new(sstable: Arc<SSTable>) -> Self {
+        Self {
+            sstable,
+            block: 0,
+            data: Bytes::new(),
+            restarts: Vec::new(),
+            run: 0,
+            entries: Vec::new(),
+            position: None,
+        }
+    }
+
+    /// The entry under the cursor, `None` once it has moved off either end.
+    pub fn current(&self) -> Option<&KeyValue> {
+        self.position.map(|index| &self.entries[index])
+    }
+
+    pub fn seek_to_first(&mut self) -> Result<(), SSTableError> {
+        self.position = None;
+        if self.sstable.block_count() == 0 {
+            return Ok(());
+        }
+        let moved = self.load_block(0).and_then(|_| {
+            self.load_run(0)?;
+            self.settle_forward(0)
+        });
+        self.settle(moved)
+    }
+
+    pub fn seek_to_last(&mut self) -> Result<(), SSTableError> {
+        self.position = None;
+        let Some(last_block) = self.sstable.block_count().checked_sub(1) else {
+            return Ok(());
+        };
+        let moved = self.load_block(last_block).and_then(|_| {
+            self.load_run(self.restarts.len() - 1)?;
+            self.settle_backward(self.entries.len().checked_sub(1))
+        });
+        self.settle(moved)
+    }
+
+    /// Moves to the first entry whose key is at or after `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<(), SSTableError> {
+        self.position = None;
+        let moved = match self.sstable.find_block(key) {
+            Ok(Some(block)) => self.seek_in_block(block, key),
+            Ok(None) => return Ok(()),
+            Err(e) => Err(e),
+        };
+        self.settle(moved)
+    }
+
+    /// Moves to the last entry whose key is at or before `key`, which is the
+    /// oldest version of the largest such key.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), SSTableError> {
+        self.seek(key)?;
+        while let Some(kv) = self.current()
+            && self.sstable.comparator.compare(&kv.key, key).is_eq()
+        {
+            self.advance()?;
+        }
+        if self.position.is_some() {
+            self.retreat()
+        } else {
+            self.seek_to_last()
+        }
+    }
+
+    pub fn advance(&mut self) -> Result<(), SSTableError> {
+        let Some(index) = self.position else {
+            return Ok(());
+        };
+        let moved = self.settle_forward(index + 1);
+        self.settle(moved)
+    }
+
+    pub fn retreat(&mut self) -> Result<(), SSTableError> {
+        let Some(index) = self.position else {
+            return Ok(());
+        };
+        let moved = self.settle_backward(index.checked_sub(1));
+        self.settle(moved)
+    }
+
+    fn settle(&mut self, moved: Result<(), SSTableError>) -> Result<(), SSTableError> {
+        if moved.is_err() {
+            self.position = None;
+        }
+        moved
+    }
+
+    fn seek_in_block(&mut self, block: usize, key: &[u8]) -> Result<(), SSTableError> {
+        self.load_block(block)?;
+
+        // every version of a key sits in one run, so the target is in the
+        // last run that starts at or before it
+        let (mut left, mut right) = (0, self.restarts.len());
+        while left < right {
+            let mid = left + (right - left) / 2;
+            let start = self.restarts[mid];
+            let first = self.sstable.deserialize_first_key_from_run(
+                self.data.get(start..).unwrap_or(&[]),
+                block,
+                start,
+            )?;
+            if self.sstable.comparator.compare(&first.key, key).is_gt() {
+                right = mid;
+            } else {
+                left = mid + 1;
+            }
+        }
+        self.load_run(left.saturating_sub(1))?;
+
+        let index = self
+            .entries
+            .iter()
+            .position(|kv| self.sstable.comparator.compare(&kv.key, key).is_ge())
+            .unwrap_or(self.entries.len());
+        self.settle_forward(index)
+    }
+
+    // lands on `index` of the current run, or the first entry after the run
+    fn settle_forward(&mut self, mut index: usize) -> Result<(), SSTableError> {
+        loop {
+            if index < self.entries.len() {
+                self.position = Some(index);
+                return Ok(());
+            }
+            index = 0;
+            if self.run + 1 < self.restarts.len() {
+                self.load_run(self.run + 1)?;
+            } else if self.block + 1 < self.sstable.block_count() {
+                self.load_block(self.block + 1)?;
+                self.load_run(0)?;
+            } else {
+                self.position = None;
+                return Ok(());
+            }
+        }
+    }
+
+    // lands on `index` of the current run, or the last entry before the run
+    fn settle_backward(&mut self, mut index: Option<usize>) -> Result<(), SSTableError> {
+        loop {
+            if index.is_some() {
+                self.position = index;
+                return Ok(());
+            }
+            if self.run > 0 {
+                self.load_run(self.run - 1)?;
+            } else if self.block > 0 {
+                self.load_block(self.block - 1)?;
+                self.load_run(self.restarts.len() - 1)?;
+            } else {
+                self.position = None;
+                return Ok(());
+            }
+            index = self.entries.len().checked_sub(1);
+        }
+    }
+
+    fn load_block(&mut self, block: usize) -> Result<(), SSTableError> {
+        self.data = self.sstable.read_block(block)?;
+        self.block = block;
+        self.restarts = self
+            .sstable
+            .with_index_part(block, |part| match part.restarts(block) {
+                Some(restarts) if !restarts.is_empty() => restarts.to_vec(),
+                _ => vec![0],
+            })?;
+        Ok(())
+    }
+
+    fn load_run(&mut self, run: usize) -> Result<(), SSTableError> {
+        let start = self.restarts[run];
+        let end = self
+            .restarts
+            .get(run + 1)
+            .copied()
+            .unwrap_or(self.data.len());
+        if start > end || end > self.data.len() {
+            return Err(self.sstable.corruption(self.block, start));
+        }
+
+        self.entries.clear();
+        let mut position = start;
+        while position < end {
+            let corruption = || self.sstable.corruption(self.block, position);
+            let (dkv, read) =
+                DeltaEncodedKV::decode(&self.data[position..end]).ok_or_else(corruption)?;
+            let kv = dkv
+                .reverse(self.entries.last().cloned())
+                .ok_or_else(corruption)?;
+            self.entries.push(kv);
+            position += read;
+        }
+        self.run = run;
+        Ok(())
+    }
+}
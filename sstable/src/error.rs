@@ -17,8 +17,8 @@ pub enum SSTableError {
     #[error("Invalid false positive rate: {0}. Must be between 0 and 1")]
     InvalidFalsePositiveRate(f64),
 
-    #[error("Bloom filter error: {0}")]
-    BloomFilterError(String),
+    #[error("Filter error: {0}")]
+    FilterError(String),
 
     #[error("DKV Decode error: {0}")]
     DecodeError(String),
@@ -38,6 +38,59 @@ pub enum SSTableError {
     #[error("Failed to build SSTable: {0}")]
     NoTableFound(String),
 
-    #[error("The KVP bridges a block boundary. Starting Offset: {0}")]
-    KVPexceedsBlock(usize),
+    #[error("Table was written with comparator {found}, expected {expected}")]
+    ComparatorMismatch { expected: String, found: String },
+
+    /// A checksum mismatch or an undecodable entry. Blocks are numbered in
+    /// file order: the data blocks first, then the index, filter and meta
+    /// blocks. A damaged footer is reported as block `usize::MAX`, since the
+    /// block count lives in the footer. `offset` is a position in the file.
+    #[error("Corruption in {} at block {block}, offset {offset}", .file.display())]
+    Corruption {
+        file: std::path::PathBuf,
+        block: usize,
+        offset: usize,
+    },
+}
+
+/// A copy of `error` with the same kind and message. `std::io::Error` can't
+/// be cloned, and its source doesn't carry over.
+pub fn clone_io_error(error: &std::io::Error) -> std::io::Error {
+    std::io::Error::new(error.kind(), error.to_string())
+}
+
+impl Clone for SSTableError {
+    fn clone(&self) -> Self {
+        match self {
+            SSTableError::FileSystemError(e) => SSTableError::FileSystemError(clone_io_error(e)),
+            SSTableError::StringUTF8(e) => SSTableError::StringUTF8(e.clone()),
+            SSTableError::InvalidBlockSize(size) => SSTableError::InvalidBlockSize(*size),
+            SSTableError::InvalidItemCount => SSTableError::InvalidItemCount,
+            SSTableError::InvalidFalsePositiveRate(fpr) => {
+                SSTableError::InvalidFalsePositiveRate(*fpr)
+            }
+            SSTableError::FilterError(message) => SSTableError::FilterError(message.clone()),
+            SSTableError::DecodeError(message) => SSTableError::DecodeError(message.clone()),
+            SSTableError::EmptyKey => SSTableError::EmptyKey,
+            SSTableError::KeyNotfound => SSTableError::KeyNotfound,
+            SSTableError::EncodingError => SSTableError::EncodingError,
+            SSTableError::BuildError(message) => SSTableError::BuildError(message.clone()),
+            SSTableError::NoTableFound(message) => SSTableError::NoTableFound(message.clone()),
+            SSTableError::ComparatorMismatch { expected, found } => {
+                SSTableError::ComparatorMismatch {
+                    expected: expected.clone(),
+                    found: found.clone(),
+                }
+            }
+            SSTableError::Corruption {
+                file,
+                block,
+                offset,
+            } => SSTableError::Corruption {
+                file: file.clone(),
+                block: *block,
+                offset: *offset,
+            },
+        }
+    }
 }
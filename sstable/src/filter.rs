@@ -0,0 +1,619 @@
+use std::{fmt, sync::Arc};
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::error::SSTableError;
+
+/// Builds and reads back one kind of key filter. A table's filter block
+/// starts with the id of the policy that built it, followed by whatever the
+/// policy encoded, so a table is read back without being told its policy.
+///
+/// Tables are only ever reopened with the built-in policies, which are
+/// found by id: the blocked bloom, Ribbon and Xor filters below.
+pub trait FilterPolicy: Send + Sync + fmt::Debug {
+    /// Tag stored in front of every filter this policy builds. Must never
+    /// change once tables have been written with it.
+    fn id(&self) -> u8;
+
+    /// Encodes a filter over `hashes`, the `key_hash` of every key of a
+    /// table, that lets through about `fpr` of the keys that aren't there.
+    /// `hashes` may repeat.
+    fn build(&self, hashes: &[u64], fpr: f64) -> Vec<u8>;
+
+    /// Reads back a filter encoded by `build`.
+    fn decode(&self, encoded: &[u8]) -> Result<Box<dyn Filter>, SSTableError>;
+}
+
+/// A decoded filter, probed by key hash.
+pub trait Filter: Send + Sync + fmt::Debug {
+    /// `false` only if no key with this hash was in the filter.
+    fn may_contain(&self, hash: u64) -> bool;
+}
+
+/// The hash every policy builds its filters from. It is part of the table
+/// format, so it must never change.
+pub fn key_hash(key: &[u8]) -> u64 {
+    xxh3_64(key)
+}
+
+pub fn default_filter_policy() -> Arc<dyn FilterPolicy> {
+    Arc::new(BlockedBloomPolicy)
+}
+
+fn builtin_policy(id: u8) -> Option<Arc<dyn FilterPolicy>> {
+    let policy: Arc<dyn FilterPolicy> = match id {
+        BLOCKED_BLOOM_ID => Arc::new(BlockedBloomPolicy),
+        RIBBON_ID => Arc::new(RibbonPolicy),
+        XOR_ID => Arc::new(XorPolicy),
+        _ => return None,
+    };
+    Some(policy)
+}
+
+/// The filter of one table, as stored in its filter block.
+#[derive(Debug)]
+pub struct TableFilter {
+    policy_id: u8,
+    filter: Box<dyn Filter>,
+    size: usize,
+}
+
+impl TableFilter {
+    /// The filter block for a table whose keys hash to `hashes`.
+    pub(crate) fn encode(policy: &dyn FilterPolicy, hashes: &[u64], fpr: f64) -> Vec<u8> {
+        let mut block = vec![policy.id()];
+        block.extend_from_slice(&policy.build(hashes, fpr));
+        block
+    }
+
+    /// `None` for an empty block, which a table without a filter has.
+    pub(crate) fn decode(block: &[u8]) -> Result<Option<Self>, SSTableError> {
+        let Some((&policy_id, encoded)) = block.split_first() else {
+            return Ok(None);
+        };
+        let policy = builtin_policy(policy_id).ok_or_else(|| {
+            SSTableError::FilterError(format!("unknown filter policy {}", policy_id))
+        })?;
+        Ok(Some(Self {
+            policy_id,
+            filter: policy.decode(encoded)?,
+            size: block.len(),
+        }))
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter.may_contain(key_hash(key))
+    }
+
+    pub fn policy_id(&self) -> u8 {
+        self.policy_id
+    }
+
+    /// Size of the encoded filter in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+const BLOCKED_BLOOM_ID: u8 = 1;
+const RIBBON_ID: u8 = 2;
+const XOR_ID: u8 = 3;
+
+// a 512-bit bloom block, the size of a cache line
+const BLOOM_BLOCK_WORDS: usize = 8;
+const BLOOM_BLOCK_BITS: u32 = 512;
+
+/// A bloom filter split into cache-line sized blocks. Each key sets all of
+/// its bits in one block, so a probe touches a single cache line. The
+/// uneven load of the blocks costs a little accuracy, which an extra bit
+/// per key buys back.
+///
+/// Encoding: probe count (u8), block count (u32 LE), then the blocks as
+/// u64 LE words.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockedBloomPolicy;
+
+#[derive(Debug)]
+struct BlockedBloom {
+    probes: u32,
+    blocks: Vec<[u64; BLOOM_BLOCK_WORDS]>,
+}
+
+impl BlockedBloom {
+    fn bits(&self, hash: u64) -> (usize, impl Iterator<Item = u32> + use<>) {
+        let block = fastrange32((hash >> 32) as u32, self.blocks.len());
+        let mut h = hash as u32;
+        let delta = h.rotate_right(17);
+        let bits = (0..self.probes).map(move |_| {
+            let bit = h % BLOOM_BLOCK_BITS;
+            h = h.wrapping_add(delta);
+            bit
+        });
+        (block, bits)
+    }
+}
+
+impl Filter for BlockedBloom {
+    fn may_contain(&self, hash: u64) -> bool {
+        let (block, mut bits) = self.bits(hash);
+        let words = &self.blocks[block];
+        bits.all(|bit| words[bit as usize / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+impl FilterPolicy for BlockedBloomPolicy {
+    fn id(&self) -> u8 {
+        BLOCKED_BLOOM_ID
+    }
+
+    fn build(&self, hashes: &[u64], fpr: f64) -> Vec<u8> {
+        let bits_per_key = -fpr.log2() / std::f64::consts::LN_2;
+        let probes = (bits_per_key * std::f64::consts::LN_2)
+            .round()
+            .clamp(1.0, 30.0) as u32;
+        let total_bits = (hashes.len() as f64 * (bits_per_key + 1.0)).ceil() as usize;
+        let blocks = total_bits.div_ceil(BLOOM_BLOCK_BITS as usize).max(1);
+
+        let mut filter = BlockedBloom {
+            probes,
+            blocks: vec![[0; BLOOM_BLOCK_WORDS]; blocks],
+        };
+        for &hash in hashes {
+            let (block, bits) = filter.bits(hash);
+            for bit in bits {
+                filter.blocks[block][bit as usize / 64] |= 1 << (bit % 64);
+            }
+        }
+
+        let mut encoded = Vec::with_capacity(5 + blocks * BLOOM_BLOCK_WORDS * 8);
+        encoded.push(probes as u8);
+        encoded.extend_from_slice(&(blocks as u32).to_le_bytes());
+        for word in filter.blocks.iter().flatten() {
+            encoded.extend_from_slice(&word.to_le_bytes());
+        }
+        encoded
+    }
+
+    fn decode(&self, encoded: &[u8]) -> Result<Box<dyn Filter>, SSTableError> {
+        let malformed = || SSTableError::FilterError("malformed blocked bloom filter".to_string());
+        let (&probes, rest) = encoded.split_first().ok_or_else(malformed)?;
+        let (blocks, words) = rest.split_at_checked(4).ok_or_else(malformed)?;
+        let blocks = u32::from_le_bytes(blocks.try_into().unwrap()) as usize;
+        if probes == 0 || blocks == 0 || words.len() != blocks * BLOOM_BLOCK_WORDS * 8 {
+            return Err(malformed());
+        }
+
+        let words: Vec<u64> = words.chunks_exact(8).map(read_u64).collect();
+        Ok(Box::new(BlockedBloom {
+            probes: probes as u32,
+            blocks: words
+                .chunks_exact(BLOOM_BLOCK_WORDS)
+                .map(|block| block.try_into().unwrap())
+                .collect(),
+        }))
+    }
+}
+
+/// A standard Ribbon filter with 64-bit wide bands (Dillinger and Walzer).
+/// Every key is a row of a banded linear system over GF(2) whose solution
+/// gives the key its fingerprint; building solves the system, and a probe
+/// multiplies the key's row with the solution. It needs about 10% more
+/// bits than the fingerprint width per key, against the 44% of a bloom
+/// filter.
+///
+/// Encoding: seed (u64 LE), slot count (u64 LE), fingerprint width in bits
+/// (u8), then one column of the solution per fingerprint bit, each as u64
+/// LE words.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RibbonPolicy;
+
+#[derive(Debug)]
+struct Ribbon {
+    seed: u64,
+    slots: usize,
+    width: u32,
+    columns: Vec<Vec<u64>>,
+}
+
+// where a key's row starts, its band of coefficients and its fingerprint
+fn ribbon_row(hash: u64, seed: u64, slots: usize, width: u32) -> (usize, u64, u64) {
+    let h = remix(hash, seed);
+    let start = fastrange64(h, slots - 63);
+    (start, remix(h, 0) | 1, h & fingerprint_mask(width))
+}
+
+impl Ribbon {
+    // the band of solution bits of column `b` starting at slot `start`
+    fn window(&self, b: usize, start: usize) -> u64 {
+        read_window(&self.columns[b], start)
+    }
+}
+
+impl Filter for Ribbon {
+    fn may_contain(&self, hash: u64) -> bool {
+        let (start, coefficients, fingerprint) =
+            ribbon_row(hash, self.seed, self.slots, self.width);
+        (0..self.width as usize).all(|b| {
+            let bit = (self.window(b, start) & coefficients).count_ones() as u64 & 1;
+            bit == (fingerprint >> b) & 1
+        })
+    }
+}
+
+impl RibbonPolicy {
+    // Gaussian elimination as the rows come in. `None` if the rows turn out
+    // to be dependent, in which case another seed is tried.
+    fn solve(hashes: &[u64], seed: u64, slots: usize, width: u32) -> Option<Ribbon> {
+        let mut rows = vec![(0u64, 0u64); slots];
+        for &hash in hashes {
+            let (mut slot, mut coefficients, mut fingerprint) =
+                ribbon_row(hash, seed, slots, width);
+            loop {
+                let (row, result) = rows[slot];
+                if row == 0 {
+                    rows[slot] = (coefficients, fingerprint);
+                    break;
+                }
+                coefficients ^= row;
+                fingerprint ^= result;
+                if coefficients == 0 {
+                    if fingerprint != 0 {
+                        return None;
+                    }
+                    break;
+                }
+                let shift = coefficients.trailing_zeros();
+                slot += shift as usize;
+                coefficients >>= shift;
+            }
+        }
+
+        // back substitution, from the last slot up
+        let words = slots.div_ceil(64) + 1;
+        let mut ribbon = Ribbon {
+            seed,
+            slots,
+            width,
+            columns: vec![vec![0; words]; width as usize],
+        };
+        for slot in (0..slots).rev() {
+            let (row, result) = rows[slot];
+            for b in 0..width as usize {
+                let bit =
+                    ((ribbon.window(b, slot) & row).count_ones() as u64 & 1) ^ ((result >> b) & 1);
+                ribbon.columns[b][slot / 64] |= bit << (slot % 64);
+            }
+        }
+        Some(ribbon)
+    }
+}
+
+impl FilterPolicy for RibbonPolicy {
+    fn id(&self) -> u8 {
+        RIBBON_ID
+    }
+
+    fn build(&self, hashes: &[u64], fpr: f64) -> Vec<u8> {
+        let hashes = distinct(hashes);
+        let width = fingerprint_bits(fpr);
+        let mut overhead = 1.1;
+        let ribbon = (1u64..)
+            .find_map(|seed| {
+                let slots = (hashes.len() as f64 * overhead).ceil() as usize + 64;
+                // a few unlucky seeds in a row mean the system is too tight
+                if seed % 4 == 0 {
+                    overhead += 0.05;
+                }
+                RibbonPolicy::solve(&hashes, seed, slots, width)
+            })
+            .expect("some seed solves the system");
+
+        let mut encoded = Vec::new();
+        encoded.extend_from_slice(&ribbon.seed.to_le_bytes());
+        encoded.extend_from_slice(&(ribbon.slots as u64).to_le_bytes());
+        encoded.push(width as u8);
+        for word in ribbon.columns.iter().flatten() {
+            encoded.extend_from_slice(&word.to_le_bytes());
+        }
+        encoded
+    }
+
+    fn decode(&self, encoded: &[u8]) -> Result<Box<dyn Filter>, SSTableError> {
+        let malformed = || SSTableError::FilterError("malformed ribbon filter".to_string());
+        let (header, words) = encoded.split_at_checked(17).ok_or_else(malformed)?;
+        let seed = read_u64(&header[..8]);
+        let slots = usize::try_from(read_u64(&header[8..16])).map_err(|_| malformed())?;
+        let width = header[16] as u32;
+        let per_column = slots.div_ceil(64) + 1;
+        if slots < 64
+            || !(1..=32).contains(&width)
+            || words.len() != width as usize * per_column * 8
+        {
+            return Err(malformed());
+        }
+
+        let words: Vec<u64> = words.chunks_exact(8).map(read_u64).collect();
+        Ok(Box::new(Ribbon {
+            seed,
+            slots,
+            width,
+            columns: words
+                .chunks_exact(per_column)
+                .map(<[u64]>::to_vec)
+                .collect(),
+        }))
+    }
+}
+
+/// An Xor filter (Graf and Lemire). Each key maps to three slots, one in
+/// each third of a table of fingerprints, chosen so the three xor to the
+/// key's fingerprint. It needs 23% more bits than the fingerprint width per
+/// key, and a probe reads exactly three slots.
+///
+/// Encoding: seed (u64 LE), slots per third (u64 LE), fingerprint width in
+/// bits (u8), then the fingerprints bit-packed, padded with 8 zero bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct XorPolicy;
+
+#[derive(Debug)]
+struct Xor {
+    seed: u64,
+    segment: usize,
+    width: u32,
+    fingerprints: Vec<u8>,
+}
+
+fn xor_slots(h: u64, segment: usize) -> [usize; 3] {
+    [
+        fastrange32(h as u32, segment),
+        segment + fastrange32(h.rotate_left(21) as u32, segment),
+        2 * segment + fastrange32(h.rotate_left(42) as u32, segment),
+    ]
+}
+
+fn xor_fingerprint(h: u64, width: u32) -> u64 {
+    (h ^ (h >> 32)) & fingerprint_mask(width)
+}
+
+impl Filter for Xor {
+    fn may_contain(&self, hash: u64) -> bool {
+        let h = remix(hash, self.seed);
+        let found = xor_slots(h, self.segment).iter().fold(0, |acc, &slot| {
+            acc ^ read_bits(&self.fingerprints, slot, self.width)
+        });
+        found == xor_fingerprint(h, self.width)
+    }
+}
+
+impl XorPolicy {
+    // Peels keys off slots that only one key maps to. `None` if some keys
+    // can't be peeled, in which case another seed is tried.
+    fn assign(hashes: &[u64], seed: u64, segment: usize, width: u32) -> Option<Xor> {
+        let capacity = 3 * segment;
+        let mut counts = vec![0u32; capacity];
+        let mut xored = vec![0u64; capacity];
+        for &hash in hashes {
+            let h = remix(hash, seed);
+            for slot in xor_slots(h, segment) {
+                counts[slot] += 1;
+                xored[slot] ^= h;
+            }
+        }
+
+        let mut queue: Vec<usize> = (0..capacity).filter(|&slot| counts[slot] == 1).collect();
+        let mut peeled = Vec::with_capacity(hashes.len());
+        while let Some(slot) = queue.pop() {
+            if counts[slot] != 1 {
+                continue;
+            }
+            let h = xored[slot];
+            peeled.push((h, slot));
+            for other in xor_slots(h, segment) {
+                counts[other] -= 1;
+                xored[other] ^= h;
+                if counts[other] == 1 {
+                    queue.push(other);
+                }
+            }
+        }
+        if peeled.len() != hashes.len() {
+            return None;
+        }
+
+        // a peeled key's slot is still free when it is assigned, so the
+        // other two slots are already final
+        let mut fingerprints = vec![0u8; (capacity * width as usize).div_ceil(8) + 8];
+        for &(h, slot) in peeled.iter().rev() {
+            let value = xor_slots(h, segment)
+                .iter()
+                .fold(xor_fingerprint(h, width), |acc, &other| {
+                    acc ^ read_bits(&fingerprints, other, width)
+                });
+            write_bits(&mut fingerprints, slot, width, value);
+        }
+        Some(Xor {
+            seed,
+            segment,
+            width,
+            fingerprints,
+        })
+    }
+}
+
+impl FilterPolicy for XorPolicy {
+    fn id(&self) -> u8 {
+        XOR_ID
+    }
+
+    fn build(&self, hashes: &[u64], fpr: f64) -> Vec<u8> {
+        let hashes = distinct(hashes);
+        let width = fingerprint_bits(fpr);
+        let segment = ((hashes.len() as f64 * 1.23).ceil() as usize + 32).div_ceil(3);
+        let xor = (1u64..)
+            .find_map(|seed| XorPolicy::assign(&hashes, seed, segment, width))
+            .expect("some seed peels every key");
+
+        let mut encoded = Vec::with_capacity(17 + xor.fingerprints.len());
+        encoded.extend_from_slice(&xor.seed.to_le_bytes());
+        encoded.extend_from_slice(&(xor.segment as u64).to_le_bytes());
+        encoded.push(width as u8);
+        encoded.extend_from_slice(&xor.fingerprints);
+        encoded
+    }
+
+    fn decode(&self, encoded: &[u8]) -> Result<Box<dyn Filter>, SSTableError> {
+        let malformed = || SSTableError::FilterError("malformed xor filter".to_string());
+        let (header, fingerprints) = encoded.split_at_checked(17).ok_or_else(malformed)?;
+        let seed = read_u64(&header[..8]);
+        let segment = usize::try_from(read_u64(&header[8..16])).map_err(|_| malformed())?;
+        let width = header[16] as u32;
+        if segment == 0
+            || !(1..=32).contains(&width)
+            || fingerprints.len() != (3 * segment * width as usize).div_ceil(8) + 8
+        {
+            return Err(malformed());
+        }
+
+        Ok(Box::new(Xor {
+            seed,
+            segment,
+            width,
+            fingerprints: fingerprints.to_vec(),
+        }))
+    }
+}
+
+// bits of fingerprint needed for a false positive rate of `fpr`
+fn fingerprint_bits(fpr: f64) -> u32 {
+    (-fpr.log2()).ceil().clamp(1.0, 32.0) as u32
+}
+
+fn fingerprint_mask(width: u32) -> u64 {
+    (1 << width) - 1
+}
+
+fn distinct(hashes: &[u64]) -> Vec<u64> {
+    let mut hashes = hashes.to_vec();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+}
+
+// splitmix64's finalizer over the hash and seed
+fn remix(hash: u64, seed: u64) -> u64 {
+    let mut z = hash ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// maps `x` onto 0..n without a division
+fn fastrange32(x: u32, n: usize) -> usize {
+    ((x as u64 * n as u64) >> 32) as usize
+}
+
+fn fastrange64(x: u64, n: usize) -> usize {
+    ((x as u128 * n as u128) >> 64) as usize
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+// the 64 bits of `words` starting at bit `start`
+fn read_window(words: &[u64], start: usize) -> u64 {
+    let (index, shift) = (start / 64, start % 64);
+    match shift {
+        0 => words[index],
+        _ => (words[index] >> shift) | (words[index + 1] << (64 - shift)),
+    }
+}
+
+// the `width`-bit value at `index` of a packed array with 8 bytes of padding
+fn read_bits(packed: &[u8], index: usize, width: u32) -> u64 {
+    let bit = index * width as usize;
+    (read_u64(&packed[bit / 8..bit / 8 + 8]) >> (bit % 8)) & fingerprint_mask(width)
+}
+
+fn write_bits(packed: &mut [u8], index: usize, width: u32, value: u64) {
+    let bit = index * width as usize;
+    let bytes = &mut packed[bit / 8..bit / 8 + 8];
+    let word = read_u64(bytes) & !(fingerprint_mask(width) << (bit % 8));
+    bytes.copy_from_slice(&(word | (value << (bit % 8))).to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policies() -> Vec<Arc<dyn FilterPolicy>> {
+        vec![
+            Arc::new(BlockedBloomPolicy),
+            Arc::new(RibbonPolicy),
+            Arc::new(XorPolicy),
+        ]
+    }
+
+    fn hashes(range: std::ops::Range<u32>) -> Vec<u64> {
+        range
+            .map(|i| key_hash(format!("key-{}", i).as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_filters_have_no_false_negatives_and_meet_fpr() -> Result<(), SSTableError> {
+        let present = hashes(0..10_000);
+        let absent = hashes(10_000..30_000);
+        for policy in policies() {
+            let block = TableFilter::encode(policy.as_ref(), &present, 0.01);
+            let filter = TableFilter::decode(&block)?.unwrap();
+            assert_eq!(filter.policy_id(), policy.id());
+
+            assert!(present.iter().all(|&hash| filter.filter.may_contain(hash)));
+            let false_positives = absent
+                .iter()
+                .filter(|&&hash| filter.filter.may_contain(hash))
+                .count();
+            let rate = false_positives as f64 / absent.len() as f64;
+            assert!(rate < 0.015, "{:?} let through {}", policy, rate);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ribbon_and_xor_are_smaller_than_bloom() {
+        let present = hashes(0..10_000);
+        let size = |policy: &dyn FilterPolicy| policy.build(&present, 0.01).len();
+        let bloom = size(&BlockedBloomPolicy);
+        let ribbon = size(&RibbonPolicy);
+        let xor = size(&XorPolicy);
+        assert!(ribbon < xor && xor < bloom, "{} {} {}", ribbon, xor, bloom);
+        // about 7.7 and 8.6 bits per key, against 10.6 for the bloom filter
+        assert!(ribbon * 8 < present.len() * 8);
+        assert!(xor * 8 < present.len() * 9);
+    }
+
+    #[test]
+    fn test_edge_cases_build_and_decode() -> Result<(), SSTableError> {
+        for policy in policies() {
+            // no keys, a single key repeated, and a loose rate
+            for (keys, fpr) in [(vec![], 0.01), (vec![7; 5], 0.01), (hashes(0..100), 0.5)] {
+                let filter = policy.decode(&policy.build(&keys, fpr))?;
+                assert!(keys.iter().all(|&hash| filter.may_contain(hash)));
+            }
+        }
+
+        assert!(TableFilter::decode(&[])?.is_none());
+        assert!(matches!(
+            TableFilter::decode(&[99, 1, 2]),
+            Err(SSTableError::FilterError(_))
+        ));
+        let mut block = TableFilter::encode(&XorPolicy, &hashes(0..100), 0.01);
+        block.truncate(block.len() - 1);
+        assert!(matches!(
+            TableFilter::decode(&block),
+            Err(SSTableError::FilterError(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::table_format::TableIndex;
+
+/// The index entries of a run of data blocks, numbered from `first_block`:
+/// a table's whole index, or the partition of it that a block falls in.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IndexPart<'a> {
+    pub fence_pointers: &'a [(Arc<[u8]>, usize)],
+    pub restart_indices: &'a [Vec<usize>],
+    pub page_hash_indices: &'a [HashMap<Arc<[u8]>, usize>],
+    pub first_block: usize,
+    // offset just past the last block of the run
+    pub end: usize,
+}
+
+impl<'a> IndexPart<'a> {
+    pub fn new(entries: &'a TableIndex, first_block: usize, end: usize) -> Self {
+        Self {
+            fence_pointers: &entries.fence_pointers,
+            restart_indices: &entries.restart_indices,
+            page_hash_indices: &entries.page_hash_indices,
+            first_block,
+            end,
+        }
+    }
+
+    /// Where `block` starts in the file, and where its checksum trailer
+    /// ends. `None` if the block isn't in this part.
+    pub fn bounds(&self, block: usize) -> Option<(usize, usize)> {
+        let local = block.checked_sub(self.first_block)?;
+        let (_, start) = self.fence_pointers.get(local)?;
+        let end = match self.fence_pointers.get(local + 1) {
+            Some((_, next)) => *next,
+            None => self.end,
+        };
+        Some((*start, end))
+    }
+
+    pub fn restarts(&self, block: usize) -> Option<&'a [usize]> {
+        let local = block.checked_sub(self.first_block)?;
+        self.restart_indices.get(local).map(Vec::as_slice)
+    }
+
+    pub fn hash_index(&self, block: usize) -> Option<&'a HashMap<Arc<[u8]>, usize>> {
+        let local = block.checked_sub(self.first_block)?;
+        self.page_hash_indices.get(local)
+    }
+}
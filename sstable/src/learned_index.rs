@@ -0,0 +1,255 @@
+use std::io::{Cursor, Read};
+
+use integer_encoding::VarInt;
+
+use crate::{error::SSTableError, range_filter::prefix, table_format::read_varint};
+
+/// A piecewise linear model of where keys fall among a table's restart runs.
+///
+/// Every key maps to the number formed by its first eight bytes. Each
+/// segment predicts a run from that number along a straight line, and is off
+/// by at most `max_error` runs for the first key of every run it was fitted
+/// to. A lookup only looks at the blocks and runs around the prediction, and
+/// checks that they hold the key before trusting them; callers fall back to
+/// searching the fence pointers and restart points when they don't.
+///
+/// Pays off for keys that differ in their first eight bytes, such as
+/// big-endian integers. Only valid for bytewise key order, where that
+/// number never decreases as the key grows.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LearnedIndex {
+    max_error: usize,
+    segments: Vec<Segment>,
+    // first run of each block, followed by the number of runs
+    block_runs: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Segment {
+    first_key: u64,
+    first_run: usize,
+    slope: f64,
+}
+
+impl LearnedIndex {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&self.max_error.encode_var_vec());
+        buffer.extend_from_slice(&self.block_runs.len().encode_var_vec());
+        let mut previous = 0;
+        for &first_run in &self.block_runs {
+            buffer.extend_from_slice(&(first_run - previous).encode_var_vec());
+            previous = first_run;
+        }
+        buffer.extend_from_slice(&self.segments.len().encode_var_vec());
+        for segment in &self.segments {
+            buffer.extend_from_slice(&segment.first_key.encode_var_vec());
+            buffer.extend_from_slice(&segment.first_run.encode_var_vec());
+            buffer.extend_from_slice(&segment.slope.to_le_bytes());
+        }
+        buffer
+    }
+
+    pub fn decode(encoded: &[u8]) -> Result<Self, SSTableError> {
+        let mut cursor = Cursor::new(encoded);
+        let max_error = read_varint(&mut cursor)?;
+
+        let count: usize = read_varint(&mut cursor)?;
+        let mut block_runs = Vec::new();
+        let mut first_run = 0;
+        for _ in 0..count {
+            first_run += read_varint::<usize>(&mut cursor)?;
+            block_runs.push(first_run);
+        }
+
+        let count: usize = read_varint(&mut cursor)?;
+        let mut segments = Vec::new();
+        for _ in 0..count {
+            let first_key = read_varint(&mut cursor)?;
+            let first_run = read_varint(&mut cursor)?;
+            let mut slope = [0u8; 8];
+            cursor
+                .read_exact(&mut slope)
+                .map_err(|e| SSTableError::DecodeError(e.to_string()))?;
+            segments.push(Segment {
+                first_key,
+                first_run,
+                slope: f64::from_le_bytes(slope),
+            });
+        }
+        Ok(Self {
+            max_error,
+            segments,
+            block_runs,
+        })
+    }
+
+    /// The blocks, first and last, that the prediction for `key` spans.
+    pub fn blocks(&self, key: &[u8]) -> Option<(usize, usize)> {
+        let (first, last) = self.runs(key)?;
+        Some((self.block_of(first), self.block_of(last)))
+    }
+
+    /// The predicted runs that fall in `block`, numbered within the block,
+    /// or `None` if the prediction misses the block.
+    pub fn runs_in_block(&self, block: usize, key: &[u8]) -> Option<(usize, usize)> {
+        let start = *self.block_runs.get(block)?;
+        let end = self.block_runs.get(block + 1)?.checked_sub(1)?;
+        let (first, last) = self.runs(key)?;
+        let (first, last) = (first.max(start), last.min(end));
+        (first <= last).then(|| (first - start, last - start))
+    }
+
+    // the runs, counted across the table, that should hold the last run
+    // starting at or before `key`
+    fn runs(&self, key: &[u8]) -> Option<(usize, usize)> {
+        let last_run = self.block_runs.last()?.checked_sub(1)?;
+        let predicted = self.predict(prefix(key))?;
+        // one run of slack for rounding, and for keys that start no run
+        let slack = self.max_error + 1;
+        Some((
+            predicted.saturating_sub(slack).min(last_run),
+            (predicted + slack).min(last_run),
+        ))
+    }
+
+    fn predict(&self, key: u64) -> Option<usize> {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.first_key <= key)
+            .saturating_sub(1);
+        let segment = self.segments.get(index)?;
+        let predicted =
+            segment.first_run as f64 + segment.slope * key.saturating_sub(segment.first_key) as f64;
+        // a segment never predicts past where the next one starts
+        let limit = match self.segments.get(index + 1) {
+            Some(next) => next.first_run.saturating_sub(1),
+            None => predicted as usize,
+        };
+        Some((predicted as usize).min(limit).max(segment.first_run))
+    }
+
+    fn block_of(&self, run: usize) -> usize {
+        self.block_runs
+            .partition_point(|&first_run| first_run <= run)
+            .saturating_sub(1)
+    }
+}
+
+/// Collects the first key of every restart run as a table is built, in
+/// order, and fits the model once the table is done.
+#[derive(Debug)]
+pub(crate) struct LearnedIndexBuilder {
+    max_error: usize,
+    run_keys: Vec<u64>,
+}
+
+impl LearnedIndexBuilder {
+    pub fn new(max_error: usize) -> Self {
+        Self {
+            max_error,
+            run_keys: Vec::new(),
+        }
+    }
+
+    pub fn add_run(&mut self, first_key: &[u8]) {
+        self.run_keys.push(prefix(first_key));
+    }
+
+    /// Fits segments to the runs added so far, grouped into blocks by the
+    /// restart points each block ended up with.
+    pub fn finish(&self, restart_indices: &[Vec<usize>]) -> LearnedIndex {
+        let mut block_runs = vec![0];
+        for restarts in restart_indices {
+            block_runs.push(block_runs[block_runs.len() - 1] + restarts.len());
+        }
+
+        // grows each segment for as long as some slope keeps every run in
+        // it within the error, narrowing the range of such slopes as it goes
+        let max_error = self.max_error as f64;
+        let mut segments: Vec<Segment> = Vec::new();
+        let (mut low, mut high) = (0.0, f64::INFINITY);
+        for (run, &key) in self.run_keys.iter().enumerate() {
+            // runs whose keys share the number are predicted from the first
+            if run > 0 && self.run_keys[run - 1] == key {
+                continue;
+            }
+            if let Some(segment) = segments.last_mut() {
+                let dx = (key - segment.first_key) as f64;
+                let dy = (run - segment.first_run) as f64;
+                let (min_slope, max_slope) = ((dy - max_error) / dx, (dy + max_error) / dx);
+                if min_slope.max(low) <= max_slope.min(high) {
+                    low = min_slope.max(low);
+                    high = max_slope.min(high);
+                    segment.slope = (low + high) / 2.0;
+                    continue;
+                }
+            }
+            segments.push(Segment {
+                first_key: key,
+                first_run: run,
+                slope: 0.0,
+            });
+            (low, high) = (0.0, f64::INFINITY);
+        }
+
+        LearnedIndex {
+            max_error: self.max_error,
+            segments,
+            block_runs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit(keys: &[u64], runs_per_block: usize, max_error: usize) -> LearnedIndex {
+        let mut builder = LearnedIndexBuilder::new(max_error);
+        for key in keys {
+            builder.add_run(&key.to_be_bytes());
+        }
+        let restart_indices = vec![vec![0; runs_per_block]; keys.len().div_ceil(runs_per_block)];
+        builder.finish(&restart_indices)
+    }
+
+    #[test]
+    fn test_predictions_stay_within_error() -> Result<(), SSTableError> {
+        // evenly spaced, then a jump, then a quadratic stretch
+        let keys: Vec<u64> = (0..1000u64)
+            .map(|i| i * 16)
+            .chain((0..1000u64).map(|i| (1 << 40) | (i * i * 3)))
+            .collect();
+        let index = fit(&keys, 8, 4);
+        assert!(
+            index.segments.len() < 40,
+            "{} segments",
+            index.segments.len()
+        );
+        assert_eq!(LearnedIndex::decode(&index.encode())?, index);
+
+        for (run, key) in keys.iter().enumerate() {
+            let (first, last) = index.runs(&key.to_be_bytes()).unwrap();
+            assert!(
+                first <= run && run <= last,
+                "run {} not in {}..={}",
+                run,
+                first,
+                last
+            );
+            assert!(last - first <= 2 * 5);
+            // a key inside a run is found next to the run's first key
+            let (first, last) = index.runs(&(key + 1).to_be_bytes()).unwrap();
+            assert!(first <= run && run <= last);
+
+            let (first_block, last_block) = index.blocks(&key.to_be_bytes()).unwrap();
+            assert!(first_block <= run / 8 && run / 8 <= last_block);
+            let (first, last) = index.runs_in_block(run / 8, &key.to_be_bytes()).unwrap();
+            assert!(first <= run % 8 && run % 8 <= last);
+        }
+
+        assert!(fit(&[], 8, 4).blocks(b"anything").is_none());
+        Ok(())
+    }
+}
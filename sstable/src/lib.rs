@@ -1,37 +1,354 @@
 use std::{
     collections::HashMap,
     fs::File,
-    io::{BufReader, Cursor, Read, Seek, SeekFrom},
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    io::{Read, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
+use block_cache::{next_table_id, BlockCache};
+use bytes::Bytes;
 use block_iter::SSTableBlockIterator;
-use bloomfilter::Bloom;
 use chained_blocks::SSTableIterator;
+use cursor::SSTableCursor;
 use error::SSTableError;
-use integer_encoding::VarIntReader;
-use key_value::{key_value_pair::DeltaEncodedKV, KeyValue};
+use filter::TableFilter;
+use index::IndexPart;
+use key_value::{
+    comparator::{default_comparator, Comparator},
+    key_value_pair::DeltaEncodedKV,
+    KeyValue,
+};
+use prefix::PrefixExtractor;
+use learned_index::LearnedIndex;
+use range_filter::RangeFilter;
+use table_cache::TableCache;
+use table_format::{
+    BlockIndex, Footer, IndexPartition, TableIndex, TableMeta, BLOCK_TRAILER_SIZE, FOOTER_SIZE,
+    MAGIC,
+};
 
+pub mod block_cache;
 mod block_iter;
 pub mod builder;
 mod chained_blocks;
+pub mod cursor;
 pub mod error;
+pub mod filter;
+mod index;
+mod learned_index;
+pub mod prefix;
+mod range_filter;
 pub mod streamed_builder;
+pub mod table_cache;
+mod table_format;
+
+/// How tables read their files. Usually one set is shared by every table of
+/// a database.
+#[derive(Debug, Default, Clone)]
+pub struct TableReadOptions {
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// Keeps file handles open between reads.
+    pub table_cache: Option<Arc<TableCache>>,
+    /// Maps the whole file into memory and serves data blocks straight from
+    /// the mapping, bypassing both caches. Index partitions are still kept
+    /// decoded in the block cache.
+    pub mmap: bool,
+}
+
+/// The smallest and largest key of a table.
+pub type KeyRange = (Arc<[u8]>, Arc<[u8]>);
+
+pub(crate) fn map_file(file: &File) -> Result<Bytes, SSTableError> {
+    // SAFETY: tables are never modified once written, and are only removed
+    // after the last handle to them, and so the mapping, is gone
+    let mapping = unsafe { memmap2::Mmap::map(file)? };
+    Ok(Bytes::from_owner(mapping))
+}
+
+/// Makes the entries of `dir` durable. A file created, renamed or removed
+/// in it may be lost in a crash until this returns, even once the file
+/// itself is synced.
+pub fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    // a bare file name lives in the current directory
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    File::open(dir)?.sync_all()
+}
+
+// Syncs a table just written to `path`, and the directory entry naming it,
+// so it survives a crash before anything that depends on it is recorded.
+pub(crate) fn sync_new_file(file: &File, path: &Path) -> Result<(), SSTableError> {
+    file.sync_all()?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))?;
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct SSTable {
     file_path: PathBuf,
-    fd: Option<File>,
-    page_hash_indices: Vec<HashMap<String, usize>>, // One hash index per block
-    fence_pointers: Vec<(Arc<str>, usize)>,
+    // the whole file, when the table is read through a memory mapping
+    mmap: Option<Bytes>,
+    // the whole index, left empty when it is partitioned
+    page_hash_indices: Vec<HashMap<Arc<[u8]>, usize>>, // One hash index per block
+    fence_pointers: Vec<(Arc<[u8]>, usize)>,
     restart_indices: Vec<Vec<usize>>, // Restart indices for each block
-    bloom_filter: Option<Arc<Bloom<String>>>,
+    // where the index partitions are, empty unless the index is partitioned
+    partitions: Vec<IndexPartition>,
+    block_count: usize,
+    filter: Option<TableFilter>,
+    // smallest and largest key, `None` for a table without entries
+    key_range: Option<KeyRange>,
+    range_filter: Option<RangeFilter>,
+    // name of the extractor whose prefixes are in the filter, empty for none
+    prefix_extractor: String,
+    learned_index: Option<LearnedIndex>,
     pub actual_item_count: usize,
+    data_end: usize, // offset just past the last data block
+    max_sequence: u64,
+    comparator: Arc<dyn Comparator>,
+    id: u64,
+    // keys the index partitions in the block cache
+    index_id: u64,
+    read_options: TableReadOptions,
     deleted: Mutex<bool>,
+    obsolete: AtomicBool,
 }
 
 impl SSTable {
+    /// Rebuilds a table from its file alone, reading the fence pointers,
+    /// restart points, page hash indices and key filter from the trailing
+    /// index, filter and meta blocks. The table must have been written in
+    /// bytewise key order.
+    pub fn open(file_path: impl Into<PathBuf>) -> Result<Arc<SSTable>, SSTableError> {
+        SSTable::open_with_comparator(file_path, default_comparator())
+    }
+
+    /// Like `open`, for a table written with `comparator`. Fails if the table
+    /// records a different one.
+    pub fn open_with_comparator(
+        file_path: impl Into<PathBuf>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Arc<SSTable>, SSTableError> {
+        SSTable::open_inner(file_path.into(), comparator, TableReadOptions::default())
+    }
+
+    /// Like `open_with_comparator`, reading the file the way `read_options`
+    /// says.
+    pub fn open_with_options(
+        file_path: impl Into<PathBuf>,
+        comparator: Arc<dyn Comparator>,
+        read_options: TableReadOptions,
+    ) -> Result<Arc<SSTable>, SSTableError> {
+        SSTable::open_inner(file_path.into(), comparator, read_options)
+    }
+
+    fn open_inner(
+        file_path: PathBuf,
+        comparator: Arc<dyn Comparator>,
+        read_options: TableReadOptions,
+    ) -> Result<Arc<SSTable>, SSTableError> {
+        let mut file = File::open(&file_path)?;
+        let file_len = file.metadata()?.len() as usize;
+        if file_len < MAGIC.len() + FOOTER_SIZE {
+            return Err(SSTableError::DecodeError(format!(
+                "{} is too small to be a table",
+                file_path.display()
+            )));
+        }
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SSTableError::FileSystemError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid file magic",
+            )));
+        }
+
+        let mut footer = vec![0u8; FOOTER_SIZE];
+        file.seek(SeekFrom::Start((file_len - FOOTER_SIZE) as u64))?;
+        file.read_exact(&mut footer)?;
+        let footer = Footer::decode(&footer).ok_or_else(|| SSTableError::Corruption {
+            file: file_path.clone(),
+            block: usize::MAX,
+            offset: file_len - FOOTER_SIZE,
+        })?;
+
+        // the trailing blocks are numbered after the data blocks, and any
+        // index partitions after those
+        let mut read_handle = |handle: table_format::BlockHandle, block: u64| {
+            let corruption = || SSTableError::Corruption {
+                file: file_path.clone(),
+                block: (footer.data_blocks + block) as usize,
+                offset: handle.offset as usize,
+            };
+            if handle.offset + handle.size > (file_len - FOOTER_SIZE) as u64 {
+                return Err(corruption());
+            }
+            let mut buffer = vec![0u8; handle.size as usize];
+            file.seek(SeekFrom::Start(handle.offset))?;
+            file.read_exact(&mut buffer)?;
+            if crc32c::crc32c(&buffer) != handle.checksum {
+                return Err(corruption());
+            }
+            Ok(buffer)
+        };
+
+        // the meta block says which version the rest was written in
+        let meta = TableMeta::decode(&read_handle(footer.meta, 2)?)?;
+        if meta.comparator != comparator.name() {
+            return Err(SSTableError::ComparatorMismatch {
+                expected: comparator.name().to_string(),
+                found: meta.comparator,
+            });
+        }
+        let (index, partitions) =
+            match BlockIndex::decode(&read_handle(footer.index, 0)?, meta.version)? {
+                BlockIndex::Full(index) => (index, Vec::new()),
+                BlockIndex::Partitioned(partitions) => (TableIndex::default(), partitions),
+            };
+        let filter = TableFilter::decode(&read_handle(footer.filter, 1)?)?;
+
+        let mmap = read_options.mmap.then(|| map_file(&file)).transpose()?;
+        let item_count = meta.item_count;
+        let mut table = Arc::new(SSTable {
+            file_path,
+            mmap,
+            page_hash_indices: index.page_hash_indices,
+            fence_pointers: index.fence_pointers,
+            restart_indices: index.restart_indices,
+            partitions,
+            block_count: footer.data_blocks as usize,
+            filter,
+            key_range: (!meta.smallest_key.is_empty())
+                .then(|| (meta.smallest_key.into(), meta.largest_key.into())),
+            range_filter: match meta.range_filter.is_empty() {
+                true => None,
+                false => Some(RangeFilter::decode(&meta.range_filter)?),
+            },
+            prefix_extractor: meta.prefix_extractor,
+            learned_index: match meta.learned_index.is_empty() {
+                true => None,
+                false => Some(LearnedIndex::decode(&meta.learned_index)?),
+            },
+            actual_item_count: meta.item_count,
+            data_end: meta.data_end,
+            max_sequence: meta.max_sequence,
+            comparator,
+            id: next_table_id(),
+            index_id: next_table_id(),
+            read_options,
+            deleted: Mutex::new(false),
+            obsolete: AtomicBool::new(false),
+        });
+
+        // tables written before the key range was kept in the meta block
+        // get it from their first and last entry
+        if table.key_range.is_none() && item_count > 0 {
+            let mut cursor = table.cursor();
+            cursor.seek_to_first()?;
+            let smallest = cursor.current().map(|entry| entry.key.clone());
+            cursor.seek_to_last()?;
+            let largest = cursor.current().map(|entry| entry.key.clone());
+            drop(cursor);
+            if let (Some(smallest), Some(largest), Some(table)) =
+                (smallest, largest, Arc::get_mut(&mut table))
+            {
+                table.key_range = Some((smallest, largest));
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// The highest sequence number of any entry in the table.
+    pub fn max_sequence(&self) -> u64 {
+        self.max_sequence
+    }
+
+    /// The filter lookups check before reading a block, if the table has one.
+    pub fn filter(&self) -> Option<&TableFilter> {
+        self.filter.as_ref()
+    }
+
+    /// How many partitions the index is cut into, zero when the whole index
+    /// is held in memory.
+    pub fn index_partitions(&self) -> usize {
+        self.partitions.len()
+    }
+
+    /// The smallest and largest key in the table, `None` if it is empty.
+    pub fn key_range(&self) -> Option<(&[u8], &[u8])> {
+        self.key_range
+            .as_ref()
+            .map(|(smallest, largest)| (&smallest[..], &largest[..]))
+    }
+
+    /// `false` only if no key of the table lies in `[from, to]`. Checks the
+    /// key range, then the range filter if the table has one.
+    pub fn may_overlap(&self, from: &[u8], to: &[u8]) -> bool {
+        let Some((smallest, largest)) = &self.key_range else {
+            return false;
+        };
+        if self.comparator.compare(to, smallest).is_lt()
+            || self.comparator.compare(from, largest).is_gt()
+        {
+            return false;
+        }
+        self.range_filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_overlap(from, to))
+    }
+
+    /// `false` only if no key of the table starts with `prefix`. Checks the
+    /// key range, then the key filter if the table was built with
+    /// `extractor` and `prefix` is one of its prefixes. Only meaningful in
+    /// bytewise key order.
+    pub fn may_contain_prefix(
+        &self,
+        extractor: Option<&dyn PrefixExtractor>,
+        prefix: &[u8],
+    ) -> bool {
+        let Some((smallest, largest)) = &self.key_range else {
+            return false;
+        };
+        // a key with the prefix sorts at or after it, and before any larger
+        // key that doesn't start with it
+        if &largest[..] < prefix || (&smallest[..] > prefix && !smallest.starts_with(prefix)) {
+            return false;
+        }
+        match (extractor, &self.filter) {
+            (Some(extractor), Some(filter))
+                if extractor.name() == self.prefix_extractor
+                    && extractor.in_domain(prefix)
+                    && extractor.transform(prefix) == prefix =>
+            {
+                filter.may_contain(prefix)
+            }
+            _ => true,
+        }
+    }
+
+    /// Deletes the file once the last handle to the table is dropped, so
+    /// that iterators still reading it can finish.
+    pub fn delete_when_unused(&self) {
+        self.obsolete.store(true, Ordering::Release);
+    }
+
     pub fn delete(&self) -> Result<(), SSTableError> {
         let mut deleted = self.deleted.lock().unwrap();
         if *deleted {
@@ -39,6 +356,9 @@ impl SSTable {
             return Ok(());
         }
 
+        if let Some(cache) = &self.read_options.table_cache {
+            cache.evict(self.id);
+        }
         match std::fs::remove_file(&self.file_path) {
             Ok(_) => {
                 *deleted = true;
@@ -54,282 +374,368 @@ impl SSTable {
             }
         }
     }
-    pub fn get(&self, key: String) -> Result<Arc<KeyValue>, SSTableError> {
-        if let Some(filter) = &self.bloom_filter {
-            if !filter.check(&key) {
-                return Err(SSTableError::KeyNotfound);
-            }
+    /// The newest version of `key` in the table.
+    pub fn get(&self, key: &[u8]) -> Result<Arc<KeyValue>, SSTableError> {
+        self.get_at(key, u64::MAX)
+    }
+
+    /// The newest version of `key` written at or before sequence `seq`.
+    /// All versions of a key sit next to each other in a single restart run,
+    /// newest first, so the first one that is old enough wins.
+    pub fn get_at(&self, key: &[u8], seq: u64) -> Result<Arc<KeyValue>, SSTableError> {
+        if !self.may_contain(key) {
+            return Err(SSTableError::KeyNotfound);
         }
 
-        let block_idx = self
-            .find_block_with_fence_pointers(key.clone())
-            .unwrap_or((0, 1));
+        let Some(block) = self.find_block(key)? else {
+            return Err(SSTableError::KeyNotfound);
+        };
+        let block_data = self.read_block(block)?;
+        self.search_block(block, &block_data, key, seq)
+    }
+
+    /// Looks up each of `keys`, which must be sorted by the table's
+    /// comparator, as of sequence `seq`. Keys the filter rules out are
+    /// skipped, and keys that fall in the same block share a single read of
+    /// it. The results line up with `keys`.
+    pub fn multi_get_at(
+        &self,
+        keys: &[&[u8]],
+        seq: u64,
+    ) -> Vec<Result<Arc<KeyValue>, SSTableError>> {
+        let mut block: Option<(usize, Bytes)> = None;
+        keys.iter()
+            .map(|&key| {
+                if !self.may_contain(key) {
+                    return Err(SSTableError::KeyNotfound);
+                }
+                let Some(block_idx) = self.find_block(key)? else {
+                    return Err(SSTableError::KeyNotfound);
+                };
+
+                let data = match &block {
+                    Some((idx, data)) if *idx == block_idx => data.clone(),
+                    _ => {
+                        let data = self.read_block(block_idx)?;
+                        block = Some((block_idx, data.clone()));
+                        data
+                    }
+                };
+                self.search_block(block_idx, &data, key, seq)
+            })
+            .collect()
+    }
 
-        let block_data = self.read_block_from_disk(block_idx)?;
+    /// Every entry whose key starts with `prefix`, all versions, in table
+    /// order. Only meaningful in bytewise key order.
+    ///
+    /// Past the first match, entries are told apart by how many key bytes
+    /// they share with the entry before them: sharing at least the length
+    /// of the prefix means the key has it too, and sharing fewer ends the
+    /// scan. Keys are only compared at entries that share nothing, such as
+    /// restart points.
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<Vec<KeyValue>, SSTableError> {
+        let mut entries = Vec::new();
+        let Some(first_block) = self.find_block(prefix)? else {
+            return Ok(entries);
+        };
 
-        if block_idx.0 >= self.restart_indices.len() || block_idx.0 >= self.page_hash_indices.len()
-        {
-            return Err(SSTableError::KeyNotfound);
+        let mut in_prefix = false;
+        for block in first_block..self.block_count {
+            let data = self.read_block(block)?;
+            let mut position = 0;
+            let mut previous: Option<KeyValue> = None;
+            while position < data.len() {
+                let (dkv, read) = DeltaEncodedKV::decode(&data[position..])
+                    .ok_or_else(|| self.corruption(block, position))?;
+                if in_prefix && dkv.shared_bytes > 0 && dkv.shared_bytes < prefix.len() {
+                    return Ok(entries);
+                }
+                let kv = dkv
+                    .reverse(previous.take())
+                    .ok_or_else(|| self.corruption(block, position))?;
+                if !in_prefix || dkv.shared_bytes == 0 {
+                    in_prefix = kv.key.starts_with(prefix);
+                    if !in_prefix && &kv.key[..] > prefix {
+                        return Ok(entries);
+                    }
+                }
+                if in_prefix {
+                    entries.push(kv.clone());
+                }
+                position += read;
+                previous = Some(kv);
+            }
         }
+        Ok(entries)
+    }
 
-        let block_start = if block_idx.0 < self.fence_pointers.len() {
-            self.fence_pointers[block_idx.0].1
-        } else {
-            4 // Fallback (right after header which is SSTB)
-        };
+    fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_contain(key))
+    }
 
-        let restart_points = self.restart_indices[block_idx.0].clone();
+    // finds `key` in a block through its page hash index, falling back to a
+    // binary search over the restart points
+    fn search_block(
+        &self,
+        block: usize,
+        block_data: &[u8],
+        key: &[u8],
+        seq: u64,
+    ) -> Result<Arc<KeyValue>, SSTableError> {
+        self.with_index_part(block, |part| {
+            match (part.restarts(block), part.hash_index(block)) {
+                (Some(restart_points), Some(hash_index)) => {
+                    self.search_block_with(block, block_data, key, seq, restart_points, hash_index)
+                }
+                _ => Err(SSTableError::KeyNotfound),
+            }
+        })?
+    }
 
-        if let Some(position) = self.page_hash_indices[block_idx.0].get(&key) {
+    fn search_block_with(
+        &self,
+        block: usize,
+        block_data: &[u8],
+        key: &[u8],
+        seq: u64,
+        restart_points: &[usize],
+        hash_index: &HashMap<Arc<[u8]>, usize>,
+    ) -> Result<Arc<KeyValue>, SSTableError> {
+        if let Some(position) = hash_index.get(key) {
             if *position >= restart_points.len() {
                 return Err(SSTableError::KeyNotfound);
             }
 
             let restart_point = restart_points[*position];
-            let read_size = 4096 / 16;
-
             if restart_point >= block_data.len() {
-                return Err(SSTableError::KeyNotfound);
+                return Err(self.corruption(block, restart_point));
             }
 
-            let end_pos = std::cmp::min(restart_point + read_size, block_data.len());
-            let kvps = &block_data[restart_point..end_pos];
-
-            let kvp: Arc<KeyValue> = match self.deserialize_run_get_key(kvps, key.clone()) {
-                Ok(key) => Arc::new(key),
-                Err(SSTableError::KVPexceedsBlock(e)) => {
-                    let fk = self.deserialize_spanning_key(e + block_start, key)?;
-                    return Ok(Arc::new(fk));
-                }
-                Err(_) => return Err(SSTableError::KeyNotfound),
-            };
-            return Ok(kvp);
+            // entries never cross a block boundary, so the rest of the block
+            // always holds the whole run
+            let kvp = self.deserialize_run_get_key(
+                &block_data[restart_point..],
+                block,
+                restart_point,
+                key,
+                seq,
+            )?;
+            return Ok(Arc::new(kvp));
         }
 
-        let kvp: Arc<KeyValue> = match self.binary_search(block_data, key.clone(), &restart_points)
+        if let Some(runs) = self
+            .learned_index
+            .as_ref()
+            .and_then(|learned_index| learned_index.runs_in_block(block, key))
+            && let Some(kvp) =
+                self.search_predicted_runs(block_data, block, key, seq, restart_points, runs)?
         {
-            Ok(key) => Arc::new(key),
-            Err(SSTableError::KVPexceedsBlock(e)) => {
-                let fk = self.deserialize_spanning_key(e + block_start, key.clone())?;
-                return Ok(Arc::new(fk));
-            }
-            Err(_) => return Err(SSTableError::KeyNotfound),
-        };
-        Ok(kvp)
+            return Ok(Arc::new(kvp));
+        }
+
+        let kvp = self.binary_search(block_data, block, key, seq, restart_points)?;
+        Ok(Arc::new(kvp))
     }
 
-    fn deserialize_spanning_key(
+    // searches only the runs `first..=last` of the block, which the learned
+    // index predicts. `Ok(None)` when the key turns out to lie outside them
+    fn search_predicted_runs(
         &self,
-        start_offset: usize,
-        needle: String,
-    ) -> Result<KeyValue, SSTableError> {
-        let mut file = if let Some(ref f) = self.fd {
-            f.try_clone().map_err(SSTableError::FileSystemError)?
-        } else {
-            File::open(&self.file_path).map_err(SSTableError::FileSystemError)?
+        block_data: &[u8],
+        block: usize,
+        key: &[u8],
+        seq: u64,
+        restart_points: &[usize],
+        (mut first, mut last): (usize, usize),
+    ) -> Result<Option<KeyValue>, SSTableError> {
+        let run = |run: usize| -> Result<(usize, &[u8]), SSTableError> {
+            let start = restart_points[run];
+            let end = restart_points.get(run + 1).copied().unwrap_or(block_data.len());
+            if start > end || end > block_data.len() {
+                return Err(self.corruption(block, start));
+            }
+            Ok((start, &block_data[start..end]))
+        };
+        let starts_after = |index: usize| -> Result<bool, SSTableError> {
+            let (start, data) = run(index)?;
+            let first_key = self.deserialize_first_key_from_run(data, block, start)?.key;
+            Ok(self.comparator.compare(&first_key, key).is_gt())
         };
 
-        file.seek(SeekFrom::Start(start_offset as u64))
-            .map_err(SSTableError::FileSystemError)?;
-
-        let mut reader = BufReader::new(file);
-        let mut buffer = Vec::new();
-
-        loop {
-            let mut chunk = [0u8; 4096];
-            let n = reader
-                .read(&mut chunk)
-                .map_err(SSTableError::FileSystemError)?;
-            if n == 0 {
-                log::info!("Broke the key!! D:");
-                break;
+        let Some(last_run) = restart_points.len().checked_sub(1) else {
+            return Ok(None);
+        };
+        last = last.min(last_run);
+        if first > last
+            || (first > 0 && starts_after(first)?)
+            || (last + 1 < restart_points.len() && !starts_after(last + 1)?)
+        {
+            return Ok(None);
+        }
+        // the last run of the window that starts at or before the key
+        while first < last {
+            let mid = first + (last - first).div_ceil(2);
+            match starts_after(mid)? {
+                true => last = mid - 1,
+                false => first = mid,
             }
-            buffer.extend_from_slice(&chunk[..n]);
+        }
+        let (start, data) = run(first)?;
+        self.deserialize_run_get_key(data, block, start, key, seq)
+            .map(Some)
+    }
 
-            match self.deserialize_run_get_key(&buffer, needle.clone()) {
-                Ok(kv) => return Ok(kv),
-                Err(SSTableError::KVPexceedsBlock(_)) => continue,
-                Err(e) => return Err(e),
-            }
+    fn corruption(&self, block: usize, offset_in_block: usize) -> SSTableError {
+        let block_start = self
+            .with_index_part(block, |part| part.bounds(block))
+            .ok()
+            .flatten()
+            .map(|(start, _)| start)
+            .unwrap_or(self.data_end);
+        SSTableError::Corruption {
+            file: self.file_path.clone(),
+            block,
+            offset: block_start + offset_in_block,
         }
-        Err(SSTableError::KeyNotfound)
     }
 
+    // `run_offset` is where `run` starts inside the block, used for error reporting.
     fn deserialize_run_get_key(
         &self,
         run: &[u8],
-        needle: String,
+        block: usize,
+        run_offset: usize,
+        needle: &[u8],
+        seq: u64,
     ) -> Result<KeyValue, SSTableError> {
-        // Return None if run is empty
-        if run.is_empty() {
-            return Err(SSTableError::KeyNotfound);
-        }
-
-        let mut cursor = Cursor::new(run);
+        let mut position = 0;
         let mut previous_key: Option<KeyValue> = None;
 
-        while (cursor.position() as usize) < run.len() {
-            let remaining_size = run.len() - cursor.position() as usize;
-            if remaining_size <= 1 {
-                return Err(SSTableError::KVPexceedsBlock(cursor.position() as usize));
-            }
-
-            let shared_bytes = match cursor.read_varint() {
-                Ok(val) => val,
-                Err(_) => break,
-            };
-            let unshared_bytes = match cursor.read_varint() {
-                Ok(val) => val,
-                Err(_) => break,
-            };
-            if remaining_size <= unshared_bytes {
-                return Err(SSTableError::KVPexceedsBlock(cursor.position() as usize));
-            }
-
-            let value_bytes = match cursor.read_varint() {
-                Ok(val) => val,
-                Err(_) => break,
-            };
-            if remaining_size <= value_bytes {
-                return Err(SSTableError::KVPexceedsBlock(cursor.position() as usize));
-            }
-
-            if unshared_bytes > run.len() - cursor.position() as usize
-                || value_bytes > run.len() - cursor.position() as usize - unshared_bytes
-            {
-                return Err(SSTableError::KVPexceedsBlock(cursor.position() as usize));
-            }
-
-            let mut key_delta = vec![0u8; unshared_bytes];
-            if cursor.read_exact(&mut key_delta).is_err() {
-                break;
-            }
-
-            let mut value = vec![0u8; value_bytes];
-            if cursor.read_exact(&mut value).is_err() {
-                break;
-            }
-
-            let dkv = DeltaEncodedKV {
-                shared_bytes,
-                unshared_bytes,
-                value_bytes,
-                key_delta: key_delta.into_boxed_slice(),
-                value: value.into_boxed_slice(),
-            };
-
-            let as_key = match dkv.reverse(previous_key) {
-                Some(kv) => kv,
-                None => break,
-            };
+        while position < run.len() {
+            let (dkv, read) = DeltaEncodedKV::decode(&run[position..])
+                .ok_or_else(|| self.corruption(block, run_offset + position))?;
+            let as_key = dkv
+                .reverse(previous_key)
+                .ok_or_else(|| self.corruption(block, run_offset + position))?;
 
-            if as_key.key == needle {
+            if self.comparator.compare(&as_key.key, needle).is_eq() && as_key.seq <= seq {
                 return Ok(as_key);
             }
+            position += read;
             previous_key = Some(as_key);
         }
 
         Err(SSTableError::KeyNotfound)
     }
 
-    fn deserialize_first_key_from_run(&self, run: &[u8]) -> Option<KeyValue> {
-        if run.is_empty() {
-            return None;
-        }
-
-        let mut cursor = Cursor::new(run);
-
-        let shared_bytes = match cursor.read_varint() {
-            Ok(val) => val,
-            Err(_) => return None,
-        };
+    fn deserialize_first_key_from_run(
+        &self,
+        run: &[u8],
+        block: usize,
+        run_offset: usize,
+    ) -> Result<KeyValue, SSTableError> {
+        let (dkv, _) =
+            DeltaEncodedKV::decode(run).ok_or_else(|| self.corruption(block, run_offset))?;
 
-        if shared_bytes != 0 {
+        if dkv.shared_bytes != 0 {
             log::info!("WARNING: First key shared byte is not 0. You are probably mangling keys!");
         }
 
-        let unshared_bytes = match cursor.read_varint() {
-            Ok(val) => val,
-            Err(_) => return None,
-        };
-
-        let value_bytes = match cursor.read_varint() {
-            Ok(val) => val,
-            Err(_) => return None,
-        };
-
         log::info!(
             "DEBUG: First key metadata: shared_bytes={}, unshared_bytes={}, value_bytes={}",
-            shared_bytes,
-            unshared_bytes,
-            value_bytes
+            dkv.shared_bytes,
+            dkv.unshared_bytes,
+            dkv.value_bytes
         );
-        if unshared_bytes > run.len() || value_bytes > run.len() {
-            return None;
-        }
 
-        let mut key_delta = vec![0u8; unshared_bytes];
-        if cursor.read_exact(&mut key_delta).is_err() {
-            return None;
+        dkv.reverse(None)
+            .ok_or_else(|| self.corruption(block, run_offset))
+    }
+
+    // the block that holds `key` if any block does: the last one starting at
+    // or before it, or else the first one
+    fn find_block(&self, key: &[u8]) -> Result<Option<usize>, SSTableError> {
+        if self.partitions.is_empty() {
+            if let Some(block) = self.find_predicted_block(key) {
+                return Ok(Some(block));
+            }
+            return Ok(self.find_block_with_fence_pointers(key).map(|(block, _)| block));
         }
+        let partition = self
+            .partitions
+            .partition_point(|partition| {
+                self.comparator.compare(&partition.first_key, key).is_le()
+            })
+            .saturating_sub(1);
+        let entries = self.load_partition(partition)?;
+        let first_block = self.partitions[partition].first_block;
+        Ok(self
+            .search_fences(&entries.fence_pointers, key)
+            .map(|(block, _)| first_block + block))
+    }
 
-        let mut value = vec![0u8; value_bytes];
-        if cursor.read_exact(&mut value).is_err() {
+    // searches only the fence pointers the learned index points at. `None`
+    // without a learned index, or when the key turns out to lie outside them
+    fn find_predicted_block(&self, key: &[u8]) -> Option<usize> {
+        let (first, last) = self.learned_index.as_ref()?.blocks(key)?;
+        let fences = self.fence_pointers.get(first..=last)?;
+        let before = first > 0 && self.comparator.compare(&fences[0].0, key).is_gt();
+        let after = self
+            .fence_pointers
+            .get(last + 1)
+            .is_some_and(|(next, _)| self.comparator.compare(next, key).is_le());
+        if before || after {
             return None;
         }
+        self.search_fences(fences, key)
+            .map(|(block, _)| first + block)
+    }
 
-        let dkv = DeltaEncodedKV {
-            shared_bytes,
-            unshared_bytes,
-            value_bytes,
-            key_delta: key_delta.into_boxed_slice(),
-            value: value.into_boxed_slice(),
-        };
-
-        dkv.reverse(None)
+    fn find_block_with_fence_pointers(&self, key: &[u8]) -> Option<(usize, usize)> {
+        self.search_fences(&self.fence_pointers, key)
     }
 
-    fn find_block_with_fence_pointers(&self, key: String) -> Option<(usize, usize)> {
+    fn search_fences(
+        &self,
+        fence_pointers: &[(Arc<[u8]>, usize)],
+        key: &[u8],
+    ) -> Option<(usize, usize)> {
         // say we get a key with first letter b, and fp 1 is a, fp 2 is B
         // in that case we want to return 1
         // so we binary search over the key and search the range inbetween fence_pounts[mid], fence_pointers[mid+1]
-        if self.fence_pointers.is_empty() {
+        if fence_pointers.is_empty() {
             return None;
         }
+        let cmp = |fence: usize| self.comparator.compare(key, &fence_pointers[fence].0);
 
-        if key.as_str() < self.fence_pointers[0].0.as_ref() {
+        if cmp(0).is_lt() {
             return Some((0, 1));
         }
 
-        if key.as_str() >= self.fence_pointers.last().unwrap().0.as_ref() {
-            return Some((self.fence_pointers.len() - 1, self.fence_pointers.len()));
+        if cmp(fence_pointers.len() - 1).is_ge() {
+            return Some((fence_pointers.len() - 1, fence_pointers.len()));
         }
 
         // for keys between fence pointers, bs
         let mut left = 0;
-        let mut right = self.fence_pointers.len() - 1;
+        let mut right = fence_pointers.len() - 1;
 
         while left < right {
             let mid = left + (right - left) / 2;
             let next = mid + 1;
-            let current_key = self.fence_pointers[mid].0.as_ref();
-            let next_key = self.fence_pointers[next].0.as_ref();
 
-            if current_key == key.as_str() {
-                return Some((mid, next));
-            }
-            if current_key == next_key && key.as_str() == current_key {
+            if cmp(mid).is_eq() {
                 return Some((mid, next));
             }
 
-            if mid + 1 < self.fence_pointers.len()
-                && key.as_str() >= self.fence_pointers[mid].0.as_ref()
-                && key.as_str() < self.fence_pointers[mid + 1].0.as_ref()
-            {
+            if mid + 1 < fence_pointers.len() && cmp(mid).is_gt() && cmp(next).is_lt() {
                 return Some((mid, next));
             }
 
-            if key.as_str() < self.fence_pointers[mid].0.as_ref() {
+            if cmp(mid).is_lt() {
                 right = mid;
             } else {
                 left = mid + 1;
@@ -339,47 +745,34 @@ impl SSTable {
         Some((left - 1, left))
     }
 
-    fn read_block_from_disk(&self, block_idx: (usize, usize)) -> Result<Arc<[u8]>, SSTableError> {
-        let mut reader: BufReader<&File>;
-        let file: File;
-        if let Some(file) = &self.fd {
-            reader = BufReader::new(file);
-        } else {
-            file = File::open(&self.file_path).map_err(SSTableError::FileSystemError)?;
-            reader = BufReader::new(&file);
-        }
-
-        log::info!("DEBUG: Reading block with index: {:?}", block_idx);
-
-        if block_idx.0 == 0 {
-            // we have a magic SSTB at the start
-            reader.seek(SeekFrom::Start(0))?;
-            let mut magic = [0u8; 4];
-            reader.read_exact(&mut magic)?;
-
-            if &magic != b"SSTB" {
-                return Err(SSTableError::FileSystemError(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid file magic",
-                )));
-            }
-        } else {
-            reader.seek(SeekFrom::Start(4))?;
+    // goes to disk only when the block cache doesn't have the block. Mapped
+    // tables skip the cache, their blocks are already in memory.
+    fn read_block(&self, block: usize) -> Result<Bytes, SSTableError> {
+        let Some(cache) = self.read_options.block_cache.as_ref().filter(|_| self.mmap.is_none())
+        else {
+            return self.read_block_from_disk(block);
+        };
+        if let Some(data) = cache.get(self.id, block) {
+            return Ok(data);
         }
+        let data = self.read_block_from_disk(block)?;
+        cache.insert(self.id, block, data.clone());
+        Ok(data)
+    }
 
-        let start_offset = if block_idx.0 < self.fence_pointers.len() {
-            self.fence_pointers[block_idx.0].1
-        } else {
-            4 // right after head
-        };
+    fn read_block_from_disk(&self, block: usize) -> Result<Bytes, SSTableError> {
+        log::info!("DEBUG: Reading block with index: {}", block);
 
-        let end_offset = if block_idx.1 < self.fence_pointers.len() {
-            self.fence_pointers[block_idx.1].1
-        } else {
-            std::fs::metadata(&self.file_path)?.len() as usize - 4 // Before the Footer
+        let Some((start_offset, end_offset)) =
+            self.with_index_part(block, |part| part.bounds(block))?
+        else {
+            return Err(self.corruption(block, 0));
         };
 
-        let block_size = end_offset - start_offset;
+        if end_offset < start_offset + BLOCK_TRAILER_SIZE {
+            return Err(self.corruption(block, 0));
+        }
+        let block_size = end_offset - start_offset - BLOCK_TRAILER_SIZE;
         log::info!(
             "DEBUG: Reading from offset {} to {}, size {}",
             start_offset,
@@ -387,54 +780,130 @@ impl SSTable {
             block_size
         );
 
-        reader.seek(SeekFrom::Start(start_offset as u64))?;
-        let mut block_data = vec![0u8; block_size];
-        reader.read_exact(&mut block_data)?;
+        let block_data =
+            self.read_range(start_offset, end_offset, || self.corruption(block, 0))?;
+
+        let (block_data, trailer) = (
+            block_data.slice(..block_size),
+            &block_data[block_size..],
+        );
+        if crc32c::crc32c(&block_data) != u32::from_le_bytes(trailer.try_into().unwrap()) {
+            return Err(self.corruption(block, 0));
+        }
 
         log::info!("DEBUG: Successfully read {} bytes", block_data.len());
-        if !block_data.is_empty() {
-            let display_size = std::cmp::min(block_data.len(), 50);
-            log::info!(
-                "DEBUG: First {} bytes: {:?}",
-                display_size,
-                &block_data[..display_size]
-            );
+        Ok(block_data)
+    }
+
+    // the bytes at `[start, end)` of the file, `corruption` if the file is
+    // shorter than that
+    fn read_range(
+        &self,
+        start: usize,
+        end: usize,
+        corruption: impl Fn() -> SSTableError,
+    ) -> Result<Bytes, SSTableError> {
+        match &self.mmap {
+            Some(mapping) => {
+                if end > mapping.len() {
+                    return Err(corruption());
+                }
+                Ok(mapping.slice(start..end))
+            }
+            None => {
+                let file = match &self.read_options.table_cache {
+                    Some(cache) => cache.file(self.id, &self.file_path)?,
+                    None => Arc::new(File::open(&self.file_path)?),
+                };
+                let mut buffer = vec![0u8; end - start];
+                match file.read_exact_at(&mut buffer, start as u64) {
+                    Ok(()) => Ok(Bytes::from(buffer)),
+                    // the file is shorter than the index says it is
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(corruption()),
+                    Err(e) => Err(SSTableError::FileSystemError(e)),
+                }
+            }
+        }
+    }
+
+    // runs `f` on the index entries that cover `block`: the whole index, or
+    // the partition holding the block, loaded if it isn't cached
+    fn with_index_part<R>(
+        &self,
+        block: usize,
+        f: impl FnOnce(IndexPart<'_>) -> R,
+    ) -> Result<R, SSTableError> {
+        if self.partitions.is_empty() {
+            return Ok(f(IndexPart {
+                fence_pointers: &self.fence_pointers,
+                restart_indices: &self.restart_indices,
+                page_hash_indices: &self.page_hash_indices,
+                first_block: 0,
+                end: self.data_end,
+            }));
+        }
+        let partition = self
+            .partitions
+            .partition_point(|partition| partition.first_block <= block)
+            .saturating_sub(1);
+        let entries = self.load_partition(partition)?;
+        let partition = &self.partitions[partition];
+        Ok(f(IndexPart::new(&entries, partition.first_block, partition.end)))
+    }
+
+    fn load_partition(&self, partition: usize) -> Result<Arc<TableIndex>, SSTableError> {
+        let cache = self.read_options.block_cache.as_ref();
+        if let Some(entries) = cache.and_then(|cache| cache.get_index(self.index_id, partition)) {
+            return Ok(entries);
         }
 
-        Ok(Arc::from(block_data.into_boxed_slice()))
+        let handle = self.partitions[partition].handle;
+        // partitions are numbered after the index, filter and meta blocks
+        let corruption = || SSTableError::Corruption {
+            file: self.file_path.clone(),
+            block: self.block_count + 3 + partition,
+            offset: handle.offset as usize,
+        };
+        let start = handle.offset as usize;
+        let data = self.read_range(start, start + handle.size as usize, corruption)?;
+        if crc32c::crc32c(&data) != handle.checksum {
+            return Err(corruption());
+        }
+        let entries = Arc::new(TableIndex::decode(&data)?);
+        if let Some(cache) = cache {
+            cache.insert_index(self.index_id, partition, entries.clone(), data.len());
+        }
+        Ok(entries)
     }
 
     fn linear_search(
         &self,
-        block_data: Arc<[u8]>,
-        key: String,
+        block_data: &[u8],
+        block: usize,
+        key: &[u8],
+        seq: u64,
         _restart_points: &[usize],
     ) -> Result<KeyValue, SSTableError> {
         // Try linear scan first for debugging
         log::info!("DEBUG: defaulting to linear scan for now");
-        let kv = self.deserialize_run_get_key(&block_data, key.clone());
-        match kv {
-            Ok(kv) => return Ok(kv),
-            Err(SSTableError::KVPexceedsBlock(e)) => return Err(SSTableError::KVPexceedsBlock(e)),
-            _ => {}
-        }
-        Err(SSTableError::KeyNotfound)
+        self.deserialize_run_get_key(block_data, block, 0, key, seq)
     }
 
     fn binary_search(
         &self,
-        block_data: Arc<[u8]>,
-        key: String,
+        block_data: &[u8],
+        block: usize,
+        key: &[u8],
+        seq: u64,
         restart_points: &[usize],
     ) -> Result<KeyValue, SSTableError> {
-        log::info!("DEBUG: Binary searching for key: '{}'", key);
+        log::info!("DEBUG: Binary searching for key: {:?}", key);
         log::info!("DEBUG: Block data size: {} bytes", block_data.len());
         log::info!("DEBUG: Restart points: {:?}", restart_points);
-        log::info!("DEBUG: Binary search complete, key not found");
         // Handle empty restart points
         if restart_points.is_empty() {
             log::info!("DEBUG: No restart points available");
-            return self.linear_search(block_data, key, restart_points);
+            return self.linear_search(block_data, block, key, seq, restart_points);
         }
 
         let mut left = 0;
@@ -449,11 +918,6 @@ impl SSTable {
                 right
             );
 
-            if mid >= restart_points.len() {
-                log::info!("DEBUG: Mid index out of bounds");
-                break;
-            }
-
             let restart_pos = restart_points[mid];
 
             let run_end = if mid + 1 < restart_points.len() {
@@ -462,74 +926,74 @@ impl SSTable {
                 block_data.len()
             };
 
+            if restart_pos > run_end || run_end > block_data.len() {
+                return Err(self.corruption(block, restart_pos));
+            }
+
             log::info!("DEBUG: Restart position: {}", restart_pos);
             log::info!("DEBUG: Restart End: {}", run_end);
 
             let run = &block_data[restart_pos..run_end];
             log::info!("DEBUG: Run size: {} bytes", run.len());
-            if !run.is_empty() {
-                log::info!(
-                    "DEBUG: First few bytes: {:?}",
-                    &run[..std::cmp::min(10, run.len())]
-                );
-            }
 
-            if let Ok(key) = self.deserialize_run_get_key(run, key.clone()) {
-                return Ok(key);
+            match self.deserialize_run_get_key(run, block, restart_pos, key, seq) {
+                Ok(kv) => return Ok(kv),
+                Err(SSTableError::KeyNotfound) => {}
+                Err(e) => return Err(e),
             }
 
-            if let Some(keyvalue) = self.deserialize_first_key_from_run(run) {
-                log::info!("DEBUG: First key in run: '{}'", keyvalue.key);
-                if keyvalue.key > key {
-                    log::info!("DEBUG: First key > target key, moving left");
-                    right = mid;
-                } else {
-                    log::info!("DEBUG: First key <= target key, moving right");
-                    left = mid + 1;
-                }
+            let keyvalue = self.deserialize_first_key_from_run(run, block, restart_pos)?;
+            log::info!("DEBUG: First key in run: {:?}", keyvalue.key);
+            if self.comparator.compare(&keyvalue.key, key).is_gt() {
+                log::info!("DEBUG: First key > target key, moving left");
+                right = mid;
             } else {
-                log::info!("DEBUG: Failed to deserialize first key from run");
+                log::info!("DEBUG: First key <= target key, moving right");
                 left = mid + 1;
             }
         }
-        self.linear_search(block_data, key, restart_points)
+        log::info!("DEBUG: Binary search complete, key not found");
+        self.linear_search(block_data, block, key, seq, restart_points)
     }
 
-    pub fn iter_block(
-        &self,
-        block_idx: (usize, usize),
-    ) -> Result<SSTableBlockIterator, SSTableError> {
-        let block_data = self.read_block_from_disk(block_idx)?.clone();
-
-        Ok(SSTableBlockIterator::new(block_data))
-    }
-    pub fn iter(&self) -> SSTableIterator {
-        SSTableIterator::new(self)
+    pub fn iter_block(&self, block: usize) -> Result<SSTableBlockIterator, SSTableError> {
+        let block_data = self.read_block(block)?;
+        let block_start = self
+            .with_index_part(block, |part| part.bounds(block))?
+            .map(|(start, _)| start)
+            .unwrap_or(MAGIC.len());
+
+        Ok(SSTableBlockIterator::new(
+            block_data,
+            self.file_path.clone(),
+            block,
+            block_start,
+        ))
     }
 
-    pub fn get_until(&self, to_key: &str) -> Result<(Vec<Box<KeyValue>>, bool), SSTableError> {
-        let mut result = Vec::new();
-        let mut found = false;
+    /// How many data blocks the table has.
+    pub fn block_count(&self) -> usize {
+        self.block_count
+    }
 
-        for kv_res in self.iter() {
-            let kv = Box::new(kv_res.unwrap());
-            if kv.key == to_key {
-                result.push(kv);
-                found = true;
-                break;
-            }
-            result.push(kv);
-        }
+    pub fn iter(self: &Arc<Self>) -> SSTableIterator {
+        SSTableIterator::new(self.clone(), 0)
+    }
 
-        Ok((result, found))
+    /// A cursor over the table that starts out invalid; position it with one
+    /// of its seeks.
+    pub fn cursor(self: &Arc<Self>) -> SSTableCursor {
+        SSTableCursor::new(self.clone())
     }
 }
 
-impl<'a> IntoIterator for &'a SSTable {
-    type Item = Result<KeyValue, SSTableError>;
-    type IntoIter = SSTableIterator<'a>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+impl Drop for SSTable {
+    fn drop(&mut self) {
+        if *self.obsolete.get_mut()
+            && let Err(e) = self.delete()
+        {
+            log::error!("Failed to delete obsolete table: {:?}", e);
+        }
     }
 }
 
@@ -537,6 +1001,7 @@ impl<'a> IntoIterator for &'a SSTable {
 mod tests {
     use super::*;
     use builder::{SSTableBuilder, SSTableFeatures};
+    use filter::{BlockedBloomPolicy, FilterPolicy, RibbonPolicy, XorPolicy};
     use key_value::KeyValue;
     use std::collections::BTreeMap;
     use std::path::PathBuf;
@@ -544,42 +1009,50 @@ mod tests {
     use tempfile::tempdir;
 
     fn create_test_kv(key: &str, value: &str) -> KeyValue {
-        KeyValue {
-            key: key.to_string(),
-            value: value.to_string(),
-        }
+        KeyValue::new(key, value)
     }
 
     impl SSTable {
-        fn new_for_tests(fence_pointers: Vec<(Arc<str>, usize)>) -> Self {
-            // create a minimal valid bloom filter for testing
-            //let bloom = Bloom::new_for_fp_rate(100, 0.01).unwrap();
+        fn new_for_tests(fence_pointers: Vec<(Arc<[u8]>, usize)>) -> Self {
 
             Self {
                 file_path: PathBuf::from("test.sst"),
-                fd: None,
+                mmap: None,
                 page_hash_indices: Vec::new(),
                 fence_pointers,
                 restart_indices: Vec::new(),
-                bloom_filter: None,
+                partitions: Vec::new(),
+                block_count: 0,
+                filter: None,
+                key_range: None,
+                range_filter: None,
+                prefix_extractor: String::new(),
+                learned_index: None,
                 actual_item_count: 0,
+                data_end: 0,
+                max_sequence: 0,
+                comparator: default_comparator(),
+                id: next_table_id(),
+                index_id: next_table_id(),
+                read_options: TableReadOptions::default(),
                 deleted: Mutex::new(false),
+                obsolete: AtomicBool::new(false),
             }
         }
     }
 
     // Helper function to create fence pointers
-    fn create_fence_pointers(keys: Vec<&str>) -> Vec<(Arc<str>, usize)> {
+    fn create_fence_pointers(keys: Vec<&str>) -> Vec<(Arc<[u8]>, usize)> {
         keys.into_iter()
             .enumerate()
-            .map(|(i, k)| (Arc::from(k), i))
+            .map(|(i, k)| (Arc::from(k.as_bytes()), i))
             .collect()
     }
 
     #[test]
     fn test_empty_fence_pointers() {
         let sstable = SSTable::new_for_tests(vec![]);
-        let result = sstable.find_block_with_fence_pointers("any_key".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"any_key");
         assert_eq!(result, None);
     }
 
@@ -589,13 +1062,13 @@ mod tests {
         let sstable = SSTable::new_for_tests(fence_pointers);
 
         // Any key should return block 0 since there's only one block
-        let result = sstable.find_block_with_fence_pointers("a".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"a");
         assert_eq!(result, Some((0, 1)));
 
-        let result = sstable.find_block_with_fence_pointers("m".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"m");
         assert_eq!(result, Some((0, 1)));
 
-        let result = sstable.find_block_with_fence_pointers("z".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"z");
         assert_eq!(result, Some((0, 1)));
     }
 
@@ -604,10 +1077,10 @@ mod tests {
         let fence_pointers = create_fence_pointers(vec!["e", "j", "o", "t", "z"]);
         let sstable = SSTable::new_for_tests(fence_pointers);
 
-        let result = sstable.find_block_with_fence_pointers("a".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"a");
         assert_eq!(result, Some((0, 1)));
 
-        let result = sstable.find_block_with_fence_pointers("d".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"d");
         assert_eq!(result, Some((0, 1)));
     }
 
@@ -616,7 +1089,7 @@ mod tests {
         let fence_pointers = create_fence_pointers(vec!["e", "j", "o", "t", "z"]);
         let sstable = SSTable::new_for_tests(fence_pointers);
 
-        let result = sstable.find_block_with_fence_pointers("zz".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"zz");
         assert_eq!(result, Some((4, 5))); // Should return index of the last fence pointer
     }
 
@@ -626,13 +1099,13 @@ mod tests {
         let sstable = SSTable::new_for_tests(fence_pointers);
 
         // Should return the block containing the key
-        let result = sstable.find_block_with_fence_pointers("e".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"e");
         assert_eq!(result, Some((0, 1)));
 
-        let result = sstable.find_block_with_fence_pointers("j".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"j");
         assert_eq!(result, Some((1, 2)));
 
-        let result = sstable.find_block_with_fence_pointers("o".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"o");
         assert_eq!(result, Some((2, 3)));
     }
 
@@ -642,16 +1115,16 @@ mod tests {
         let sstable = SSTable::new_for_tests(fence_pointers);
 
         // Should return the block where the key would be found
-        let result = sstable.find_block_with_fence_pointers("g".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"g");
         assert_eq!(result, Some((0, 1))); // Between "e" and "j", should return 0
 
-        let result = sstable.find_block_with_fence_pointers("l".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"l");
         assert_eq!(result, Some((1, 2))); // Between "j" and "o", should return 1
 
-        let result = sstable.find_block_with_fence_pointers("r".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"r");
         assert_eq!(result, Some((2, 3))); // Between "o" and "t", should return 2
 
-        let result = sstable.find_block_with_fence_pointers("w".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"w");
         assert_eq!(result, Some((3, 4))); // Between "t" and "z", should return 3
     }
 
@@ -661,13 +1134,13 @@ mod tests {
         let sstable = SSTable::new_for_tests(fence_pointers);
 
         // Keys just before and after fence pointers
-        let result = sstable.find_block_with_fence_pointers("i".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"i");
         assert_eq!(result, Some((0, 1))); // Just before "j", should return 0
 
-        let result = sstable.find_block_with_fence_pointers("j".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"j");
         assert_eq!(result, Some((1, 2))); // Exact match "j", correctly return 1
 
-        let result = sstable.find_block_with_fence_pointers("j0".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"j0");
         assert_eq!(result, Some((1, 2))); // Just after "j", should return 1
     }
 
@@ -677,10 +1150,10 @@ mod tests {
         let sstable = SSTable::new_for_tests(fence_pointers);
 
         // Lowercase keys should be treated differently from uppercase (lexicographically)
-        let result = sstable.find_block_with_fence_pointers("a".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"a");
         assert_eq!(result, Some((4, 5)));
 
-        let result = sstable.find_block_with_fence_pointers("m".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"m");
         assert_eq!(result, Some((4, 5))); // Should match block 2, assuming "m" > "M" lexicographically
     }
 
@@ -690,10 +1163,10 @@ mod tests {
         let sstable = SSTable::new_for_tests(fence_pointers);
 
         // Lexicographical comparison for numeric strings
-        let result = sstable.find_block_with_fence_pointers("2".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"2");
         assert_eq!(result, Some((4, 5))); // Between "1" and "5"
 
-        let result = sstable.find_block_with_fence_pointers("20".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"20");
         assert_eq!(result, Some((4, 5))); // Between "10" and "50"
     }
 
@@ -701,15 +1174,15 @@ mod tests {
     fn test_consecutive_identical_keys() {
         // Edge case: consecutive fence pointers with the same key
         let fence_pointers = vec![
-            (Arc::from("a"), 0),
-            (Arc::from("m"), 1),
-            (Arc::from("m"), 2), // Duplicate key
-            (Arc::from("z"), 3),
+            (Arc::from(&b"a"[..]), 0),
+            (Arc::from(&b"m"[..]), 1),
+            (Arc::from(&b"m"[..]), 2), // Duplicate key
+            (Arc::from(&b"z"[..]), 3),
         ];
 
         let sstable = SSTable::new_for_tests(fence_pointers);
 
-        let result = sstable.find_block_with_fence_pointers("m".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"m");
         // Should find the first "m" at index 1
         assert_eq!(result, Some((1, 2)));
     }
@@ -719,7 +1192,7 @@ mod tests {
     fn test_upper_bound_calculation() {
         let fence_pointers = create_fence_pointers(vec!["a", "z"]);
         let sstable = SSTable::new_for_tests(fence_pointers);
-        let result = sstable.find_block_with_fence_pointers("m".to_string());
+        let result = sstable.find_block_with_fence_pointers(b"m");
         assert_eq!(result, Some((0, 1)));
     }
 
@@ -735,6 +1208,7 @@ mod tests {
         let features = SSTableFeatures {
             fpr: 0.01,
             item_count: 1000,
+            ..Default::default()
         };
 
         log::info!("Creating SSTableBuilder...");
@@ -768,7 +1242,7 @@ mod tests {
         if !sstable.fence_pointers.is_empty() {
             log::info!("Fence pointers:");
             for (i, (key, offset)) in sstable.fence_pointers.iter().enumerate() {
-                log::info!("  [{}] Key: '{}', Offset: {}", i, key, offset);
+                log::info!("  [{}] Key: {:?}, Offset: {}", i, key, offset);
             }
         }
 
@@ -782,7 +1256,7 @@ mod tests {
         if !sstable.page_hash_indices.is_empty() {
             log::info!("Page hash indices (first block):");
             for (key, pos) in sstable.page_hash_indices[0].iter() {
-                log::info!("  Key: '{}', Position: {}", key, pos);
+                log::info!("  Key: {:?}, Position: {}", key, pos);
             }
         }
         log::info!("checking if file exists: {}", file_path.exists());
@@ -795,11 +1269,11 @@ mod tests {
 
         log::info!("finding block with fence pointers...");
         let block_idx = sstable
-            .find_block_with_fence_pointers(test_key.to_string())
+            .find_block_with_fence_pointers(test_key.as_bytes())
             .unwrap_or((0, 1));
         log::info!("block index: {:?}", block_idx);
 
-        let block_data_result = sstable.read_block_from_disk(block_idx);
+        let block_data_result = sstable.read_block_from_disk(block_idx.0);
         match &block_data_result {
             Ok(data) => log::info!("block data size: {} bytes", data.len()),
             Err(e) => log::info!("error reading block: {:?}", e),
@@ -822,20 +1296,20 @@ mod tests {
         if block_idx.0 < sstable.page_hash_indices.len() {
             let page_hash = &sstable.page_hash_indices[block_idx.0];
             log::info!("page hash count: {}", page_hash.len());
-            log::info!("does page has key? {}", page_hash.contains_key(test_key));
+            log::info!("does page has key? {}", page_hash.contains_key(test_key.as_bytes()));
 
-            if let Some(pos) = page_hash.get(test_key) {
+            if let Some(pos) = page_hash.get(test_key.as_bytes()) {
                 log::info!("position in page hash: {}", pos);
             }
         } else {
             log::info!("Block iob 4 page_hash_indices");
         }
         log::info!("attempting to get key: '{}'::", test_key);
-        match sstable.get(test_key.to_string()) {
+        match sstable.get(test_key.as_bytes()) {
             Ok(kv) => {
                 log::info!("success! key found!");
-                log::info!("retrieved value: '{}'", kv.value);
-                assert_eq!(kv.value, test_value);
+                log::info!("retrieved value: {:?}", kv.value);
+                assert_eq!(kv.value.as_ref(), test_value.as_bytes());
                 Ok(())
             }
             Err(e) => {
@@ -853,6 +1327,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -866,7 +1341,7 @@ mod tests {
 
         // Test getting a nonexistent key
         let key = "nonexistent-key".to_string();
-        let result = sstable.get(key);
+        let result = sstable.get(key.as_bytes());
         assert!(matches!(result, Err(SSTableError::KeyNotfound)));
 
         Ok(())
@@ -880,6 +1355,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -899,9 +1375,9 @@ mod tests {
         ];
 
         for &key in &test_keys {
-            let result = sstable.get(key.to_string())?;
+            let result = sstable.get(key.as_bytes())?;
             log::info!("result: {:?}", result);
-            assert_eq!(result.value, "5");
+            assert_eq!(result.value.as_ref(), b"5");
         }
 
         Ok(())
@@ -916,6 +1392,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -938,8 +1415,8 @@ mod tests {
 
         for i in (0..500).step_by(50) {
             let key = format!("key-{:05}", i);
-            let result = sstable.get(key.to_string()).unwrap();
-            assert!(result.key == key);
+            let result = sstable.get(key.as_bytes()).unwrap();
+            assert!(result.key.as_ref() == key.as_bytes());
         }
 
         Ok(())
@@ -953,6 +1430,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -968,12 +1446,12 @@ mod tests {
 
         for i in (0..150).step_by(50) {
             let key = format!("key-{:05}", i);
-            let result = sstable.get(key);
+            let result = sstable.get(key.as_bytes());
             assert!(result.is_ok());
         }
         for i in [49, 50, 51, 99, 100, 101] {
             let key = format!("key-{:05}", i);
-            let result = sstable.get(key);
+            let result = sstable.get(key.as_bytes());
             assert!(result.is_ok());
         }
 
@@ -988,6 +1466,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -999,8 +1478,7 @@ mod tests {
         let sstable = builder.build()?;
 
         if !sstable.fence_pointers.is_empty() {
-            let block_offset = (0, 1); // First block
-            let block_data = sstable.read_block_from_disk(block_offset)?;
+            let block_data = sstable.read_block_from_disk(0)?; // First block
             assert!(!block_data.is_empty());
         }
 
@@ -1015,6 +1493,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -1036,7 +1515,7 @@ mod tests {
 
                 for i in start..end {
                     let key = format!("key-{:05}", i);
-                    let result = sstable_clone.get(key);
+                    let result = sstable_clone.get(key.as_bytes());
                     assert!(result.is_ok());
                 }
             });
@@ -1059,6 +1538,7 @@ mod tests {
         let features = SSTableFeatures {
             item_count: 1000,
             fpr: 0.01,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -1067,7 +1547,7 @@ mod tests {
         builder.add_from_kv(create_test_kv("large-key", &large_value))?;
 
         let sstable = builder.build()?;
-        let result = sstable.get("large-key".to_string())?;
+        let result = sstable.get(b"large-key")?;
         assert_eq!(result.value.len(), 1_000_000);
 
         Ok(())
@@ -1081,6 +1561,7 @@ mod tests {
         let features = SSTableFeatures {
             fpr: 0.01,
             item_count: 1000,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -1102,8 +1583,8 @@ mod tests {
         // Test a sampling of keys
         for i in [0, 15, 35, 67, 99] {
             let key = format!("prefix-{:03}:suffix-{:03}", i % 10, i);
-            let result = sstable.get(key)?;
-            assert_eq!(result.value, format!("value-{}", i));
+            let result = sstable.get(key.as_bytes())?;
+            assert_eq!(result.value.as_ref(), format!("value-{}", i).as_bytes());
         }
 
         Ok(())
@@ -1117,6 +1598,7 @@ mod tests {
         let features = SSTableFeatures {
             fpr: 0.01,
             item_count: 100,
+            ..Default::default()
         };
 
         let mut builder = SSTableBuilder::new(features, &file_path)?;
@@ -1134,16 +1616,931 @@ mod tests {
             let mut fence_keys = sstable
                 .fence_pointers
                 .iter()
-                .map(|(k, _)| k.to_string())
+                .map(|(k, _)| k.to_vec())
                 .collect::<Vec<_>>();
 
             fence_keys.sort();
 
-            for i in 0..fence_keys.len() {
-                assert_eq!(sstable.fence_pointers[i].0.to_string(), fence_keys[i]);
+            for (i, fence_key) in fence_keys.iter().enumerate() {
+                assert_eq!(&sstable.fence_pointers[i].0.to_vec(), fence_key);
             }
         }
 
         Ok(())
     }
+
+    #[test]
+    fn test_binary_keys_and_values() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 300,
+            ..Default::default()
+        };
+
+        // "è" and "é" share their first byte, so the delta encoding splits a
+        // character; the rest isn't UTF-8 at all
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            ("è".as_bytes().to_vec(), vec![0xff, 0x00, 0xfe]),
+            ("é".as_bytes().to_vec(), vec![]),
+        ];
+        for i in 0..300u32 {
+            let mut key = vec![0xff, 0xfe];
+            key.extend_from_slice(&i.to_be_bytes());
+            entries.push((key, i.to_le_bytes().to_vec()));
+        }
+        entries.sort();
+
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for (key, value) in &entries {
+            builder.add_from_kv(KeyValue::new(key, value))?;
+        }
+        builder.build()?;
+
+        let opened = SSTable::open(&file_path)?;
+        for (key, value) in &entries {
+            assert_eq!(opened.get(key)?.value.as_ref(), value.as_slice());
+        }
+        let scanned = opened.iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(scanned.len(), entries.len());
+        assert!(scanned.iter().zip(&entries).all(|(kv, (key, _))| kv.key.as_ref() == key));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_at_picks_visible_version() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 400,
+            ..Default::default()
+        };
+
+        // forty versions of "key-hot" with 100 byte values can't fit in one
+        // 4KB block, but they must not be split across two
+        let mut builder =
+            streamed_builder::StreamedSSTableBuilder::new(features, true, &file_path)?;
+        for i in 0..100 {
+            let key = format!("key-{:03}", i);
+            let versions = if i == 50 { 40 } else { 1 };
+            for v in (0..versions).rev() {
+                builder.add_from_kv(KeyValue {
+                    seq: 1000 + i * 100 + v,
+                    ..create_test_kv(&key, &format!("{:0>100}", v))
+                })?;
+            }
+        }
+        let built = builder.finalize()?;
+        assert!(built.fence_pointers.len() > 1);
+        assert!(built.fence_pointers.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let opened = SSTable::open(&file_path)?;
+        assert_eq!(opened.max_sequence(), 10_900);
+        let hot = b"key-050";
+        assert_eq!(opened.get(hot)?.seq, 6039);
+        assert_eq!(opened.get_at(hot, 6020)?.seq, 6020);
+        assert_eq!(opened.get_at(hot, 6000)?.seq, 6000);
+        assert!(matches!(
+            opened.get_at(hot, 5999),
+            Err(SSTableError::KeyNotfound)
+        ));
+        assert_eq!(opened.get_at(b"key-051", u64::MAX)?.seq, 6100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cursor_seeks_and_walks_both_ways() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 600,
+            ..Default::default()
+        };
+
+        // even keys only, every tenth one with three versions
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        let mut expected = Vec::new();
+        for i in (0..1000).step_by(2) {
+            let key = format!("key-{:04}", i);
+            let versions = if i % 10 == 0 { 3 } else { 1 };
+            for v in (0..versions).rev() {
+                let kv = KeyValue {
+                    seq: i * 10 + v,
+                    ..create_test_kv(&key, &format!("value-{}-{}", i, v))
+                };
+                expected.push((kv.key.clone(), kv.seq));
+                builder.add_from_kv(kv)?;
+            }
+        }
+        builder.build()?;
+
+        let table = SSTable::open(&file_path)?;
+        assert!(table.fence_pointers.len() > 2);
+        let position = |cursor: &SSTableCursor| cursor.current().map(|kv| (kv.key.clone(), kv.seq));
+
+        let mut cursor = table.cursor();
+        assert!(cursor.current().is_none());
+        cursor.seek(b"key-0101")?;
+        assert_eq!(cursor.current().unwrap().key_str(), Some("key-0102"));
+        cursor.seek(b"key-0100")?;
+        assert_eq!(cursor.current().unwrap().seq, 1002);
+        cursor.seek_for_prev(b"key-0101")?;
+        assert_eq!(cursor.current().unwrap().seq, 1000);
+        cursor.seek_for_prev(b"key-0102")?;
+        assert_eq!(cursor.current().unwrap().key_str(), Some("key-0102"));
+        cursor.seek_for_prev(b"key")?;
+        assert!(cursor.current().is_none());
+        cursor.seek(b"key-9999")?;
+        assert!(cursor.current().is_none());
+        cursor.seek_for_prev(b"key-9999")?;
+        assert_eq!(cursor.current().unwrap().key_str(), Some("key-0998"));
+
+        // stepping back from the first key of a block lands in the one before
+        let fence = table.fence_pointers[2].0.clone();
+        cursor.seek(&fence)?;
+        let at = expected.iter().position(|(key, _)| *key == fence).unwrap();
+        cursor.retreat()?;
+        assert_eq!(position(&cursor), Some(expected[at - 1].clone()));
+        cursor.advance()?;
+        assert_eq!(position(&cursor), Some(expected[at].clone()));
+
+        let mut forward = Vec::new();
+        cursor.seek_to_first()?;
+        while let Some(entry) = position(&cursor) {
+            forward.push(entry);
+            cursor.advance()?;
+        }
+        assert_eq!(forward, expected);
+
+        let mut backward = Vec::new();
+        cursor.seek_to_last()?;
+        while let Some(entry) = position(&cursor) {
+            backward.push(entry);
+            cursor.retreat()?;
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_cache_serves_repeated_reads() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+        let cache = Arc::new(BlockCache::new(1 << 20));
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 300,
+            read_options: TableReadOptions {
+                block_cache: Some(cache.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..300 {
+            builder.add_from_kv(create_test_kv(&format!("key-{:03}", i), &"x".repeat(50)))?;
+        }
+        let built = builder.build()?;
+
+        built.get(b"key-010")?;
+        built.get(b"key-011")?;
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert!(stats.usage > 0 && stats.usage <= 4096);
+
+        // a reopened table gets its own id, so it doesn't see stale blocks
+        let read_options = TableReadOptions {
+            block_cache: Some(cache.clone()),
+            ..Default::default()
+        };
+        let reopened = SSTable::open_with_options(&file_path, default_comparator(), read_options)?;
+        reopened.get(b"key-010")?;
+        assert_eq!(cache.stats().misses, 2);
+        let mut cursor = reopened.cursor();
+        cursor.seek(b"key-010")?;
+        assert_eq!(cursor.current().unwrap().key_str(), Some("key-010"));
+        assert_eq!(cache.stats().hits, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mapped_and_cached_file_reads() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+        let block_cache = Arc::new(BlockCache::new(1 << 20));
+        let table_cache = Arc::new(TableCache::new(4));
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 300,
+            read_options: TableReadOptions {
+                table_cache: Some(table_cache.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..300 {
+            builder.add_from_kv(create_test_kv(&format!("key-{:03}", i), &"x".repeat(50)))?;
+        }
+        let built = builder.build()?;
+        for i in (0..300).step_by(30) {
+            built.get(format!("key-{:03}", i).as_bytes())?;
+        }
+        // one open for the first read, the handle serves the rest
+        let stats = table_cache.stats();
+        assert_eq!((stats.misses, stats.open_files), (1, 1));
+        assert_eq!(stats.hits, 9);
+
+        let mapped = SSTable::open_with_options(
+            &file_path,
+            default_comparator(),
+            TableReadOptions {
+                block_cache: Some(block_cache.clone()),
+                mmap: true,
+                ..Default::default()
+            },
+        )?;
+        let mut cursor = mapped.cursor();
+        cursor.seek_to_first()?;
+        let mut count = 0;
+        while cursor.current().is_some() {
+            count += 1;
+            cursor.advance()?;
+        }
+        assert_eq!(count, 300);
+        assert_eq!(mapped.get(b"key-150")?.value.len(), 50);
+        // blocks come straight from the mapping
+        let stats = block_cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.usage), (0, 0, 0));
+
+        built.delete()?;
+        assert_eq!(table_cache.stats().open_files, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_get_reads_each_block_once() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+        let cache = Arc::new(BlockCache::new(1 << 20));
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 300,
+            read_options: TableReadOptions {
+                block_cache: Some(cache.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..300 {
+            builder.add_from_kv(create_test_kv(&format!("key-{:03}", i), &"x".repeat(50)))?;
+        }
+        let built = builder.build()?;
+        assert!(built.fence_pointers.len() > 1);
+
+        let keys: Vec<String> = (0..300)
+            .step_by(5)
+            .map(|i| format!("key-{:03}", i))
+            .chain(["key-999".to_string()])
+            .collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_bytes()).collect();
+        let results = built.multi_get_at(&keys, u64::MAX);
+
+        assert_eq!(results.len(), keys.len());
+        for (key, result) in keys.iter().zip(&results[..keys.len() - 1]) {
+            assert_eq!(result.as_ref().unwrap().key.as_ref(), *key);
+        }
+        assert!(matches!(results.last(), Some(Err(SSTableError::KeyNotfound))));
+
+        // every block holds some of the keys, and none is read twice
+        let stats = cache.stats();
+        assert_eq!(stats.misses as usize, built.fence_pointers.len());
+        assert_eq!(stats.hits, 0);
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn compare(&self, a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+            b.cmp(a)
+        }
+
+        fn name(&self) -> &str {
+            "test.ReverseComparator"
+        }
+    }
+
+    #[test]
+    fn test_custom_comparator_orders_lookups() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 500,
+            ..Default::default()
+        };
+
+        let mut builder = SSTableBuilder::new(features, &file_path)?
+            .with_comparator(Arc::new(ReverseComparator));
+        for i in (0..500).rev() {
+            let key = format!("key-{:05}", i);
+            builder.add_from_kv(create_test_kv(&key, &format!("value-{}", i)))?;
+        }
+        builder.build()?;
+
+        let opened = SSTable::open_with_comparator(&file_path, Arc::new(ReverseComparator))?;
+        assert!(opened.fence_pointers.len() > 1);
+        for i in (0..500).step_by(13) {
+            let key = format!("key-{:05}", i);
+            assert_eq!(
+                opened.get(key.as_bytes())?.value.as_ref(),
+                format!("value-{}", i).as_bytes()
+            );
+        }
+
+        assert!(matches!(
+            SSTable::open(&file_path),
+            Err(SSTableError::ComparatorMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_rebuilds_table() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 500,
+            ..Default::default()
+        };
+
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..500 {
+            let key = format!("key-{:05}", i);
+            builder.add_from_kv(create_test_kv(&key, &format!("value-{}", i)))?;
+        }
+        let built = builder.build()?;
+        assert!(built.fence_pointers.len() > 1);
+
+        let opened = SSTable::open(&file_path)?;
+        assert_eq!(opened.fence_pointers, built.fence_pointers);
+        assert_eq!(opened.restart_indices, built.restart_indices);
+        assert_eq!(opened.page_hash_indices, built.page_hash_indices);
+        assert_eq!(opened.actual_item_count, 500);
+        assert_eq!(opened.data_end, built.data_end);
+        assert_eq!(opened.max_sequence, built.max_sequence);
+        assert!(opened.filter.is_some());
+
+        for i in (0..500).step_by(7) {
+            let key = format!("key-{:05}", i);
+            assert_eq!(
+                opened.get(key.as_bytes())?.value.as_ref(),
+                format!("value-{}", i).as_bytes()
+            );
+        }
+        assert!(matches!(
+            opened.get(b"missing"),
+            Err(SSTableError::KeyNotfound)
+        ));
+
+        let scanned = opened.iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(scanned.len(), 500);
+        assert_eq!(scanned.last().unwrap().key.as_ref(), b"key-00499");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_policy_survives_reopen() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let policies: [Arc<dyn FilterPolicy>; 3] = [
+            Arc::new(BlockedBloomPolicy),
+            Arc::new(RibbonPolicy),
+            Arc::new(XorPolicy),
+        ];
+
+        for policy in policies {
+            let file_path = temp_dir.path().join(format!("test-{}.sst", policy.id()));
+            let features = SSTableFeatures {
+                fpr: 0.01,
+                item_count: 500,
+                filter_policy: Some(policy.clone()),
+                ..Default::default()
+            };
+            let mut builder = SSTableBuilder::new(features, &file_path)?;
+            for i in 0..500 {
+                builder.add_from_kv(create_test_kv(&format!("key-{:05}", i), "value"))?;
+            }
+            let built = builder.build()?;
+
+            let opened = SSTable::open(&file_path)?;
+            let (built_filter, opened_filter) = (built.filter().unwrap(), opened.filter().unwrap());
+            assert_eq!(opened_filter.policy_id(), policy.id());
+            assert_eq!(opened_filter.size(), built_filter.size());
+            for i in 0..500 {
+                let key = format!("key-{:05}", i);
+                assert!(opened_filter.may_contain(key.as_bytes()));
+                assert_eq!(opened.get(key.as_bytes())?.value.as_ref(), b"value");
+            }
+            let missed = (500..5500)
+                .filter(|i| opened_filter.may_contain(format!("key-{:05}", i).as_bytes()))
+                .count();
+            assert!(missed < 100, "{:?} let through {} of 5000", policy, missed);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_unfiltered_streamed_table() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 100,
+            ..Default::default()
+        };
+
+        let mut builder =
+            streamed_builder::StreamedSSTableBuilder::new(features, false, &file_path)?;
+        for i in 0..100 {
+            let key = format!("key-{:05}", i);
+            builder.add_from_kv(create_test_kv(&key, &"x".repeat(100)))?;
+        }
+        builder.finalize()?;
+
+        let opened = SSTable::open(&file_path)?;
+        assert!(opened.filter.is_none());
+        assert_eq!(opened.get(b"key-00099")?.value.len(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_filter_survives_reopen() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 1000,
+            range_filter: true,
+            ..Default::default()
+        };
+        let mut builder =
+            streamed_builder::StreamedSSTableBuilder::new(features, true, &file_path)?;
+        for i in 1..=1000u64 {
+            builder.add_from_kv(KeyValue::new((i * 1000).to_be_bytes(), "value"))?;
+        }
+        builder.finalize()?;
+
+        let opened = SSTable::open(&file_path)?;
+        let key = |i: u64| i.to_be_bytes();
+        assert_eq!(opened.key_range(), Some((&key(1000)[..], &key(1_000_000)[..])));
+        assert!(opened.range_filter.is_some());
+
+        assert!(!opened.may_overlap(&key(0), &key(999)));
+        assert!(!opened.may_overlap(&key(1_000_001), &key(u64::MAX)));
+        assert!(opened.may_overlap(&key(0), &key(u64::MAX)));
+        for i in (1..=1000).step_by(7) {
+            assert!(opened.may_overlap(&key(i * 1000 - 10), &key(i * 1000)));
+        }
+        let matched = (1..1000)
+            .filter(|i| opened.may_overlap(&key(i * 1000 + 100), &key(i * 1000 + 900)))
+            .count();
+        assert!(matched < 30, "{} of 999 empty ranges matched", matched);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_table_from_older_version() -> Result<(), SSTableError> {
+        use table_format::{BlockHandle, Footer};
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 500,
+            ..Default::default()
+        };
+        let mut builder =
+            streamed_builder::StreamedSSTableBuilder::new(features, true, &file_path)?;
+        for i in 0..500 {
+            builder.add_from_kv(create_test_kv(&format!("key-{:05}", i), "value"))?;
+        }
+        builder.finalize()?;
+
+        // rewrite the tail the way version 7 laid it out: an untagged index
+        // and a meta block that ends after the comparator
+        let data = std::fs::read(&file_path)?;
+        let footer = Footer::decode(&data[data.len() - FOOTER_SIZE..]).unwrap();
+        let block = |handle: BlockHandle| {
+            &data[handle.offset as usize..(handle.offset + handle.size) as usize]
+        };
+        let meta = TableMeta::decode(block(footer.meta))?;
+        let BlockIndex::Full(index) = BlockIndex::decode(block(footer.index), meta.version)?
+        else {
+            panic!("expected a full index");
+        };
+        let old_meta = TableMeta {
+            version: 7,
+            item_count: meta.item_count,
+            data_end: meta.data_end,
+            max_sequence: meta.max_sequence,
+            comparator: meta.comparator,
+            ..TableMeta::default()
+        }
+        .encode();
+        // each empty trailing field is a single zero length byte
+        let old_meta = &old_meta[..old_meta.len() - 5];
+        let old_index = TableIndex::encode(
+            &index.fence_pointers,
+            &index.restart_indices,
+            &index.page_hash_indices,
+        );
+
+        let mut rewritten = data[..meta.data_end].to_vec();
+        let mut old_footer = Footer {
+            data_blocks: footer.data_blocks,
+            ..Footer::default()
+        };
+        for (handle, block) in [
+            (&mut old_footer.index, old_index.as_slice()),
+            (&mut old_footer.filter, block(footer.filter)),
+            (&mut old_footer.meta, old_meta),
+        ] {
+            *handle = BlockHandle {
+                offset: rewritten.len() as u64,
+                size: block.len() as u64,
+                checksum: crc32c::crc32c(block),
+            };
+            rewritten.extend_from_slice(block);
+        }
+        rewritten.extend_from_slice(&old_footer.encode());
+        std::fs::write(&file_path, rewritten)?;
+
+        let opened = SSTable::open(&file_path)?;
+        assert_eq!(opened.get(b"key-00250")?.value.as_ref(), b"value");
+        assert_eq!(
+            opened.key_range(),
+            Some((&b"key-00000"[..], &b"key-00499"[..]))
+        );
+        assert!(opened.may_overlap(b"key-00100", b"key-00200"));
+        assert!(!opened.may_overlap(b"key-00500", b"key-00600"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_learned_index_narrows_lookups() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 5000,
+            learned_index: Some(2),
+            ..Default::default()
+        };
+        // unevenly spaced integer keys, some with a few versions
+        let key = |i: u64| (i * i * 37 + i % 5).to_be_bytes();
+        let mut builder =
+            streamed_builder::StreamedSSTableBuilder::new(features, true, &file_path)?;
+        for i in 0..5000u64 {
+            let versions = if i % 100 == 0 { 3 } else { 1 };
+            for v in (0..versions).rev() {
+                builder.add_from_kv(KeyValue {
+                    seq: i * 10 + v,
+                    ..KeyValue::new(key(i), format!("{}-{}", i, v))
+                })?;
+            }
+        }
+        builder.finalize()?;
+
+        let opened = SSTable::open(&file_path)?;
+        assert!(opened.learned_index.is_some());
+        let mut predicted = 0;
+        for i in 0..5000u64 {
+            let expected = opened.find_block_with_fence_pointers(&key(i)).map(|(b, _)| b);
+            if let Some(block) = opened.find_predicted_block(&key(i)) {
+                assert_eq!(Some(block), expected);
+                predicted += 1;
+            }
+            let newest = if i % 100 == 0 { 2 } else { 0 };
+            let found = opened.get(&key(i))?;
+            assert_eq!(found.value_str(), Some(format!("{}-{}", i, newest).as_str()));
+            // keys between the table's keys are still missing
+            let missing = (i * i * 37 + 6).to_be_bytes();
+            assert!(matches!(opened.get(&missing), Err(SSTableError::KeyNotfound)));
+        }
+        assert!(predicted > 4900, "only {} of 5000 blocks predicted", predicted);
+        assert_eq!(opened.get_at(&key(300), 3001)?.value_str(), Some("300-1"));
+
+        let mut cursor = opened.cursor();
+        cursor.seek(&(1000 * 1000 * 37 + 5u64).to_be_bytes())?;
+        assert_eq!(cursor.current().unwrap().key.as_ref(), &key(1001)[..]);
+
+        // keys sharing their first eight bytes defeat the model, and lookups
+        // fall back to the plain search
+        let file_path = temp_dir.path().join("strings.sst");
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 2000,
+            learned_index: Some(2),
+            ..Default::default()
+        };
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..2000 {
+            builder.add_from_kv(create_test_kv(&format!("common-prefix-{:05}", i), "v"))?;
+        }
+        let built = builder.build()?;
+        for i in (0..2000).step_by(3) {
+            let key = format!("common-prefix-{:05}", i);
+            assert_eq!(built.get(key.as_bytes())?.key_str(), Some(key.as_str()));
+        }
+        assert!(matches!(built.get(b"common-prefix-0100"), Err(SSTableError::KeyNotfound)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_scan_and_prefix_filter() -> Result<(), SSTableError> {
+        use prefix::{DelimitedPrefix, FixedPrefix};
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+        let extractor = DelimitedPrefix::new(b':', 2);
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 3000,
+            prefix_extractor: Some(Arc::new(extractor.clone())),
+            ..Default::default()
+        };
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        let mut keys = Vec::new();
+        for tenant in 0..10 {
+            // only even objects exist
+            for object in (0..100).step_by(2) {
+                for field in 0..3 {
+                    keys.push(format!("t{:02}:o{:03}:f{}", tenant, object, field));
+                }
+            }
+        }
+        for key in &keys {
+            builder.add_from_kv(KeyValue {
+                seq: 2,
+                ..create_test_kv(key, "new")
+            })?;
+            builder.add_from_kv(KeyValue {
+                seq: 1,
+                ..create_test_kv(key, "old")
+            })?;
+        }
+        builder.build()?;
+        let sstable = SSTable::open(&file_path)?;
+
+        for prefix in ["t03:o042:", "t03:o04", "t03:", "t09:o098:f2", "", "t10", "a"] {
+            let found = sstable.prefix_scan(prefix.as_bytes())?;
+            let expected: Vec<_> = keys
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .flat_map(|key| [(key.clone(), 2), (key.clone(), 1)])
+                .collect();
+            let found: Vec<_> = found
+                .iter()
+                .map(|kv| (String::from_utf8(kv.key.to_vec()).unwrap(), kv.seq))
+                .collect();
+            assert_eq!(found, expected, "prefix {:?}", prefix);
+        }
+
+        let extractor: &dyn PrefixExtractor = &extractor;
+        assert!(sstable.may_contain_prefix(Some(extractor), b"t03:o042:"));
+        assert!(!sstable.may_contain_prefix(Some(extractor), b"t10:o000:"));
+        let missed = (0..10)
+            .flat_map(|tenant| (1..100).step_by(2).map(move |object| (tenant, object)))
+            .filter(|(tenant, object)| {
+                let prefix = format!("t{:02}:o{:03}:", tenant, object);
+                sstable.may_contain_prefix(Some(extractor), prefix.as_bytes())
+            })
+            .count();
+        assert!(missed < 25, "{} of 500 absent prefixes let through", missed);
+
+        // prefixes the extractor can't produce, or another extractor, fall
+        // back to the key range
+        assert!(sstable.may_contain_prefix(Some(extractor), b"t03:o041"));
+        assert!(sstable.may_contain_prefix(Some(&FixedPrefix::new(9)), b"t03:o041:"));
+        assert!(sstable.may_contain_prefix(None, b"t03:o041:"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_partitioned_index_reads_through_cache() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let read_options = TableReadOptions {
+            block_cache: Some(cache.clone()),
+            ..Default::default()
+        };
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 2000,
+            read_options: read_options.clone(),
+            index_partition_blocks: Some(4),
+            ..Default::default()
+        };
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..2000 {
+            builder.add_from_kv(create_test_kv(&format!("key-{:05}", i), &"x".repeat(50)))?;
+        }
+        let built = builder.build()?;
+        assert!(built.index_partitions() > 2);
+        assert!(built.fence_pointers.is_empty());
+
+        let reopened = SSTable::open_with_options(&file_path, default_comparator(), read_options)?;
+        assert_eq!(reopened.index_partitions(), built.index_partitions());
+        assert_eq!(reopened.block_count(), built.block_count());
+        for table in [&built, &reopened] {
+            for i in (0..2000).step_by(7) {
+                let key = format!("key-{:05}", i);
+                assert_eq!(table.get(key.as_bytes())?.key_str(), Some(key.as_str()));
+            }
+            assert!(matches!(table.get(b"key-00010x"), Err(SSTableError::KeyNotfound)));
+        }
+
+        // once every partition is cached, lookups stop missing
+        let misses = cache.stats().misses;
+        for i in (0..2000).step_by(7) {
+            reopened.get(format!("key-{:05}", i).as_bytes())?;
+        }
+        assert_eq!(cache.stats().misses, misses);
+
+        let keys: Vec<&[u8]> = vec![b"key-00003", b"key-01500", b"key-01500x", b"key-01999"];
+        let found: Vec<_> = reopened
+            .multi_get_at(&keys, u64::MAX)
+            .into_iter()
+            .map(|result| result.is_ok())
+            .collect();
+        assert_eq!(found, vec![true, true, false, true]);
+
+        let mut cursor = reopened.cursor();
+        cursor.seek_to_first()?;
+        let mut count = 0;
+        while cursor.current().is_some() {
+            count += 1;
+            cursor.advance()?;
+        }
+        assert_eq!(count, 2000);
+        cursor.seek_for_prev(b"key-01000x")?;
+        assert_eq!(cursor.current().unwrap().key_str(), Some("key-01000"));
+        cursor.seek_to_last()?;
+        cursor.retreat()?;
+        assert_eq!(cursor.current().unwrap().key_str(), Some("key-01998"));
+        assert_eq!(reopened.iter().count(), 2000);
+        assert_eq!(reopened.prefix_scan(b"key-010")?.len(), 100);
+
+        // a damaged partition is reported as the block after the meta block
+        let partition = &reopened.partitions[1];
+        let first_key = partition.first_key.clone();
+        let offset = partition.handle.offset as usize;
+        let mut contents = std::fs::read(&file_path)?;
+        contents[offset + 2] ^= 0x01;
+        std::fs::write(&file_path, &contents)?;
+        let damaged = SSTable::open(&file_path)?;
+        match damaged.get(&first_key) {
+            Err(SSTableError::Corruption { block, offset: at, .. }) => {
+                assert_eq!(block, damaged.block_count() + 4);
+                assert_eq!(at, offset);
+            }
+            other => panic!("expected corruption, got {:?}", other),
+        }
+        assert!(damaged.get(b"key-00000").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_rejects_truncated_file() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 10,
+            ..Default::default()
+        };
+
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        builder.add_from_kv(create_test_kv("key", "value"))?;
+        builder.build()?;
+
+        let contents = std::fs::read(&file_path)?;
+        std::fs::write(&file_path, &contents[..contents.len() - 10])?;
+        assert!(SSTable::open(&file_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_block_is_reported() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 1000,
+            ..Default::default()
+        };
+
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        for i in 0..1000 {
+            let key = format!("key-{:05}", i);
+            builder.add_from_kv(create_test_kv(&key, &format!("value-{}", i)))?;
+        }
+        let sstable = builder.build()?;
+        assert!(sstable.fence_pointers.len() > 2);
+
+        // flip a bit in the middle of the second data block
+        let second_block = sstable.fence_pointers[1].1;
+        let mut contents = std::fs::read(&file_path)?;
+        contents[second_block + 100] ^= 0x01;
+        std::fs::write(&file_path, &contents)?;
+
+        let first_key_of_block = sstable.fence_pointers[1].0.clone();
+        match sstable.get(&first_key_of_block) {
+            Err(SSTableError::Corruption { file, block, offset }) => {
+                assert_eq!(file, file_path);
+                assert_eq!(block, 1);
+                assert_eq!(offset, second_block);
+            }
+            other => panic!("expected corruption, got {:?}", other),
+        }
+
+        // other blocks are still readable
+        assert_eq!(sstable.get(b"key-00000")?.value.as_ref(), b"value-0");
+
+        let scanned: Vec<_> = sstable.iter().collect();
+        assert!(matches!(
+            scanned.last(),
+            Some(Err(SSTableError::Corruption { block: 1, .. }))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_rejects_corrupted_footer() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.sst");
+
+        let features = SSTableFeatures {
+            fpr: 0.01,
+            item_count: 10,
+            ..Default::default()
+        };
+
+        let mut builder = SSTableBuilder::new(features, &file_path)?;
+        builder.add_from_kv(create_test_kv("key", "value"))?;
+        builder.build()?;
+
+        let mut contents = std::fs::read(&file_path)?;
+        let footer_start = contents.len() - FOOTER_SIZE;
+        contents[footer_start + 3] ^= 0x01;
+        std::fs::write(&file_path, &contents)?;
+
+        assert!(matches!(
+            SSTable::open(&file_path),
+            Err(SSTableError::Corruption { offset, .. }) if offset == footer_start
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_dir() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        sync_dir(temp_dir.path())?;
+        // a bare file name's parent is the current directory
+        sync_dir(Path::new(""))?;
+        assert!(sync_dir(&temp_dir.path().join("missing")).is_err());
+        Ok(())
+    }
 }
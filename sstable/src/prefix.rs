@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// Picks the part of a key that goes into a table's key filter next to the
+/// whole key, so that a prefix scan can skip tables that hold no key with
+/// that prefix.
+///
+/// `transform` returns a prefix of the key. For a filter check to be safe,
+/// every key that starts with an in-domain `p` with `transform(p) == p` must
+/// itself be in the domain and map to `p`; prefixes that are not of that
+/// shape are never checked against the filter.
+pub trait PrefixExtractor: Send + Sync + fmt::Debug {
+    /// Identifies the extractor. It is stored in every table, and a table
+    /// only answers prefix checks for the extractor it was built with.
+    fn name(&self) -> &str;
+
+    /// Whether `key` has a prefix at all. Keys outside the domain only go
+    /// into the filter whole.
+    fn in_domain(&self, key: &[u8]) -> bool;
+
+    /// The prefix of an in-domain `key`.
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+}
+
+/// The first `len` bytes of the key.
+#[derive(Debug, Clone)]
+pub struct FixedPrefix {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefix {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("byron.FixedPrefix.{}", len),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+}
+
+/// Everything up to and including the `count`th `delimiter`, so with `:`
+/// and 2, `tenant:object:field` maps to `tenant:object:`.
+#[derive(Debug, Clone)]
+pub struct DelimitedPrefix {
+    delimiter: u8,
+    count: usize,
+    name: String,
+}
+
+impl DelimitedPrefix {
+    pub fn new(delimiter: u8, count: usize) -> Self {
+        Self {
+            delimiter,
+            count,
+            name: format!("byron.DelimitedPrefix.{:#04x}.{}", delimiter, count),
+        }
+    }
+
+    // length of the prefix, if the key has enough delimiters
+    fn prefix_len(&self, key: &[u8]) -> Option<usize> {
+        if self.count == 0 {
+            return Some(0);
+        }
+        key.iter()
+            .enumerate()
+            .filter(|&(_, &byte)| byte == self.delimiter)
+            .nth(self.count - 1)
+            .map(|(index, _)| index + 1)
+    }
+}
+
+impl PrefixExtractor for DelimitedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        self.prefix_len(key).is_some()
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.prefix_len(key).unwrap_or(key.len())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extractors_map_keys_to_prefixes() {
+        let fixed = FixedPrefix::new(3);
+        assert!(!fixed.in_domain(b"ab"));
+        assert_eq!(fixed.transform(b"abcdef"), b"abc");
+
+        let delimited = DelimitedPrefix::new(b':', 2);
+        assert!(!delimited.in_domain(b"tenant:object"));
+        assert_eq!(
+            delimited.transform(b"tenant:object:field"),
+            b"tenant:object:"
+        );
+        assert_eq!(delimited.transform(b"tenant:object:"), b"tenant:object:");
+        assert_eq!(delimited.transform(b"a::b:c"), b"a::");
+        assert_ne!(delimited.name(), DelimitedPrefix::new(b'/', 2).name());
+    }
+}
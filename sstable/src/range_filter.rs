@@ -0,0 +1,158 @@
+use crate::{
+    error::SSTableError,
+    filter::{BlockedBloomPolicy, Filter, FilterPolicy, key_hash},
+};
+
+// bits of key prefix added per level of the filter
+const LEVEL_BITS: u32 = 4;
+const FPR: f64 = 0.01;
+// filter probes one range query may spend before giving up with a "maybe"
+const PROBE_BUDGET: usize = 512;
+
+/// A Rosetta-style range filter over the first eight bytes of each key.
+///
+/// Every key prefix of 4, 8, ... 64 bits goes into one bloom filter,
+/// tagged with its length. A query walks down from the coarse prefixes
+/// that overlap the range to the finer ones, and only answers "maybe" once
+/// it finds a full 64-bit prefix inside the range. A coarse prefix that
+/// misses rules out every key under it, so a short range over a gap
+/// between keys is usually ruled out after a few probes. A range that
+/// would take too many probes gets a "maybe".
+///
+/// Only valid for bytewise key order, where the 8-byte prefix of a key
+/// never decreases as the key grows.
+#[derive(Debug)]
+pub(crate) struct RangeFilter {
+    bloom: Box<dyn Filter>,
+}
+
+impl RangeFilter {
+    pub fn decode(encoded: &[u8]) -> Result<Self, SSTableError> {
+        Ok(Self {
+            bloom: BlockedBloomPolicy.decode(encoded)?,
+        })
+    }
+
+    /// `false` only if no key of the table lies in `[from, to]`.
+    pub fn may_overlap(&self, from: &[u8], to: &[u8]) -> bool {
+        let (from, to) = (prefix(from), prefix(to));
+        let mut budget = PROBE_BUDGET;
+        from <= to && self.search(0, 0, from, to, &mut budget)
+    }
+
+    // whether some 64-bit prefix under the `bits`-bit `node` may lie in
+    // `[from, to]`, which overlaps the node
+    fn search(&self, bits: u32, node: u64, from: u64, to: u64, budget: &mut usize) -> bool {
+        if bits > 0 {
+            if *budget == 0 {
+                return true;
+            }
+            *budget -= 1;
+            if !self.bloom.may_contain(prefix_hash(bits, node)) {
+                return false;
+            }
+        }
+        if bits == 64 {
+            return true;
+        }
+
+        let low = node.checked_shl(64 - bits).unwrap_or(0);
+        let high = low | u64::MAX.checked_shr(bits).unwrap_or(0);
+        let shift = 64 - bits - LEVEL_BITS;
+        let mask = (1 << LEVEL_BITS) - 1;
+        let first = (from.max(low) >> shift) & mask;
+        let last = (to.min(high) >> shift) & mask;
+        (first..=last).any(|child| {
+            self.search(
+                bits + LEVEL_BITS,
+                (node << LEVEL_BITS) | child,
+                from,
+                to,
+                budget,
+            )
+        })
+    }
+}
+
+/// Collects the prefixes of a table's keys as they are added in order.
+#[derive(Debug, Default)]
+pub(crate) struct RangeFilterBuilder {
+    hashes: Vec<u64>,
+    last: Option<u64>,
+}
+
+impl RangeFilterBuilder {
+    pub fn add(&mut self, key: &[u8]) {
+        let key = prefix(key);
+        for bits in (LEVEL_BITS..=64).step_by(LEVEL_BITS as usize) {
+            let node = key >> (64 - bits);
+            // keys come sorted, so a prefix repeats only right after itself
+            if self.last.is_some_and(|last| last >> (64 - bits) == node) {
+                continue;
+            }
+            self.hashes.push(prefix_hash(bits, node));
+        }
+        self.last = Some(key);
+    }
+
+    pub fn finish(&self) -> Vec<u8> {
+        BlockedBloomPolicy.build(&self.hashes, FPR)
+    }
+}
+
+// the first eight bytes of a key, zero padded, as a big-endian number
+pub(crate) fn prefix(key: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    let len = key.len().min(8);
+    bytes[..len].copy_from_slice(&key[..len]);
+    u64::from_be_bytes(bytes)
+}
+
+fn prefix_hash(bits: u32, node: u64) -> u64 {
+    let mut bytes = [0u8; 9];
+    bytes[0] = bits as u8;
+    bytes[1..].copy_from_slice(&node.to_be_bytes());
+    key_hash(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(keys: impl Iterator<Item = u64>) -> Result<RangeFilter, SSTableError> {
+        let mut builder = RangeFilterBuilder::default();
+        for key in keys {
+            builder.add(&key.to_be_bytes());
+        }
+        RangeFilter::decode(&builder.finish())
+    }
+
+    #[test]
+    fn test_ranges_holding_keys_always_match() -> Result<(), SSTableError> {
+        let filter = build((0..2000).map(|i| i * 1000 + 7))?;
+        for i in (0..2000u64).step_by(13) {
+            let key = i * 1000 + 7;
+            assert!(filter.may_overlap(&key.to_be_bytes(), &key.to_be_bytes()));
+            assert!(filter.may_overlap(&(key - 5).to_be_bytes(), &(key + 500).to_be_bytes()));
+        }
+        assert!(filter.may_overlap(&[0], &[0xff; 9]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges_between_keys_are_ruled_out() -> Result<(), SSTableError> {
+        let filter = build((0..2000).map(|i| i * 1000 + 7))?;
+        let matched = (0..1999u64)
+            .filter(|i| {
+                let (from, to) = (i * 1000 + 100, i * 1000 + 900);
+                filter.may_overlap(&from.to_be_bytes(), &to.to_be_bytes())
+            })
+            .count();
+        assert!(matched < 40, "{} of 1999 empty ranges matched", matched);
+
+        // keys longer than the prefix only compare by their first 8 bytes
+        assert!(filter.may_overlap(&7u64.to_be_bytes(), b"\0\0\0\0\0\0\0\x07zzz"));
+        assert!(!filter.may_overlap(&[1], &[0]));
+        Ok(())
+    }
+}
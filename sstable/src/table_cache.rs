@@ -0,0 +1,108 @@
+use std::{
+    fs::File,
+    num::NonZeroUsize,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use lru::LruCache;
+
+use crate::error::SSTableError;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TableCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub open_files: usize,
+}
+
+/// Open file handles of tables, keyed by table id. At most `capacity` files
+/// stay open; the least recently used one is closed to make room. Reads go
+/// through positioned reads, so one handle serves any number of readers.
+#[derive(Debug)]
+pub struct TableCache {
+    files: Mutex<LruCache<u64, Arc<File>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl TableCache {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            files: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The open handle of table `table`, opening `path` if it isn't cached.
+    pub(crate) fn file(&self, table: u64, path: &Path) -> Result<Arc<File>, SSTableError> {
+        if let Some(file) = self.files.lock().unwrap().get(&table) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(file.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // opened without the lock held; if two readers race, one handle wins
+        let file = Arc::new(File::open(path)?);
+        self.files.lock().unwrap().put(table, file.clone());
+        Ok(file)
+    }
+
+    /// Closes the handle of a table that is going away.
+    pub(crate) fn evict(&self, table: u64) {
+        self.files.lock().unwrap().pop(&table);
+    }
+
+    pub fn stats(&self) -> TableCacheStats {
+        TableCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            open_files: self.files.lock().unwrap().len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_handles_are_reused_and_evicted() -> Result<(), SSTableError> {
+        let temp_dir = tempdir().unwrap();
+        let paths: Vec<_> = (0..3)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("table-{}", i));
+                std::fs::write(&path, b"SSTB").unwrap();
+                path
+            })
+            .collect();
+
+        let cache = TableCache::new(2);
+        cache.file(0, &paths[0])?;
+        cache.file(0, &paths[0])?;
+        cache.file(1, &paths[1])?;
+        cache.file(2, &paths[2])?;
+        assert_eq!(
+            cache.stats(),
+            TableCacheStats {
+                hits: 1,
+                misses: 3,
+                open_files: 2
+            }
+        );
+
+        // table 0 was the least recently used
+        cache.file(0, &paths[0])?;
+        assert_eq!(cache.stats().misses, 4);
+
+        cache.evict(0);
+        assert_eq!(cache.stats().open_files, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,569 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Write},
+    sync::Arc,
+};
+
+use integer_encoding::{VarInt, VarIntReader};
+
+use crate::error::SSTableError;
+
+// On-disk layout of a table:
+//
+//   "SSTB" | data blocks | [index partitions] | index block | filter block |
+//   meta block | footer
+//
+// Every data block is followed by a crc32c of its contents. The footer has a
+// fixed size so it can be found from the end of the file. It holds a handle
+// (offset, size, crc32c) for each of the three trailing blocks, the number of
+// data blocks, a crc32c of everything before it, and the closing magic.
+// The filter block opens with the id of the policy that built it, and is
+// empty for a table without a filter.
+// Since version 8 the index block opens with a tag: either the whole index
+// follows, or only a handle to each index partition, which covers a run of
+// data blocks.
+// Fields added to the meta block are appended at its end and read as empty
+// from a table written before them, so they don't change the version.
+pub(crate) const MAGIC: &[u8; 4] = b"SSTB";
+pub(crate) const FORMAT_VERSION: u64 = 8;
+/// Oldest version whose data and filter blocks are still laid out as today.
+const OLDEST_READABLE_VERSION: u64 = 7;
+const TAGGED_INDEX_VERSION: u64 = 8;
+pub(crate) const BLOCK_TRAILER_SIZE: usize = 4;
+const HANDLE_SIZE: usize = 20;
+const FULL_INDEX: u8 = 0;
+const PARTITIONED_INDEX: u8 = 1;
+const FOOTER_BODY_SIZE: usize = 3 * HANDLE_SIZE + 8;
+pub(crate) const FOOTER_SIZE: usize = FOOTER_BODY_SIZE + 4 + MAGIC.len();
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockHandle {
+    pub offset: u64,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Footer {
+    pub index: BlockHandle,
+    pub filter: BlockHandle,
+    pub meta: BlockHandle,
+    pub data_blocks: u64,
+}
+
+impl Footer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(FOOTER_SIZE);
+        for handle in [self.index, self.filter, self.meta] {
+            buffer.extend_from_slice(&handle.offset.to_le_bytes());
+            buffer.extend_from_slice(&handle.size.to_le_bytes());
+            buffer.extend_from_slice(&handle.checksum.to_le_bytes());
+        }
+        buffer.extend_from_slice(&self.data_blocks.to_le_bytes());
+        let checksum = crc32c::crc32c(&buffer);
+        buffer.extend_from_slice(&checksum.to_le_bytes());
+        buffer.extend_from_slice(MAGIC);
+        buffer
+    }
+
+    /// `None` if the footer is damaged: wrong size, missing magic or a
+    /// checksum mismatch.
+    pub fn decode(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() != FOOTER_SIZE || &buffer[FOOTER_SIZE - MAGIC.len()..] != MAGIC {
+            return None;
+        }
+        let body = &buffer[..FOOTER_BODY_SIZE];
+        let checksum = u32::from_le_bytes(buffer[FOOTER_BODY_SIZE..][..4].try_into().unwrap());
+        if crc32c::crc32c(body) != checksum {
+            return None;
+        }
+
+        let read_u64 = |at: usize| u64::from_le_bytes(body[at..at + 8].try_into().unwrap());
+        let handle = |at: usize| BlockHandle {
+            offset: read_u64(at),
+            size: read_u64(at + 8),
+            checksum: u32::from_le_bytes(body[at + 16..at + 20].try_into().unwrap()),
+        };
+        Some(Self {
+            index: handle(0),
+            filter: handle(HANDLE_SIZE),
+            meta: handle(2 * HANDLE_SIZE),
+            data_blocks: read_u64(3 * HANDLE_SIZE),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct TableMeta {
+    pub version: u64,
+    pub item_count: usize,
+    pub data_end: usize,
+    pub max_sequence: u64,
+    pub comparator: String,
+    /// Both empty for a table without entries.
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    /// Empty unless the table has a range filter.
+    pub range_filter: Vec<u8>,
+    /// Name of the prefix extractor whose prefixes are in the key filter,
+    /// empty for none.
+    pub prefix_extractor: String,
+    /// Empty unless the table has a learned index.
+    pub learned_index: Vec<u8>,
+}
+
+impl TableMeta {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&self.version.encode_var_vec());
+        buffer.extend_from_slice(&self.item_count.encode_var_vec());
+        buffer.extend_from_slice(&self.data_end.encode_var_vec());
+        buffer.extend_from_slice(&self.max_sequence.encode_var_vec());
+        write_bytes(&mut buffer, self.comparator.as_bytes());
+        write_bytes(&mut buffer, &self.smallest_key);
+        write_bytes(&mut buffer, &self.largest_key);
+        write_bytes(&mut buffer, &self.range_filter);
+        write_bytes(&mut buffer, self.prefix_extractor.as_bytes());
+        write_bytes(&mut buffer, &self.learned_index);
+        buffer
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, SSTableError> {
+        let mut cursor = Cursor::new(buffer);
+        let version = read_varint(&mut cursor)?;
+        if !(OLDEST_READABLE_VERSION..=FORMAT_VERSION).contains(&version) {
+            return Err(SSTableError::DecodeError(format!(
+                "unsupported table format version {}",
+                version
+            )));
+        }
+        Ok(Self {
+            version,
+            item_count: read_varint(&mut cursor)?,
+            data_end: read_varint(&mut cursor)?,
+            max_sequence: read_varint(&mut cursor)?,
+            comparator: String::from_utf8(read_bytes(&mut cursor)?)?,
+            smallest_key: read_optional_bytes(&mut cursor)?,
+            largest_key: read_optional_bytes(&mut cursor)?,
+            range_filter: read_optional_bytes(&mut cursor)?,
+            prefix_extractor: String::from_utf8(read_optional_bytes(&mut cursor)?)?,
+            learned_index: read_optional_bytes(&mut cursor)?,
+        })
+    }
+}
+
+/// Everything needed to locate a key inside the data blocks.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct TableIndex {
+    pub fence_pointers: Vec<(Arc<[u8]>, usize)>,
+    pub restart_indices: Vec<Vec<usize>>,
+    pub page_hash_indices: Vec<HashMap<Arc<[u8]>, usize>>,
+}
+
+impl TableIndex {
+    // per block: fence key | block offset | restarts | page hash entries
+    pub fn encode(
+        fence_pointers: &[(Arc<[u8]>, usize)],
+        restart_indices: &[Vec<usize>],
+        page_hash_indices: &[HashMap<Arc<[u8]>, usize>],
+    ) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&fence_pointers.len().encode_var_vec());
+        for (block, (fence_key, offset)) in fence_pointers.iter().enumerate() {
+            write_bytes(&mut buffer, fence_key);
+            buffer.extend_from_slice(&offset.encode_var_vec());
+
+            let restarts = restart_indices.get(block).map(Vec::as_slice).unwrap_or(&[]);
+            buffer.extend_from_slice(&restarts.len().encode_var_vec());
+            for restart in restarts {
+                buffer.extend_from_slice(&restart.encode_var_vec());
+            }
+
+            match page_hash_indices.get(block) {
+                Some(hash_index) => {
+                    buffer.extend_from_slice(&hash_index.len().encode_var_vec());
+                    for (key, position) in hash_index {
+                        write_bytes(&mut buffer, key);
+                        buffer.extend_from_slice(&position.encode_var_vec());
+                    }
+                }
+                None => buffer.extend_from_slice(&0usize.encode_var_vec()),
+            }
+        }
+        buffer
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, SSTableError> {
+        let mut cursor = Cursor::new(buffer);
+        let block_count: usize = read_varint(&mut cursor)?;
+        let mut index = Self::default();
+
+        for _ in 0..block_count {
+            let fence_key = read_bytes(&mut cursor)?;
+            let offset = read_varint(&mut cursor)?;
+            index.fence_pointers.push((fence_key.into(), offset));
+
+            let restart_count: usize = read_varint(&mut cursor)?;
+            let mut restarts = Vec::with_capacity(restart_count);
+            for _ in 0..restart_count {
+                restarts.push(read_varint(&mut cursor)?);
+            }
+            index.restart_indices.push(restarts);
+
+            let hash_count: usize = read_varint(&mut cursor)?;
+            let mut hash_index = HashMap::with_capacity(hash_count);
+            for _ in 0..hash_count {
+                let key = read_bytes(&mut cursor)?;
+                hash_index.insert(key.into(), read_varint(&mut cursor)?);
+            }
+            index.page_hash_indices.push(hash_index);
+        }
+
+        Ok(index)
+    }
+}
+
+/// Where one partition of a partitioned index sits, and which data blocks it
+/// covers: from `first_block` up to the next partition's first block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexPartition {
+    pub first_key: Arc<[u8]>,
+    pub first_block: usize,
+    /// Offset just past the partition's last data block.
+    pub end: usize,
+    pub handle: BlockHandle,
+}
+
+/// The contents of the index block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BlockIndex {
+    Full(TableIndex),
+    Partitioned(Vec<IndexPartition>),
+}
+
+impl BlockIndex {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            BlockIndex::Full(index) => encode_full_index(index),
+            BlockIndex::Partitioned(partitions) => {
+                let mut buffer = vec![PARTITIONED_INDEX];
+                buffer.extend_from_slice(&partitions.len().encode_var_vec());
+                for partition in partitions {
+                    write_bytes(&mut buffer, &partition.first_key);
+                    buffer.extend_from_slice(&partition.first_block.encode_var_vec());
+                    buffer.extend_from_slice(&partition.end.encode_var_vec());
+                    buffer.extend_from_slice(&partition.handle.offset.encode_var_vec());
+                    buffer.extend_from_slice(&partition.handle.size.encode_var_vec());
+                    buffer.extend_from_slice(&partition.handle.checksum.to_le_bytes());
+                }
+                buffer
+            }
+        }
+    }
+
+    /// Reads an index block written at format `version`. Before the tag was
+    /// introduced the block held a full index and nothing else.
+    pub fn decode(buffer: &[u8], version: u64) -> Result<Self, SSTableError> {
+        if version < TAGGED_INDEX_VERSION {
+            return Ok(BlockIndex::Full(TableIndex::decode(buffer)?));
+        }
+        match buffer.split_first() {
+            Some((&FULL_INDEX, rest)) => Ok(BlockIndex::Full(TableIndex::decode(rest)?)),
+            Some((&PARTITIONED_INDEX, rest)) => {
+                let mut cursor = Cursor::new(rest);
+                let count: usize = read_varint(&mut cursor)?;
+                let mut partitions = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let first_key = read_bytes(&mut cursor)?.into();
+                    let first_block = read_varint(&mut cursor)?;
+                    let end = read_varint(&mut cursor)?;
+                    let offset = read_varint(&mut cursor)?;
+                    let size = read_varint(&mut cursor)?;
+                    let mut checksum = [0u8; 4];
+                    cursor.read_exact(&mut checksum)?;
+                    partitions.push(IndexPartition {
+                        first_key,
+                        first_block,
+                        end,
+                        handle: BlockHandle {
+                            offset,
+                            size,
+                            checksum: u32::from_le_bytes(checksum),
+                        },
+                    });
+                }
+                Ok(BlockIndex::Partitioned(partitions))
+            }
+            _ => Err(SSTableError::DecodeError("unknown index block tag".to_string())),
+        }
+    }
+}
+
+fn encode_full_index(index: &TableIndex) -> Vec<u8> {
+    let mut buffer = vec![FULL_INDEX];
+    buffer.extend_from_slice(&TableIndex::encode(
+        &index.fence_pointers,
+        &index.restart_indices,
+        &index.page_hash_indices,
+    ));
+    buffer
+}
+
+/// Writes the index, filter and meta blocks plus the footer, starting at
+/// `meta.data_end` (the offset just past the last data block). The meta
+/// block is stamped with the current format version.
+///
+/// With `partition_blocks`, the index is first cut into partitions of that
+/// many data blocks, written ahead of an index block that only locates
+/// them. Returns the partitions, or nothing for a whole index.
+pub(crate) fn write_table_tail<W: Write>(
+    writer: &mut W,
+    index: &TableIndex,
+    partition_blocks: Option<usize>,
+    filter: &[u8],
+    meta: TableMeta,
+) -> Result<Vec<IndexPartition>, SSTableError> {
+    let mut offset = meta.data_end as u64;
+    let data_blocks = index.fence_pointers.len();
+
+    let mut partitions = Vec::new();
+    if let Some(partition_blocks) = partition_blocks {
+        for first_block in (0..data_blocks).step_by(partition_blocks.max(1)) {
+            let last = (first_block + partition_blocks.max(1)).min(data_blocks);
+            let encoded = TableIndex::encode(
+                &index.fence_pointers[first_block..last],
+                index.restart_indices.get(first_block..last).unwrap_or(&[]),
+                index.page_hash_indices.get(first_block..last).unwrap_or(&[]),
+            );
+            let handle = BlockHandle {
+                offset,
+                size: encoded.len() as u64,
+                checksum: crc32c::crc32c(&encoded),
+            };
+            writer.write_all(&encoded)?;
+            offset += encoded.len() as u64;
+            partitions.push(IndexPartition {
+                first_key: index.fence_pointers[first_block].0.clone(),
+                first_block,
+                end: index
+                    .fence_pointers
+                    .get(last)
+                    .map(|(_, offset)| *offset)
+                    .unwrap_or(meta.data_end),
+                handle,
+            });
+        }
+    }
+    let index = match partition_blocks {
+        Some(_) => BlockIndex::Partitioned(partitions.clone()).encode(),
+        None => encode_full_index(index),
+    };
+
+    let meta = TableMeta {
+        version: FORMAT_VERSION,
+        ..meta
+    }
+    .encode();
+
+    let mut footer = Footer {
+        data_blocks: data_blocks as u64,
+        ..Footer::default()
+    };
+    for (handle, block) in [
+        (&mut footer.index, index.as_slice()),
+        (&mut footer.filter, filter),
+        (&mut footer.meta, meta.as_slice()),
+    ] {
+        *handle = BlockHandle {
+            offset,
+            size: block.len() as u64,
+            checksum: crc32c::crc32c(block),
+        };
+        writer.write_all(block)?;
+        offset += block.len() as u64;
+    }
+    writer.write_all(&footer.encode())?;
+    Ok(partitions)
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&bytes.len().encode_var_vec());
+    buffer.extend_from_slice(bytes);
+}
+
+fn read_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Vec<u8>, SSTableError> {
+    let len: usize = read_varint(cursor)?;
+    let remaining = cursor.get_ref().len() - cursor.position() as usize;
+    if len > remaining {
+        return Err(SSTableError::DecodeError(format!(
+            "length {} exceeds remaining {} bytes",
+            len, remaining
+        )));
+    }
+    let mut bytes = vec![0u8; len];
+    cursor.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Like `read_bytes`, but a field missing from the end of the buffer reads as
+/// empty.
+fn read_optional_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Vec<u8>, SSTableError> {
+    if cursor.position() as usize == cursor.get_ref().len() {
+        return Ok(Vec::new());
+    }
+    read_bytes(cursor)
+}
+
+pub(crate) fn read_varint<V: VarInt>(cursor: &mut Cursor<&[u8]>) -> Result<V, SSTableError> {
+    cursor
+        .read_varint()
+        .map_err(|e| SSTableError::DecodeError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_footer_round_trip() -> Result<(), SSTableError> {
+        let footer = Footer {
+            index: BlockHandle {
+                offset: 4096,
+                size: 120,
+                checksum: 7,
+            },
+            filter: BlockHandle {
+                offset: 4216,
+                size: 0,
+                checksum: 0,
+            },
+            meta: BlockHandle {
+                offset: 4216,
+                size: 3,
+                checksum: 42,
+            },
+            data_blocks: 2,
+        };
+        let mut encoded = footer.encode();
+        assert_eq!(encoded.len(), FOOTER_SIZE);
+        assert!(encoded.ends_with(MAGIC));
+        assert_eq!(Footer::decode(&encoded), Some(footer));
+
+        encoded[10] ^= 0x01;
+        assert_eq!(Footer::decode(&encoded), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_round_trip() -> Result<(), SSTableError> {
+        let fence_pointers: Vec<(Arc<[u8]>, usize)> =
+            vec![(Arc::from(&b"apple"[..]), 4), (Arc::from(&b"mango"[..]), 4100)];
+        let restart_indices = vec![vec![0, 230, 470], vec![0]];
+        let page_hash_indices = vec![
+            HashMap::from([(Arc::from(&b"banana"[..]), 1), (Arc::from(&b"cherry"[..]), 2)]),
+            HashMap::new(),
+        ];
+
+        let encoded = TableIndex::encode(&fence_pointers, &restart_indices, &page_hash_indices);
+        let decoded = TableIndex::decode(&encoded)?;
+        assert_eq!(decoded.fence_pointers, fence_pointers);
+        assert_eq!(decoded.restart_indices, restart_indices);
+        assert_eq!(decoded.page_hash_indices, page_hash_indices);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partitioned_index_round_trip() -> Result<(), SSTableError> {
+        let index = BlockIndex::Partitioned(vec![
+            IndexPartition {
+                first_key: Arc::from(&b"apple"[..]),
+                first_block: 0,
+                end: 8200,
+                handle: BlockHandle {
+                    offset: 8200,
+                    size: 90,
+                    checksum: 11,
+                },
+            },
+            IndexPartition {
+                first_key: Arc::from(&b"pear"[..]),
+                first_block: 2,
+                end: 9000,
+                handle: BlockHandle {
+                    offset: 9000,
+                    size: 40,
+                    checksum: 12,
+                },
+            },
+        ]);
+        assert_eq!(BlockIndex::decode(&index.encode(), FORMAT_VERSION)?, index);
+        assert!(BlockIndex::decode(&[7], FORMAT_VERSION).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_untagged_index_from_older_version() -> Result<(), SSTableError> {
+        let fence_pointers: Vec<(Arc<[u8]>, usize)> = vec![(Arc::from(&b"apple"[..]), 4)];
+        let encoded = TableIndex::encode(&fence_pointers, &[vec![0]], &[HashMap::new()]);
+        match BlockIndex::decode(&encoded, OLDEST_READABLE_VERSION)? {
+            BlockIndex::Full(index) => assert_eq!(index.fence_pointers, fence_pointers),
+            BlockIndex::Partitioned(_) => panic!("expected a full index"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_without_trailing_fields() -> Result<(), SSTableError> {
+        // a meta block as written before the optional fields existed
+        let mut encoded = OLDEST_READABLE_VERSION.encode_var_vec();
+        encoded.extend_from_slice(&3usize.encode_var_vec());
+        encoded.extend_from_slice(&4100usize.encode_var_vec());
+        encoded.extend_from_slice(&9u64.encode_var_vec());
+        write_bytes(&mut encoded, b"bytewise");
+
+        let meta = TableMeta::decode(&encoded)?;
+        assert_eq!(
+            meta,
+            TableMeta {
+                version: OLDEST_READABLE_VERSION,
+                item_count: 3,
+                data_end: 4100,
+                max_sequence: 9,
+                comparator: "bytewise".to_string(),
+                ..TableMeta::default()
+            }
+        );
+
+        let current = TableMeta {
+            version: FORMAT_VERSION,
+            smallest_key: b"apple".to_vec(),
+            largest_key: b"pear".to_vec(),
+            prefix_extractor: "fixed:3".to_string(),
+            ..meta
+        };
+        assert_eq!(TableMeta::decode(&current.encode())?, current);
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_version_is_checked() {
+        for version in [OLDEST_READABLE_VERSION - 1, FORMAT_VERSION + 1] {
+            let meta = TableMeta {
+                version,
+                ..TableMeta::default()
+            };
+            assert!(matches!(
+                TableMeta::decode(&meta.encode()),
+                Err(SSTableError::DecodeError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_truncated_index_is_rejected() {
+        let fence_pointers: Vec<(Arc<[u8]>, usize)> = vec![(Arc::from(&b"apple"[..]), 4)];
+        let encoded = TableIndex::encode(&fence_pointers, &[vec![0]], &[HashMap::new()]);
+        let result = TableIndex::decode(&encoded[..encoded.len() - 2]);
+        assert!(matches!(result, Err(SSTableError::DecodeError(_))));
+    }
+}